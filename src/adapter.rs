@@ -0,0 +1,530 @@
+// Source adapters that turn structured input into records of one or more fields of text.
+//
+// 'WordIndex::build_index' indexes records. Every record consists of fields and a WordLoc refers to the record (its 'line'),
+// the field and the position of the word within the field. The adapters:
+//  - text: every line is a record with a single field,
+//  - CSV: every row after the header row is a record and the selected columns are its fields (all columns by default).
+//    Quoted values may contain separators, quotes ("") and line breaks,
+//  - JSON Lines: every line is a record and the selected fields of its JSON object are the fields of the record. A field is
+//    selected by name or by a path like 'meta.title'; an array of strings is joined. Without a selection all fields with a
+//    string value are indexed, numbered in the order in which they are first seen,
+//  - Markdown: every line is a record with the text of the line without the markup,
+//  - HTML: every line is a record with the text of the line without tags, comments, scripts and styles.
+// Except for CSV the record number is the line number in the source, so a location can be traced back to the source line.
+//
+// With section rules (see section.rs) the reader also reports the headings that a record starts. The rules are matched
+// against the source line before the markup is removed, for CSV and JSON Lines against the first field of the record.
+
+use std::fmt;
+use std::io::{self, BufRead, Lines};
+use std::path::Path;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::section::{Heading, SectionDetector, SectionRule};
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SourceFormat {
+    #[default]
+    Text,
+    Csv{ columns: Vec<String> },    // names (or numbers) of the indexed columns, empty for all
+    JsonLines{ fields: Vec<String> },  // names or paths of the indexed fields, empty for all fields with a string value
+    Markdown,
+    Html
+}
+
+
+impl FromStr for SourceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // 'csv' and 'jsonl' take an optional list of fields: 'csv:title,body'
+        let (name, selection) = s.split_once(':').unwrap_or((s, ""));
+        if !selection.is_empty() && !matches!(name.to_lowercase().as_str(), "csv" | "jsonl" | "json-lines" | "ndjson") {
+            return Err(format!("only csv and jsonl accept a selection of fields, not '{s}'"));
+        }
+        let selection: Vec<String> = selection.split(',').filter(|f| !f.is_empty()).map(|f| f.to_string()).collect();
+        Ok(match name.to_lowercase().as_str() {
+            "text" | "txt" => SourceFormat::Text,
+            "csv" => SourceFormat::Csv{ columns: selection },
+            "jsonl" | "json-lines" | "ndjson" => SourceFormat::JsonLines{ fields: selection },
+            "md" | "markdown" => SourceFormat::Markdown,
+            "html" | "htm" => SourceFormat::Html,
+            _ => return Err(format!("unknown source format '{s}' (use text, csv[:columns], jsonl[:fields], markdown or html)"))
+        })
+    }
+}
+
+
+impl fmt::Display for SourceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceFormat::Text => write!(f, "text"),
+            SourceFormat::Csv{ columns } if columns.is_empty() => write!(f, "csv"),
+            SourceFormat::Csv{ columns } => write!(f, "csv:{}", columns.join(",")),
+            SourceFormat::JsonLines{ fields } if fields.is_empty() => write!(f, "jsonl"),
+            SourceFormat::JsonLines{ fields } => write!(f, "jsonl:{}", fields.join(",")),
+            SourceFormat::Markdown => write!(f, "markdown"),
+            SourceFormat::Html => write!(f, "html")
+        }
+    }
+}
+
+
+impl SourceFormat {
+    pub fn from_path(path: &Path) -> Self {
+        // guess the format from the extension of 'path', plain text when it is not known
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+            Some("csv") => SourceFormat::Csv{ columns: Vec::new() },
+            Some("jsonl" | "ndjson") => SourceFormat::JsonLines{ fields: Vec::new() },
+            Some("md" | "markdown") => SourceFormat::Markdown,
+            Some("html" | "htm") => SourceFormat::Html,
+            _ => SourceFormat::Text
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    pub fields: Vec<String>,
+    pub headings: Vec<Heading>  // the sections that start at this record, outermost first
+}
+
+
+pub struct RecordReader<R: BufRead> {
+    lines: Lines<R>,
+    format: SourceFormat,
+    line_idx: usize,           // number of lines read
+    field_names: Vec<String>,  // empty for the formats with a single field
+    columns: Vec<usize>,       // CSV: the column of every field, set when the header row is read
+    in_code_block: bool,       // Markdown: inside a fenced code block
+    html: HtmlStripper,
+    sections: Option<SectionDetector>
+}
+
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(reader: R, format: &SourceFormat) -> Self {
+        let field_names = match format {
+            SourceFormat::JsonLines{ fields } => fields.clone(),
+            _ => Vec::new()
+        };
+        RecordReader{ lines: reader.lines(), format: format.clone(), line_idx: 0, field_names, columns: Vec::new(),
+            in_code_block: false, html: HtmlStripper::default(), sections: None }
+    }
+
+    pub fn with_sections(mut self, rules: &[SectionRule]) -> io::Result<Self> {
+        // detect the headings of 'rules', fails when a rule is not a valid regex
+        if !rules.is_empty() {
+            self.sections = Some(SectionDetector::new(rules)?);
+        }
+        Ok(self)
+    }
+
+    fn headings(&self, text: &str) -> Vec<Heading> {
+        self.sections.as_ref().map_or_else(Vec::new, |detector| detector.headings(text))
+    }
+
+    pub fn field_names(&self) -> &[String] {
+        // the names of the fields read so far, indexed by 'WordLoc::field'
+        &self.field_names
+    }
+
+    fn next_line(&mut self) -> Option<io::Result<String>> {
+        self.line_idx += 1;
+        self.lines.next()
+    }
+
+    fn next_csv_row(&mut self) -> Option<io::Result<Vec<String>>> {
+        // a row ends at a line break outside quotes, so a quoted value can span lines
+        let mut row = match self.next_line()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err))
+        };
+        while row.matches('"').count() % 2 == 1 {
+            match self.next_line() {
+                Some(Ok(line)) => {
+                    row.push('\n');
+                    row.push_str(&line);
+                },
+                Some(Err(err)) => return Some(Err(err)),
+                None => return Some(Err(invalid_data(format!("unterminated quoted value in the CSV row ending at line {}", self.line_idx))))
+            }
+        }
+        Some(Ok(parse_csv_row(&row)))
+    }
+
+    fn read_csv_header(&mut self, columns: &[String]) -> io::Result<()> {
+        // resolve the selected columns by name, or by number when no column has that name
+        let header = match self.next_csv_row() {
+            Some(row) => row?,
+            None => Vec::new()
+        };
+        self.columns = if columns.is_empty() {
+            (0..header.len()).collect()
+        } else {
+            columns.iter().map(|name| header.iter().position(|h| h == name)
+                .or_else(|| name.parse().ok().filter(|&idx: &usize| idx < header.len()))
+                .ok_or_else(|| invalid_data(format!("column '{name}' is not in the CSV header {header:?}"))))
+                .collect::<io::Result<_>>()?
+        };
+        self.field_names = self.columns.iter().map(|&idx| header[idx].clone()).collect();
+        Ok(())
+    }
+
+    fn json_record(&mut self, line: &str) -> io::Result<Vec<String>> {
+        if line.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let value: Value = serde_json::from_str(line).map_err(|err| invalid_data(format!("line {} is not valid JSON: {err}", self.line_idx)))?;
+        let SourceFormat::JsonLines{ fields } = &self.format else { unreachable!() };
+        if !fields.is_empty() {
+            return Ok(fields.iter().map(|path| json_field(&value, path).and_then(json_text).unwrap_or_default()).collect());
+        }
+        let Value::Object(object) = &value else {
+            return Err(invalid_data(format!("line {} is not a JSON object", self.line_idx)));
+        };
+        let mut record = vec![String::new(); self.field_names.len()];
+        for (name, value) in object {
+            let Some(text) = json_text(value) else { continue };
+            match self.field_names.iter().position(|n| n == name) {
+                Some(idx) => record[idx] = text,
+                None => {
+                    self.field_names.push(name.clone());
+                    record.push(text);
+                }
+            }
+        }
+        Ok(record)
+    }
+}
+
+
+impl<R: BufRead> Iterator for RecordReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        // the next record, its fields are indexed like 'field_names'
+        if let SourceFormat::Csv{ columns } = &self.format {
+            if self.line_idx == 0 {
+                let columns = columns.clone();
+                if let Err(err) = self.read_csv_header(&columns) {
+                    return Some(Err(err));
+                }
+            }
+            return self.next_csv_row().map(|row| row.map(|row| {
+                let fields: Vec<String> = self.columns.iter().map(|&idx| row.get(idx).cloned().unwrap_or_default()).collect();
+                Record{ headings: fields.first().map_or_else(Vec::new, |f| self.headings(f)), fields }
+            }));
+        }
+        let line = match self.next_line()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err))
+        };
+        Some(match self.format {
+            SourceFormat::JsonLines{..} => self.json_record(&line).map(|fields| {
+                Record{ headings: fields.first().map_or_else(Vec::new, |f| self.headings(f)), fields }
+            }),
+            SourceFormat::Markdown => {
+                // a '#' in a fenced code block is not a heading
+                let in_code_block = self.in_code_block;
+                let text = strip_markdown(&line, &mut self.in_code_block);
+                let headings = if in_code_block || self.in_code_block { Vec::new() } else { self.headings(&line) };
+                Ok(Record{ fields: vec![text], headings })
+            },
+            SourceFormat::Html => Ok(Record{ headings: self.headings(&line), fields: vec![self.html.strip_line(&line)] }),
+            _ => Ok(Record{ headings: self.headings(&line), fields: vec![line] })
+        })
+    }
+}
+
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+pub fn parse_csv_row(row: &str) -> Vec<String> {
+    // split a row at the commas outside quotes and unquote the values
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                value.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(std::mem::take(&mut value)),
+            _ => value.push(ch)
+        }
+    }
+    values.push(value);
+    values
+}
+
+
+fn json_field<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    // a field selected by name, or by a path with the names separated by dots
+    value.get(path).or_else(|| path.split('.').try_fold(value, |v, name| v.get(name)))
+}
+
+
+fn json_text(value: &Value) -> Option<String> {
+    // the text of a string, or of the strings in an array
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Array(values) => {
+            let texts: Vec<&str> = values.iter().filter_map(|v| v.as_str()).collect();
+            (!texts.is_empty()).then(|| texts.join(" "))
+        },
+        _ => None
+    }
+}
+
+
+pub fn strip_markdown(line: &str, in_code_block: &mut bool) -> String {
+    // The text of a Markdown line without the block markup (headings, quotes, list markers, rules, fences and link
+    // definitions) and the inline markup (emphasis, code spans, links, images and HTML tags). Code blocks are kept as text.
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+        *in_code_block = !*in_code_block;
+        return String::new();
+    }
+    if *in_code_block {
+        return line.to_string();
+    }
+    let is_rule = |c: char| trimmed.len() >= 3 && trimmed.trim_end().chars().all(|ch| ch == c || ch == ' ');
+    if is_rule('-') || is_rule('*') || is_rule('_') || is_rule('=') {
+        return String::new();
+    }
+    if trimmed.starts_with('[') && trimmed.contains("]:") {
+        return String::new();
+    }
+    let mut text = trimmed.trim_start_matches(['>', ' ']);
+    let hashes = text.len() - text.trim_start_matches('#').len();
+    if hashes > 0 && hashes <= 6 && text[hashes..].starts_with(' ') {
+        text = text[hashes..].trim_end_matches(['#', ' ']);
+    }
+    for marker in ["- [ ] ", "- [x] ", "- ", "* ", "+ "] {
+        if let Some(rest) = text.strip_prefix(marker) {
+            text = rest;
+            break;
+        }
+    }
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 && (text[digits..].starts_with(". ") || text[digits..].starts_with(") ")) {
+        text = &text[digits + 2..];
+    }
+    strip_inline_markdown(text)
+}
+
+
+fn strip_inline_markdown(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        match ch {
+            '!' if chars.get(i + 1) == Some(&'[') => (),
+            '[' => (),
+            ']' if chars.get(i + 1) == Some(&'(') => {
+                // skip the target of a link or an image
+                let end = chars[i..].iter().position(|&c| c == ')').map_or(chars.len(), |p| i + p);
+                i = end;
+            },
+            ']' => (),
+            '<' => {
+                // an HTML tag or an autolink
+                match chars[i..].iter().position(|&c| c == '>') {
+                    Some(p) => {
+                        out.push(' ');
+                        i += p;
+                    },
+                    None => out.push(ch)
+                }
+            },
+            '*' | '`' | '~' | '|' => out.push(if ch == '|' { ' ' } else { '\u{0}' }),
+            '_' => {
+                // an underscore within a word (snake_case) is not emphasis
+                let inner = i > 0 && chars[i - 1].is_alphanumeric() && chars.get(i + 1).is_some_and(|c| c.is_alphanumeric());
+                out.push(if inner { '_' } else { '\u{0}' });
+            },
+            '\\' if chars.get(i + 1).is_some_and(|c| c.is_ascii_punctuation()) => {
+                out.push(chars[i + 1]);
+                i += 1;
+            },
+            _ => out.push(ch)
+        }
+        i += 1;
+    }
+    out.replace('\u{0}', "").trim().to_string()
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+enum HtmlState {
+    #[default]
+    Text,
+    Tag{ name: String, quote: Option<char> },  // inside a tag, 'name' is the tag name read so far
+    Comment,
+    Raw(&'static str)  // inside a script or style element, up to its end tag
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct HtmlStripper {
+    state: HtmlState
+}
+
+
+impl HtmlStripper {
+    pub fn strip_line(&mut self, line: &str) -> String {
+        // The text of a line of HTML. The state is kept between lines, as tags, comments and scripts can span lines.
+        // Every tag is replaced by a space, so that words on both sides of a tag are separated.
+        let mut out = String::with_capacity(line.len());
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+            let ch = rest.chars().next().unwrap();
+            let mut len = ch.len_utf8();
+            match &mut self.state {
+                HtmlState::Text if rest.starts_with("<!--") => {
+                    self.state = HtmlState::Comment;
+                    len = 4;
+                },
+                HtmlState::Text if ch == '<' && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!' || c == '?') => {
+                    self.state = HtmlState::Tag{ name: String::new(), quote: None };
+                    out.push(' ');
+                },
+                HtmlState::Text if ch == '&' => {
+                    let (decoded, entity_len) = decode_entity(rest);
+                    out.push(decoded);
+                    len = entity_len;
+                },
+                HtmlState::Text => out.push(ch),
+                HtmlState::Comment => {
+                    if rest.starts_with("-->") {
+                        self.state = HtmlState::Text;
+                        len = 3;
+                    }
+                },
+                HtmlState::Raw(name) => {
+                    let end_tag = format!("</{name}");
+                    if rest.get(..end_tag.len()).is_some_and(|s| s.eq_ignore_ascii_case(&end_tag)) {
+                        self.state = HtmlState::Tag{ name: String::new(), quote: None };
+                    }
+                },
+                HtmlState::Tag{ quote: quote @ Some(_), .. } => {
+                    if Some(ch) == *quote {
+                        *quote = None;
+                    }
+                },
+                HtmlState::Tag{ name, quote } => {
+                    match ch {
+                        '"' | '\'' => *quote = Some(ch),
+                        '>' => {
+                            let self_closing = line[..i].ends_with('/');
+                            let next = match name.trim_end().to_ascii_lowercase().as_str() {
+                                "script" if !self_closing => HtmlState::Raw("script"),
+                                "style" if !self_closing => HtmlState::Raw("style"),
+                                _ => HtmlState::Text
+                            };
+                            self.state = next;
+                        },
+                        c if !name.ends_with(' ') && (c.is_ascii_alphanumeric() || (c == '/' && name.is_empty())) => name.push(c),
+                        _ if !name.is_empty() && !name.ends_with(' ') => name.push(' '),  // the name ends at the first other character
+                        _ => ()
+                    }
+                }
+            }
+            i += len;
+        }
+        if let HtmlState::Tag{ name, .. } = &mut self.state {
+            // the line break ends the tag name
+            if !name.is_empty() && !name.ends_with(' ') {
+                name.push(' ');
+            }
+        }
+        out.trim().to_string()
+    }
+}
+
+
+fn decode_entity(s: &str) -> (char, usize) {
+    // the character of the entity at the start of 's' and the length of the entity, or '&' when it is not an entity
+    let Some(end) = s[1..].find(';').filter(|&end| end <= 8).map(|end| end + 1) else { return ('&', 1) };
+    let name = &s[1..end];
+    let decoded = match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")).map_or_else(
+            || name.strip_prefix('#').and_then(|n| n.parse().ok()),
+            |hex| u32::from_str_radix(hex, 16).ok()).and_then(char::from_u32)
+    };
+    decoded.map_or(('&', 1), |c| (c, end + 1))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_csv_row, strip_markdown, HtmlStripper, RecordReader, SourceFormat};
+
+    fn records(input: &str, format: &str) -> (Vec<Vec<String>>, Vec<String>) {
+        let mut reader = RecordReader::new(input.as_bytes(), &format.parse().unwrap());
+        let records = reader.by_ref().map(|r| r.map(|r| r.fields)).collect::<std::io::Result<Vec<_>>>().unwrap();
+        (records, reader.field_names().to_vec())
+    }
+
+    #[test]
+    fn test_csv_and_json_lines() {
+        assert_eq!(parse_csv_row(r#"1,"a, ""b""",c"#), vec!["1", "a, \"b\"", "c"]);
+        let csv = "id,title,body\n1,Hamlet,\"to be, or not\nto be\"\n2,Macbeth,out damned spot\n";
+        let (rows, names) = records(csv, "csv:body,title");
+        assert_eq!(names, vec!["body", "title"]);
+        assert_eq!(rows, vec![vec!["to be, or not\nto be", "Hamlet"], vec!["out damned spot", "Macbeth"]]);
+        assert_eq!(records(csv, "csv:2").1, vec!["body"]);
+        assert!(RecordReader::new(csv.as_bytes(), &"csv:author".parse().unwrap()).next().unwrap().is_err());
+
+        let jsonl = "{\"title\":\"Hamlet\",\"meta\":{\"tags\":[\"drama\",\"denmark\"]},\"year\":1603}\n\n{\"text\":\"words\",\"title\":\"Lear\"}\n";
+        let (rows, names) = records(jsonl, "jsonl:title,meta.tags");
+        assert_eq!(names, vec!["title", "meta.tags"]);
+        assert_eq!(rows, vec![vec!["Hamlet", "drama denmark"], vec![], vec!["Lear", ""]]);
+        let (rows, names) = records(jsonl, "jsonl");
+        assert_eq!(names, vec!["title", "text"]);
+        assert_eq!(rows, vec![vec!["Hamlet"], vec![], vec!["Lear", "words"]]);
+        assert!(RecordReader::new("{oops\n".as_bytes(), &SourceFormat::JsonLines{ fields: Vec::new() }).next().unwrap().is_err());
+        assert_eq!("csv:a,b".parse::<SourceFormat>().unwrap().to_string(), "csv:a,b");
+        assert!("html:a".parse::<SourceFormat>().is_err());
+    }
+
+    #[test]
+    fn test_strip_markup() {
+        let mut in_code = false;
+        let mut md = |line| strip_markdown(line, &mut in_code);
+        assert_eq!(md("## The *tragedy* of __Hamlet__ ##"), "The tragedy of Hamlet");
+        assert_eq!(md("> - see [the text](http://x.org/h) and ![a portrait](p.png)"), "see the text and a portrait");
+        assert_eq!(md("1. use `snake_case` names"), "use snake_case names");
+        assert_eq!(md("| a | b |"), "a   b");
+        assert_eq!(md("---"), "");
+        assert_eq!(md("```rust"), "");
+        assert_eq!(md("let *x* = 1;"), "let *x* = 1;");
+        assert_eq!(md("```"), "");
+
+        let mut html = HtmlStripper::default();
+        assert_eq!(html.strip_line("<p class=\"a>b\">Fish &amp; chips<br/>today</p><script>"), "Fish & chips today");
+        assert_eq!(html.strip_line("var x = '<p>not text</p>';"), "");
+        assert_eq!(html.strip_line("</script><!-- a"), "");
+        assert_eq!(html.strip_line("comment --> caf&#233; <a"), "café");
+        assert_eq!(html.strip_line("href='x'>link</a> 3 &lt; 4 & 5"), "link  3 < 4 & 5");
+    }
+}
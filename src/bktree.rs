@@ -0,0 +1,160 @@
+// A BK-tree (Burkhard-Keller tree) over the vocabulary of a WordIndex.
+//
+// Each node holds a word and its children are keyed by their distance to that word. Because the full-word Damerau-Levenshtein
+// distance is a metric, a query for all words within distance 'max_dist' of a word at distance 'd' from a node only needs to
+// descend into the children with a key in 'd - max_dist ..= d + max_dist'. For small distances this visits a fraction of the nodes
+// instead of the whole vocabulary.
+
+use std::sync::OnceLock;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::levenshtein::dam_lev_distance;
+
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BkTree {
+    nodes: Vec<BkNode>
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BkNode {
+    word: String,
+    children: Vec<(u32, u32)>  // (distance to 'word', index of the child node)
+}
+
+
+fn distance(a: &str, b: &str) -> usize {
+    // the exact distance; it can never exceed the length of the longest word
+    let max_dist = a.chars().count().max(b.chars().count());
+    dam_lev_distance(a, b, max_dist).unwrap_or(max_dist)
+}
+
+
+impl BkTree {
+    pub fn new() -> Self {
+        BkTree::default()
+    }
+
+    pub fn from_words<'a, I: IntoIterator<Item = &'a String>>(words: I) -> Self {
+        let mut tree = BkTree::new();
+        for word in words {
+            tree.insert(word);
+        }
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        // the words of the tree, each once, in the order they were inserted
+        self.nodes.iter().map(|n| n.word.as_str())
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.nodes.capacity() * std::mem::size_of::<BkNode>()
+            + self.nodes.iter().map(|n| n.word.capacity() + n.children.capacity() * std::mem::size_of::<(u32, u32)>()).sum::<usize>()
+    }
+
+    pub fn insert(&mut self, word: &str) {
+        // Walk down the tree following the edge with the distance to the current node, and attach the word as a new leaf.
+        let new_idx = self.nodes.len() as u32;
+        if self.nodes.is_empty() {
+            self.nodes.push(BkNode{word: word.to_string(), children: Vec::new()});
+            return;
+        }
+        let mut curr = 0;
+        loop {
+            let dist = distance(word, &self.nodes[curr].word) as u32;
+            if dist == 0 {
+                return;  // word is already present
+            }
+            match self.nodes[curr].children.iter().find(|(d, _)| *d == dist) {
+                Some(&(_, child)) => curr = child as usize,
+                None => {
+                    self.nodes[curr].children.push((dist, new_idx));
+                    break;
+                }
+            }
+        }
+        self.nodes.push(BkNode{word: word.to_string(), children: Vec::new()});
+    }
+
+    pub fn find_within(&self, word: &str, max_dist: usize) -> Vec<(&str, usize)> {
+        // Return all words within 'max_dist' of 'word' together with their distance, ordered by distance.
+        self.find_within_counting(word, max_dist).0
+    }
+
+    pub fn find_within_counting(&self, word: &str, max_dist: usize) -> (Vec<(&str, usize)>, usize) {
+        // As 'find_within' but also returns the number of nodes visited, to judge the effectiveness of the pruning.
+        let mut matches = Vec::new();
+        let mut visited = 0;
+        if self.nodes.is_empty() {
+            return (matches, visited);
+        }
+        let mut stack = vec![0usize];
+        while let Some(curr) = stack.pop() {
+            visited += 1;
+            let node = &self.nodes[curr];
+            let dist = distance(word, &node.word);
+            if dist <= max_dist {
+                matches.push((node.word.as_str(), dist));
+            }
+            let (lo, hi) = (dist.saturating_sub(max_dist) as u32, (dist + max_dist) as u32);
+            stack.extend(node.children.iter().filter(|(d, _)| *d >= lo && *d <= hi).map(|&(_, child)| child as usize));
+        }
+        matches.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+        (matches, visited)
+    }
+}
+
+
+// (De)serialization of the lazily built tree, so that a tree that has been built is persisted together with the index.
+pub mod lazy {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(cell: &OnceLock<BkTree>, serializer: S) -> Result<S::Ok, S::Error> {
+        cell.get().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OnceLock<BkTree>, D::Error> {
+        let cell = OnceLock::new();
+        if let Some(tree) = Option::<BkTree>::deserialize(deserializer)? {
+            let _ = cell.set(tree);
+        }
+        Ok(cell)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{BkTree, distance};
+
+    #[test]
+    fn test_bk_tree_matches_linear_scan() {
+        let words: Vec<String> = ["the", "thee", "they", "then", "them", "these", "those", "three", "tree", "hte", "other", "mother", "brother", "a", "an", "and", "hand", "band"]
+            .iter().map(|s| s.to_string()).collect();
+        let tree = BkTree::from_words(&words);
+        assert_eq!(tree.len(), words.len());
+
+        for query in ["the", "teh", "bother", "nad", "x"] {
+            for max_dist in 0..3 {
+                let mut expected: Vec<(&str, usize)> = words.iter()
+                    .map(|w| (w.as_str(), distance(query, w)))
+                    .filter(|(_, d)| *d <= max_dist)
+                    .collect();
+                expected.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+                assert_eq!(tree.find_within(query, max_dist), expected, "query '{query}' max_dist {max_dist}");
+            }
+        }
+        let (matches, visited) = tree.find_within_counting("teh", 1);
+        assert_eq!(matches[0], ("the", 1));
+        assert!(visited < words.len());
+    }
+}
@@ -0,0 +1,314 @@
+// Record boundaries: which unit of the text is a record of the index.
+//
+// By default every line is a record. The lines read by the adapter (see adapter.rs) can instead be grouped into
+//  - paragraphs: blocks of lines separated by blank lines,
+//  - sentences: split by the sentence splitter below, a blank line always ends a sentence,
+//  - regex-delimited blocks: every line that matches the regex starts a new record (e.g. '^(ACT|SCENE) '),
+//  - fixed windows: records of a fixed number of words, regardless of the lines.
+// Only the formats with a single field (text, Markdown and HTML) can be grouped. The position of a word in a record is a u16,
+// so a paragraph or block that grows beyond MAX_RECORD_WORDS words is continued in a new record.
+//
+// A line that starts a section (see section.rs) always starts a new record, so that every section starts at a record.
+// The headings are reported with the record that the line starts.
+//
+// The sentence splitter ends a sentence after '.', '!' or '?' (and any closing quotes or brackets) when whitespace and a
+// character that is not a lowercase letter follow. A period after a known abbreviation or a single capital (an initial)
+// does not end a sentence.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use regex_automata::meta::Regex;
+use serde::{Deserialize, Serialize};
+use crate::adapter::Record;
+use crate::section::Heading;
+
+
+pub const MAX_RECORD_WORDS: usize = u16::MAX as usize;
+
+const ABBREVIATIONS: [&str; 16] = ["Mr", "Mrs", "Ms", "Dr", "St", "Jr", "Sr", "Prof", "Mt", "No", "vs", "cf", "e.g", "i.e", "viz", "Ft"];
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordBoundary {
+    #[default]
+    Line,
+    Paragraph,
+    Sentence,
+    Regex(String),  // a line that matches starts a new record
+    Window(usize)   // number of words per record
+}
+
+
+impl FromStr for RecordBoundary {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = s.split_once(':').unwrap_or((s, ""));
+        match name.to_lowercase().as_str() {
+            "line" | "lines" => Ok(RecordBoundary::Line),
+            "paragraph" | "paragraphs" => Ok(RecordBoundary::Paragraph),
+            "sentence" | "sentences" => Ok(RecordBoundary::Sentence),
+            "regex" => Regex::new(arg).map(|_| RecordBoundary::Regex(arg.to_string())).map_err(|err| format!("invalid regex '{arg}': {err}")),
+            "window" => match arg.parse() {
+                Ok(size) if (1..=MAX_RECORD_WORDS).contains(&size) => Ok(RecordBoundary::Window(size)),
+                _ => Err(format!("the window size must be a number from 1 to {MAX_RECORD_WORDS}, not '{arg}'"))
+            },
+            _ => Err(format!("unknown record boundary '{s}' (use line, paragraph, sentence, regex:<pattern> or window:<words>)"))
+        }
+    }
+}
+
+
+impl fmt::Display for RecordBoundary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordBoundary::Line => write!(f, "line"),
+            RecordBoundary::Paragraph => write!(f, "paragraph"),
+            RecordBoundary::Sentence => write!(f, "sentence"),
+            RecordBoundary::Regex(pattern) => write!(f, "regex:{pattern}"),
+            RecordBoundary::Window(size) => write!(f, "window:{size}")
+        }
+    }
+}
+
+
+pub fn group_records<'a, I>(records: I, boundary: &RecordBoundary) -> io::Result<Box<dyn Iterator<Item = io::Result<Record>> + 'a>>
+where I: Iterator<Item = io::Result<Record>> + 'a {
+    // The records for 'boundary' from the single-field records (lines) of an adapter.
+    if *boundary == RecordBoundary::Line {
+        return Ok(Box::new(records));
+    }
+    let regex = match boundary {
+        RecordBoundary::Regex(pattern) => Some(Regex::new(pattern).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?),
+        _ => None
+    };
+    Ok(Box::new(Grouper{ lines: records, boundary: boundary.clone(), regex, buffer: String::new(), buffer_words: 0, scanned: 0,
+        headings: Vec::new(), window: VecDeque::new(), ready: VecDeque::new(), done: false }))
+}
+
+
+struct Grouper<I> {
+    lines: I,
+    boundary: RecordBoundary,
+    regex: Option<Regex>,
+    buffer: String,            // the text of the current paragraph, sentence or block
+    buffer_words: usize,
+    scanned: usize,            // the sentences of 'buffer' end after this offset
+    headings: Vec<Heading>,    // the headings of the current record
+    window: VecDeque<String>,  // the words of the current window
+    ready: VecDeque<Record>,   // finished records
+    done: bool
+}
+
+
+impl<I> Grouper<I> {
+    fn emit(&mut self, text: String) {
+        self.ready.push_back(Record{ fields: vec![text], headings: std::mem::take(&mut self.headings) });
+    }
+
+    fn flush(&mut self) {
+        // the headings of a record without text are kept for the next record
+        if !self.buffer.trim().is_empty() {
+            let text = std::mem::take(&mut self.buffer);
+            self.emit(text);
+        }
+        self.buffer.clear();
+        self.buffer_words = 0;
+        self.scanned = 0;
+    }
+
+    fn append(&mut self, line: &str) {
+        let words = line.split_whitespace().count();
+        if self.buffer_words + words > MAX_RECORD_WORDS {
+            self.flush();
+        }
+        if !self.buffer.is_empty() {
+            self.buffer.push('\n');
+        }
+        self.buffer.push_str(line);
+        self.buffer_words += words;
+    }
+
+    fn flush_window(&mut self) {
+        if !self.window.is_empty() {
+            let words: Vec<String> = self.window.drain(..).collect();
+            self.emit(words.join(" "));
+        }
+    }
+
+    fn push_line(&mut self, line: &str, headings: Vec<Heading>) {
+        if !headings.is_empty() {
+            self.flush();
+            self.flush_window();
+            self.headings.extend(headings);
+        }
+        match &self.boundary {
+            RecordBoundary::Line => self.emit(line.to_string()),
+            RecordBoundary::Paragraph => {
+                if line.trim().is_empty() {
+                    self.flush();
+                } else {
+                    self.append(line);
+                }
+            },
+            RecordBoundary::Regex(_) => {
+                if self.regex.as_ref().is_some_and(|r| r.is_match(line)) {
+                    self.flush();
+                }
+                self.append(line);
+            },
+            RecordBoundary::Sentence => {
+                if line.trim().is_empty() {
+                    self.flush();
+                    return;
+                }
+                self.append(line);
+                while let Some(end) = sentence_end(&self.buffer, self.scanned) {
+                    let rest = self.buffer.split_off(end);
+                    self.flush();
+                    self.buffer = rest.trim_start().to_string();
+                    self.buffer_words = self.buffer.split_whitespace().count();
+                }
+                // only a terminator at the end of the buffer can still end a sentence when the next line is known
+                self.scanned = self.buffer.trim_end_matches(|c: char| c.is_whitespace() || is_terminator(c) || is_closing(c)).len();
+            },
+            RecordBoundary::Window(size) => {
+                let size = *size;
+                self.window.extend(line.split_whitespace().map(|w| w.to_string()));
+                while self.window.len() >= size {
+                    let words: Vec<String> = self.window.drain(..size).collect();
+                    self.emit(words.join(" "));
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.flush();
+        self.flush_window();
+    }
+}
+
+
+impl<I: Iterator<Item = io::Result<Record>>> Iterator for Grouper<I> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        loop {
+            if let Some(record) = self.ready.pop_front() {
+                return Some(Ok(record));
+            }
+            if self.done {
+                return None;
+            }
+            match self.lines.next() {
+                Some(Ok(Record{ fields, headings })) => self.push_line(fields.first().map_or("", |f| f.as_str()), headings),
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    self.done = true;
+                    self.finish();
+                }
+            }
+        }
+    }
+}
+
+
+fn is_terminator(c: char) -> bool {
+    matches!(c, '.' | '!' | '?')
+}
+
+
+fn is_closing(c: char) -> bool {
+    matches!(c, '"' | '\'' | ')' | ']' | '’' | '”')
+}
+
+
+pub fn sentence_end(text: &str, from: usize) -> Option<usize> {
+    // The byte offset just after the first complete sentence of 'text' that ends after 'from'. None when the text does
+    // not contain the start of the next sentence yet, as only that decides whether a period ends a sentence.
+    let chars: Vec<(usize, char)> = text[from..].char_indices().map(|(pos, c)| (from + pos, c)).collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, ch) = chars[i];
+        i += 1;
+        if !is_terminator(ch) {
+            continue;
+        }
+        if ch == '.' && is_abbreviation(&text[..pos]) {
+            continue;
+        }
+        while i < chars.len() && (is_terminator(chars[i].1) || is_closing(chars[i].1)) {
+            i += 1;
+        }
+        let end = chars.get(i).map_or(text.len(), |&(p, _)| p);
+        let mut next = i;
+        while next < chars.len() && chars[next].1.is_whitespace() {
+            next += 1;
+        }
+        if next == i {
+            continue;  // no whitespace after the terminator, e.g. "3.14" or "e.g."
+        }
+        match chars.get(next) {
+            Some((_, c)) if !c.is_lowercase() => return Some(end),
+            Some(_) => (),
+            None => return None  // the next sentence has not started yet
+        }
+    }
+    None
+}
+
+
+fn is_abbreviation(before: &str) -> bool {
+    // whether the word in front of a period is an abbreviation or an initial
+    let word = before.rsplit(|c: char| c.is_whitespace() || c == '(' || c == '"').next().unwrap_or("");
+    ABBREVIATIONS.contains(&word) || (word.chars().count() == 1 && word.chars().all(|c| c.is_uppercase()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{group_records, sentence_end, RecordBoundary};
+    use crate::adapter::Record;
+    use crate::section::Heading;
+
+    fn group(text: &str, boundary: &str) -> Vec<String> {
+        let lines = text.lines().map(|l| Ok(Record{ fields: vec![l.to_string()], headings: Vec::new() }));
+        group_records(lines, &boundary.parse().unwrap()).unwrap().map(|r| r.unwrap().fields.remove(0)).collect()
+    }
+
+    #[test]
+    fn test_sentence_end() {
+        assert_eq!(sentence_end("To be. Or not", 0), Some(6));
+        assert_eq!(sentence_end("To be. ", 0), None);
+        assert_eq!(sentence_end("Ask Mr. Smith. He knows", 0), Some(14));
+        assert_eq!(sentence_end("Is it 3.14? \"Yes!\" she said", 0), Some(11));
+        assert_eq!(sentence_end("\"Yes!\" she said. And", 0), Some(16));
+        assert_eq!(sentence_end("J. R. R. Tolkien wrote it. Then", 0), Some(26));
+        assert_eq!(sentence_end("no end here", 0), None);
+        assert_eq!(sentence_end("To be. Or not. So", 7), Some(14));
+    }
+
+    #[test]
+    fn test_group_records() {
+        let text = "ACT I\nSCENE I. Elsinore.\nWho's there?\nNay, answer me: stand,\nand unfold yourself.\n\nLong live the king!\n";
+        assert_eq!(group(text, "paragraph"), vec!["ACT I\nSCENE I. Elsinore.\nWho's there?\nNay, answer me: stand,\nand unfold yourself.", "Long live the king!"]);
+        assert_eq!(group(text, "sentence"), vec!["ACT I\nSCENE I. Elsinore.", "Who's there?", "Nay, answer me: stand,\nand unfold yourself.", "Long live the king!"]);
+        assert_eq!(group(text, "regex:^(ACT|SCENE) ").len(), 2);
+        assert_eq!(group(text, "window:7"), vec!["ACT I SCENE I. Elsinore. Who's there?", "Nay, answer me: stand, and unfold yourself.", "Long live the king!"]);
+        assert_eq!(group(text, "line").len(), 7);
+        assert!("window:0".parse::<RecordBoundary>().is_err());
+        assert!("regex:(".parse::<RecordBoundary>().is_err());
+        assert_eq!("regex:^ACT".parse::<RecordBoundary>().unwrap().to_string(), "regex:^ACT");
+
+        // a heading starts a new record, also in the middle of a paragraph or window
+        let heading = |title: &str| vec![Heading{ level: 1, title: title.to_string() }];
+        let lines = text.lines().map(|l| Ok(Record{ fields: vec![l.to_string()], headings: if l.starts_with("SCENE") { heading(l) } else { Vec::new() } }));
+        let records: Vec<Record> = group_records(lines, &RecordBoundary::Window(4)).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(records.iter().map(|r| r.fields[0].as_str()).collect::<Vec<_>>(),
+            vec!["ACT I", "SCENE I. Elsinore. Who's", "there? Nay, answer me:", "stand, and unfold yourself.", "Long live the king!"]);
+        assert_eq!((records[1].headings.len(), records[2].headings.len()), (1, 0));
+    }
+}
@@ -0,0 +1,162 @@
+// Character helpers shared by the distance functions and the completions.
+
+use std::ops::Bound;
+
+
+pub fn fold_diacritic(ch: char) -> char {
+    // Map a Latin letter with a diacritic to its base letter (e.g. 'é' -> 'e', 'Ç' -> 'C'). Other characters are returned unchanged.
+    match ch {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => 'A',
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => 'C',
+        'ď' | 'đ' => 'd',
+        'Ď' | 'Đ' => 'D',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => 'E',
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => 'G',
+        'ĥ' | 'ħ' => 'h',
+        'Ĥ' | 'Ħ' => 'H',
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => 'I',
+        'ĵ' => 'j',
+        'Ĵ' => 'J',
+        'ķ' => 'k',
+        'Ķ' => 'K',
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => 'L',
+        'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' => 'N',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => 'O',
+        'ŕ' | 'ŗ' | 'ř' => 'r',
+        'Ŕ' | 'Ŗ' | 'Ř' => 'R',
+        'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => 'S',
+        'ţ' | 'ť' | 'ŧ' => 't',
+        'Ţ' | 'Ť' | 'Ŧ' => 'T',
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => 'U',
+        'ŵ' => 'w',
+        'Ŵ' => 'W',
+        'ý' | 'ÿ' | 'ŷ' => 'y',
+        'Ý' | 'Ÿ' | 'Ŷ' => 'Y',
+        'ź' | 'ż' | 'ž' => 'z',
+        'Ź' | 'Ż' | 'Ž' => 'Z',
+        _ => ch
+    }
+}
+
+
+pub fn fold_str(s: &str) -> String {
+    // lowercase and diacritic-free version of a string, used as the key for case- and diacritic-insensitive comparisons.
+    s.chars().flat_map(char::to_lowercase).map(fold_diacritic).collect()
+}
+
+
+pub fn prefix_successor(prefix: &str) -> Option<String> {
+    // The smallest string that is larger than every string starting with 'prefix', so 'prefix..successor' is exactly
+    // the range of strings with that prefix. None when no such string exists (an empty prefix or only char::MAX).
+    let mut chs: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chs.pop() {
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),  // skip the surrogate range
+            char::MAX => None,
+            ch => char::from_u32(ch as u32 + 1)
+        };
+        if let Some(next) = next {
+            chs.push(next);
+            return Some(chs.into_iter().collect());
+        }
+    }
+    None
+}
+
+
+pub fn prefix_range(prefix: &str) -> (Bound<&str>, Bound<String>) {
+    // bounds of the range of all strings starting with 'prefix', to be used with BTreeMap::range
+    let end = match prefix_successor(prefix) {
+        Some(successor) => Bound::Excluded(successor),
+        None => Bound::Unbounded
+    };
+    (Bound::Included(prefix), end)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyboardLayout {
+    #[default]
+    Qwerty,
+    Azerty
+}
+
+
+impl KeyboardLayout {
+    fn rows(&self) -> [&'static str; 3] {
+        match self {
+            KeyboardLayout::Qwerty => ["qwertyuiop", "asdfghjkl", "zxcvbnm"],
+            KeyboardLayout::Azerty => ["azertyuiop", "qsdfghjklm", "wxcvbn"]
+        }
+    }
+
+    fn position(&self, ch: char) -> Option<(usize, f32)> {
+        // (row, horizontal position) of a key, taking the stagger of the rows into account
+        const ROW_OFFSET: [f32; 3] = [0.0, 0.25, 0.75];
+        let ch = ch.to_ascii_lowercase();
+        self.rows().iter().enumerate()
+            .find_map(|(row, keys)| keys.find(ch).map(|col| (row, col as f32 + ROW_OFFSET[row])))
+    }
+
+    pub fn adjacent(&self, a: char, b: char) -> bool {
+        // true when 'a' and 'b' are different keys that touch each other on the keyboard
+        match (self.position(a), self.position(b)) {
+            (Some((row_a, x_a)), Some((row_b, x_b))) => {
+                let dx = (x_a - x_b).abs();
+                match row_a.abs_diff(row_b) {
+                    0 => dx == 1.0,
+                    1 => dx <= 1.0,
+                    _ => false
+                }
+            },
+            _ => false
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{fold_str, prefix_successor, KeyboardLayout};
+
+    #[test]
+    fn test_fold_str() {
+        assert_eq!(fold_str("Élève"), "eleve");
+        assert_eq!(fold_str("Straße"), "straße");
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor("the").as_deref(), Some("thf"));
+        assert_eq!(prefix_successor("az").as_deref(), Some("a{"));
+        assert_eq!(prefix_successor("thé").as_deref(), Some("thê"));
+        assert_eq!(prefix_successor("a\u{D7FF}").as_deref(), Some("a\u{E000}"));
+        assert_eq!(prefix_successor("a\u{10FFFF}").as_deref(), Some("b"));
+        assert_eq!(prefix_successor("\u{10FFFF}"), None);
+        assert_eq!(prefix_successor(""), None);
+    }
+
+    #[test]
+    fn test_adjacent_keys() {
+        let qwerty = KeyboardLayout::Qwerty;
+        assert!(qwerty.adjacent('w', 'e'));
+        assert!(qwerty.adjacent('a', 'q'));
+        assert!(qwerty.adjacent('a', 'w'));
+        assert!(qwerty.adjacent('Z', 's'));
+        assert!(!qwerty.adjacent('a', 'e'));
+        assert!(!qwerty.adjacent('q', 'z'));
+        assert!(!qwerty.adjacent('a', 'a'));
+        assert!(KeyboardLayout::Azerty.adjacent('a', 'q'));
+        assert!(!KeyboardLayout::Azerty.adjacent('a', 's'));
+    }
+}
@@ -0,0 +1,121 @@
+// Collocations: the words that co-occur with a term within a window of positions on the same line (in the same field).
+//
+// For a term T with frequency f(T) and a window of w words on both sides there are R = 2 * w * f(T) positions around T.
+// A collocate C with frequency f(C) that is observed O times in these positions is compared to the E = R * f(C) / N
+// occurrences expected when words are spread independently over the N words of the text. The measures are
+//  - PMI: log2(O / E), which favours rare collocates,
+//  - t-score: (O - E) / sqrt(O), which favours frequent collocates,
+//  - log-likelihood: Dunning's G2 over the 2x2 contingency table of (in window, C), which balances both.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use crate::index::WordLoc;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AssociationMeasure {
+    #[default]
+    Pmi,
+    LogLikelihood,
+    TScore
+}
+
+
+impl FromStr for AssociationMeasure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pmi" => Ok(AssociationMeasure::Pmi),
+            "ll" | "log-likelihood" | "loglikelihood" => Ok(AssociationMeasure::LogLikelihood),
+            "t" | "t-score" | "tscore" => Ok(AssociationMeasure::TScore),
+            _ => Err(format!("unknown association measure '{s}' (use pmi, ll or t-score)"))
+        }
+    }
+}
+
+
+impl fmt::Display for AssociationMeasure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssociationMeasure::Pmi => write!(f, "PMI"),
+            AssociationMeasure::LogLikelihood => write!(f, "log-likelihood"),
+            AssociationMeasure::TScore => write!(f, "t-score")
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collocation {
+    pub word: String,
+    pub cooccurrences: usize,  // O: occurrences within the window of the term
+    pub frequency: usize,      // f(C): occurrences in the full text
+    pub score: f64
+}
+
+
+impl AssociationMeasure {
+    pub fn score(&self, observed: usize, term_freq: usize, collocate_freq: usize, window: usize, num_words: usize) -> f64 {
+        let n = num_words as f64;
+        let o11 = observed as f64;
+        let r1 = (2 * window * term_freq) as f64;
+        let c1 = collocate_freq as f64;
+        let expected = r1 * c1 / n;
+        match self {
+            AssociationMeasure::Pmi => (o11 / expected).log2(),
+            AssociationMeasure::TScore => (o11 - expected) / o11.sqrt(),
+            AssociationMeasure::LogLikelihood => {
+                // the cells can get slightly negative as windows overlap and are cut at the line ends
+                let (o12, o21) = ((r1 - o11).max(0.0), (c1 - o11).max(0.0));
+                let o22 = (n - o11 - o12 - o21).max(0.0);
+                let (r2, c2) = (o21 + o22, o12 + o22);
+                let cells = [(o11, r1, c1), (o12, r1, c2), (o21, r2, c1), (o22, r2, c2)];
+                2.0 * cells.iter()
+                    .filter(|(o, _, _)| *o > 0.0)
+                    .map(|&(o, r, c)| o * (o * n / (r * c)).ln())
+                    .sum::<f64>()
+            }
+        }
+    }
+}
+
+
+pub fn cooccurrences<'a, I>(term_locs: &[WordLoc], vocabulary: I, window: usize) -> Vec<(&'a String, usize, usize)>
+where I: IntoIterator<Item = (&'a String, &'a Vec<WordLoc>)> {
+    // For every word of the vocabulary that occurs within 'window' positions of a location of the term: (word, number of
+    // co-occurrences, frequency). A co-occurrence is a pair of a term location and a location of the word.
+    let mut term_positions: HashMap<(u32, u16), Vec<u16>> = HashMap::new();
+    for loc in term_locs {
+        term_positions.entry((loc.line, loc.field)).or_default().push(loc.word);
+    }
+    vocabulary.into_iter()
+        .filter_map(|(word, locs)| {
+            let observed: usize = locs.iter()
+                .filter_map(|loc| term_positions.get(&(loc.line, loc.field)).map(|positions| (loc.word, positions)))
+                .map(|(pos, positions)| positions.iter().filter(|&&p| p != pos && (p.abs_diff(pos) as usize) <= window).count())
+                .sum();
+            (observed > 0).then_some((word, observed, locs.len()))
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::AssociationMeasure;
+
+    #[test]
+    fn test_association_measures() {
+        // independent: observed equals expected (2 * 1 * 10 * 50 / 1000 = 1)
+        assert_eq!(AssociationMeasure::Pmi.score(1, 10, 50, 1, 1000), 0.0);
+        assert_eq!(AssociationMeasure::TScore.score(1, 10, 50, 1, 1000), 0.0);
+        assert!(AssociationMeasure::LogLikelihood.score(1, 10, 50, 1, 1000).abs() < 1e-9);
+        // attracted
+        assert_eq!(AssociationMeasure::Pmi.score(8, 10, 50, 1, 1000), 3.0);
+        assert!(AssociationMeasure::LogLikelihood.score(8, 10, 50, 1, 1000) > 10.0);
+        assert_eq!("LL".parse::<AssociationMeasure>(), Ok(AssociationMeasure::LogLikelihood));
+        assert!("chi2".parse::<AssociationMeasure>().is_err());
+    }
+}
@@ -0,0 +1,407 @@
+// Export of a WordIndex to a file in one of several formats, optionally gzip-compressed.
+//
+// All formats are streamed entry by entry into a buffered writer, so the export never holds the full output in memory.
+//  - word-count JSON/CSV/TSV: the vocabulary with the number of occurrences per word,
+//  - postings JSON: an object with the header (see header.rs) and an array with the word and all its locations per entry,
+//  - JSON Lines: the header on the first line, followed by the same entries, one JSON object per line,
+//  - binary: a compact format that 'WordIndex::load_binary' reads back into a complete index. All integers are
+//    variable-byte encoded (see postings.rs) and the lines of the postings are stored as deltas. The number of locations
+//    of a word is stored times two, plus one when its locations are followed by their field (see adapter.rs). The header
+//    precedes the postings and the optional structures (n-grams and BK-tree) follow them, all as JSON.
+// The postings JSON, the JSON Lines and the binary format are read back by 'WordIndex::load_json' and 'WordIndex::load_binary'.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::Serialize;
+use serde::Deserialize;
+use crate::bktree::BkTree;
+use crate::header::{check_format_version, IndexHeader, FORMAT_VERSION};
+use crate::index::{WordLoc, WordLocationsEntry};
+use crate::ngram::NGramModel;
+
+
+const BINARY_MAGIC: &[u8; 4] = b"TIDX";
+
+const SECTION_END: u8 = 0;
+const SECTION_NGRAMS: u8 = 1;
+const SECTION_BK_TREE: u8 = 2;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    WordCountJson,
+    WordCountCsv,
+    WordCountTsv,
+    PostingsJson,
+    JsonLines,
+    Binary
+}
+
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wc-json" | "count-json" => Ok(ExportFormat::WordCountJson),
+            "csv" | "wc-csv" => Ok(ExportFormat::WordCountCsv),
+            "tsv" | "wc-tsv" => Ok(ExportFormat::WordCountTsv),
+            "json" | "postings-json" => Ok(ExportFormat::PostingsJson),
+            "jsonl" | "json-lines" => Ok(ExportFormat::JsonLines),
+            "bin" | "binary" => Ok(ExportFormat::Binary),
+            _ => Err(format!("unknown export format '{s}' (use wc-json, csv, tsv, json, jsonl or binary)"))
+        }
+    }
+}
+
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        // guess the format from the extension of 'path', ignoring a trailing '.gz'
+        let name = path.file_name()?.to_str()?;
+        let name = name.strip_suffix(".gz").unwrap_or(name);
+        match name.rsplit_once('.')?.1 {
+            "csv" => Some(ExportFormat::WordCountCsv),
+            "tsv" => Some(ExportFormat::WordCountTsv),
+            "json" => Some(ExportFormat::PostingsJson),
+            "jsonl" => Some(ExportFormat::JsonLines),
+            "bin" => Some(ExportFormat::Binary),
+            _ => None
+        }
+    }
+}
+
+
+// the parts of a WordIndex that are exported
+pub(crate) struct ExportSource<'a> {
+    pub header: IndexHeader,
+    pub bt: &'a BTreeMap<String, Vec<WordLoc>>,
+    pub duration: Duration,
+    pub record_count: usize,
+    pub word_count: usize,
+    pub vocabulary_growth: &'a [(usize, usize)],
+    pub ngrams: Option<&'a NGramModel>,
+    pub bk_tree: Option<&'a BkTree>
+}
+
+
+// the parts of a WordIndex that are read back from a file; no header means that the file has format version 1
+pub(crate) struct LoadedIndex {
+    pub header: Option<IndexHeader>,
+    pub bt: BTreeMap<String, Vec<WordLoc>>,
+    pub duration: Duration,
+    pub record_count: usize,
+    pub word_count: usize,
+    pub vocabulary_growth: Vec<(usize, usize)>,
+    pub ngrams: Option<NGramModel>,
+    pub bk_tree: Option<BkTree>
+}
+
+
+pub(crate) fn export_to_file(source: &ExportSource, path: &Path, format: ExportFormat, compress: bool) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    if compress {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        export(source, &mut encoder, format)?;
+        encoder.finish()?.flush()
+    } else {
+        let mut file = file;
+        export(source, &mut file, format)?;
+        file.flush()
+    }
+}
+
+
+pub(crate) fn export<W: Write>(source: &ExportSource, w: &mut W, format: ExportFormat) -> io::Result<()> {
+    match format {
+        ExportFormat::WordCountJson => write_json_array(w, source.bt.iter().map(|(word, locs)| WordCountRef{ word, count: locs.len() })),
+        ExportFormat::WordCountCsv => {
+            writeln!(w, "word,count")?;
+            for (word, locs) in source.bt {
+                writeln!(w, "{},{}", csv_field(word), locs.len())?;
+            }
+            Ok(())
+        },
+        ExportFormat::WordCountTsv => {
+            // the words never contain whitespace, so they need no quoting
+            writeln!(w, "word\tcount")?;
+            for (word, locs) in source.bt {
+                writeln!(w, "{word}\t{}", locs.len())?;
+            }
+            Ok(())
+        },
+        ExportFormat::PostingsJson => {
+            w.write_all(b"{\"header\":")?;
+            serde_json::to_writer(&mut *w, &source.header)?;
+            w.write_all(b",\"entries\":")?;
+            write_json_array(w, source.bt.iter().map(|(word, locations)| WordLocationsRef{ word, locations }))?;
+            w.write_all(b"}")
+        },
+        ExportFormat::JsonLines => {
+            serde_json::to_writer(&mut *w, &HeaderLine{ header: &source.header })?;
+            writeln!(w)?;
+            for (word, locations) in source.bt {
+                serde_json::to_writer(&mut *w, &WordLocationsRef{ word, locations })?;
+                writeln!(w)?;
+            }
+            Ok(())
+        },
+        ExportFormat::Binary => write_binary(source, w)
+    }
+}
+
+
+#[derive(Serialize)]
+struct WordCountRef<'a> {
+    word: &'a str,
+    count: usize
+}
+
+
+#[derive(Serialize)]
+struct HeaderLine<'a> {
+    header: &'a IndexHeader
+}
+
+
+// serializes as a WordLocationsEntry without copying the locations
+#[derive(Serialize)]
+struct WordLocationsRef<'a> {
+    word: &'a str,
+    locations: &'a [WordLoc]
+}
+
+
+fn write_json_array<W: Write, T: Serialize, I: Iterator<Item = T>>(w: &mut W, items: I) -> io::Result<()> {
+    w.write_all(b"[")?;
+    for (idx, item) in items.enumerate() {
+        if idx > 0 {
+            w.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut *w, &item)?;
+    }
+    w.write_all(b"]")
+}
+
+
+fn csv_field(s: &str) -> String {
+    // quote a field when it contains a separator or a quote
+    if s.contains([',', '"']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+
+fn write_varint<W: Write>(w: &mut W, mut value: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    while value >= 0x80 {
+        buf[len] = (value as u8 & 0x7f) | 0x80;
+        value >>= 7;
+        len += 1;
+    }
+    buf[len] = value as u8;
+    w.write_all(&buf[..=len])
+}
+
+
+fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut value = 0;
+    let mut byte = [0u8];
+    for shift in (0..64).step_by(7) {
+        r.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("variable-byte integer is too long"))
+}
+
+
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_varint(w, bytes.len() as u64)?;
+    w.write_all(bytes)
+}
+
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_varint(r)? as usize;
+    let mut bytes = Vec::new();
+    r.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+
+fn write_binary<W: Write>(source: &ExportSource, w: &mut W) -> io::Result<()> {
+    w.write_all(BINARY_MAGIC)?;
+    w.write_all(&[FORMAT_VERSION as u8])?;
+    write_bytes(w, &serde_json::to_vec(&source.header)?)?;
+    write_varint(w, source.record_count as u64)?;
+    write_varint(w, source.word_count as u64)?;
+    write_varint(w, source.duration.as_micros() as u64)?;
+
+    write_varint(w, source.bt.len() as u64)?;
+    for (word, locs) in source.bt {
+        write_bytes(w, word.as_bytes())?;
+        let with_fields = locs.iter().any(|loc| loc.field > 0);
+        write_varint(w, 2 * locs.len() as u64 + with_fields as u64)?;
+        let mut prev_line = 0;
+        for loc in locs {
+            write_varint(w, (loc.line - prev_line) as u64)?;
+            write_varint(w, loc.word as u64)?;
+            if with_fields {
+                write_varint(w, loc.field as u64)?;
+            }
+            prev_line = loc.line;
+        }
+    }
+
+    write_varint(w, source.vocabulary_growth.len() as u64)?;
+    for &(tokens, types) in source.vocabulary_growth {
+        write_varint(w, tokens as u64)?;
+        write_varint(w, types as u64)?;
+    }
+
+    if let Some(ngrams) = source.ngrams {
+        w.write_all(&[SECTION_NGRAMS])?;
+        write_bytes(w, &serde_json::to_vec(ngrams)?)?;
+    }
+    if let Some(bk_tree) = source.bk_tree {
+        w.write_all(&[SECTION_BK_TREE])?;
+        write_bytes(w, &serde_json::to_vec(bk_tree)?)?;
+    }
+    w.write_all(&[SECTION_END])
+}
+
+
+fn open_maybe_gzipped(path: &Path) -> io::Result<Box<dyn Read>> {
+    // a reader over the contents of 'path', which is decompressed when it starts with the gzip magic bytes
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 2];
+    let n = reader.read(&mut magic)?;
+    let reader = io::Cursor::new(magic[..n].to_vec()).chain(reader);
+    if magic == [0x1f, 0x8b] {
+        Ok(Box::new(BufReader::new(GzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(BufReader::new(reader)))
+    }
+}
+
+
+pub(crate) fn read_binary_file(path: &Path) -> io::Result<LoadedIndex> {
+    // Read a binary export, which may be gzip-compressed.
+    read_binary(&mut open_maybe_gzipped(path)?)
+}
+
+
+pub(crate) fn read_binary<R: Read>(r: &mut R) -> io::Result<LoadedIndex> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != BINARY_MAGIC {
+        return Err(invalid_data("not a binary text index"));
+    }
+    let mut version = [0u8];
+    r.read_exact(&mut version)?;
+    check_format_version(version[0] as u32)?;
+    let header: Option<IndexHeader> = if version[0] >= 2 { Some(serde_json::from_slice(&read_bytes(r)?)?) } else { None };
+    let record_count = read_varint(r)? as usize;
+    let word_count = read_varint(r)? as usize;
+    let duration = Duration::from_micros(read_varint(r)?);
+
+    let mut bt = BTreeMap::new();
+    for _ in 0..read_varint(r)? {
+        let word = String::from_utf8(read_bytes(r)?).map_err(|_| invalid_data("word is not valid UTF-8"))?;
+        // format version 2 has no fields
+        let num_locs = read_varint(r)? as usize;
+        let (num_locs, with_fields) = if version[0] >= 3 { (num_locs / 2, num_locs % 2 == 1) } else { (num_locs, false) };
+        let mut locs = Vec::with_capacity(num_locs);
+        let mut line = 0;
+        for _ in 0..num_locs {
+            line += read_varint(r)? as u32;
+            let word = read_varint(r)? as u16;
+            let field = if with_fields { read_varint(r)? as u16 } else { 0 };
+            locs.push(WordLoc{ line, field, word });
+        }
+        bt.insert(word, locs);
+    }
+
+    let mut vocabulary_growth = Vec::new();
+    for _ in 0..read_varint(r)? {
+        vocabulary_growth.push((read_varint(r)? as usize, read_varint(r)? as usize));
+    }
+
+    let (mut ngrams, mut bk_tree) = (None, None);
+    loop {
+        let mut tag = [0u8];
+        r.read_exact(&mut tag)?;
+        match tag[0] {
+            SECTION_END => break,
+            SECTION_NGRAMS => ngrams = Some(serde_json::from_slice(&read_bytes(r)?)?),
+            SECTION_BK_TREE => bk_tree = Some(serde_json::from_slice(&read_bytes(r)?)?),
+            _ => { read_bytes(r)?; }  // a section of a newer writer that this version does not use
+        }
+    }
+    Ok(LoadedIndex{ header, bt, duration, record_count, word_count, vocabulary_growth, ngrams, bk_tree })
+}
+
+
+// a value in a JSON export: the header (first line of JSON Lines), an entry (the other lines of JSON Lines),
+// a postings JSON document, or the headerless array of format version 1
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonValue {
+    Document{ header: IndexHeader, entries: Vec<WordLocationsEntry> },
+    Header{ header: IndexHeader },
+    Entry(WordLocationsEntry),
+    Legacy(Vec<WordLocationsEntry>)
+}
+
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    format_version: u32
+}
+
+
+pub(crate) fn read_json_file(path: &Path) -> io::Result<LoadedIndex> {
+    // Read a postings JSON or JSON Lines export, which may be gzip-compressed.
+    let mut header = None;
+    let mut bt = BTreeMap::new();
+    for value in serde_json::Deserializer::from_reader(open_maybe_gzipped(path)?).into_iter::<serde_json::Value>() {
+        let value = value?;
+        // check the version before the header is interpreted, as a newer header may not fit in IndexHeader
+        if let Some(version) = value.get("header").and_then(|h| VersionOnly::deserialize(h).ok()) {
+            check_format_version(version.format_version)?;
+        }
+        match JsonValue::deserialize(value)? {
+            JsonValue::Document{ header: h, entries } => {
+                header = Some(h);
+                bt.extend(entries.into_iter().map(|e| (e.word, e.locations)));
+            },
+            JsonValue::Header{ header: h } => header = Some(h),
+            JsonValue::Entry(e) => { bt.insert(e.word, e.locations); },
+            JsonValue::Legacy(entries) => bt.extend(entries.into_iter().map(|e| (e.word, e.locations)))
+        }
+    }
+    // the counts of a headerless file are derived from the postings
+    let duration = header.as_ref().map_or(Duration::default(), |h| h.build_duration);
+    let record_count = header.as_ref().map_or_else(|| bt.values().flatten().map(|loc: &WordLoc| loc.line as usize + 1).max().unwrap_or(0), |h| h.record_count);
+    let word_count = header.as_ref().map_or_else(|| bt.values().map(|locs: &Vec<WordLoc>| locs.len()).sum(), |h| h.word_count);
+    Ok(LoadedIndex{ header, bt, duration, record_count, word_count, vocabulary_growth: Vec::new(), ngrams: None, bk_tree: None })
+}
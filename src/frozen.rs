@@ -0,0 +1,148 @@
+// A read-only WordIndex where the vocabulary is stored as an Fst instead of the keys of a BTreeMap.
+//
+// Once an index is built it is not updated anymore, so 'WordIndex::freeze' can move the posting lists into a Vec, where the
+// position of a posting list is the id the Fst assigns to its term. The posting lists are compressed (see postings.rs) and
+// decoded on the fly by the iterator returned from 'find_matches'; 'records_with_all' intersects them with the skip entries
+// of the blocks. The completions are computed on the Fst, so they
+// return the same results as the WordIndex they are frozen from.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use crate::fst::{DamLevAutomaton, Fst, RegexAutomaton};
+use crate::index::{top_completions_count, CompletionsRec, IndexOptions, NewCompl, WordLoc};
+use crate::postings::{CompressedPostings, PostingsIter};
+use crate::header::{check_format_version, check_tokenizer, IndexHeader, FORMAT_VERSION};
+
+
+#[derive(Serialize, Deserialize)]
+pub struct FrozenWordIndex {
+    fst: Fst,
+    postings: Vec<CompressedPostings>,  // postings[id] are the locations of the term with id 'id' in 'fst'
+    pub duration: Duration,
+    pub record_count: usize,
+    pub word_count: usize,
+    #[serde(default)]
+    header: Option<IndexHeader>  // None only while reading a file written before the header existed (format version 1)
+}
+
+
+impl FrozenWordIndex {
+    pub(crate) fn new(fst: Fst, postings: Vec<CompressedPostings>, header: IndexHeader) -> Self {
+        FrozenWordIndex{ fst, postings, duration: header.build_duration, record_count: header.record_count, word_count: header.word_count, header: Some(header) }
+    }
+
+    pub fn header(&self) -> &IndexHeader {
+        self.header.as_ref().expect("the header is set by 'new' and 'load_from_file'")
+    }
+
+    pub fn len(&self) -> usize {
+        self.fst.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fst.is_empty()
+    }
+
+    pub fn fst(&self) -> &Fst {
+        &self.fst
+    }
+
+    pub fn find_matches(&self, search_str: &str) -> Option<PostingsIter<'_>> {
+        self.fst.get(search_str).map(|id| self.postings[id as usize].iter())
+    }
+
+    pub fn records_with_all(&self, words: &[&str]) -> Vec<u32> {
+        // The records that contain all 'words', in increasing order. The shortest posting list proposes the records, and
+        // every list seeks to the record proposed: a list that is beyond it proposes its own record instead.
+        let Some(mut lists) = words.iter().map(|word| self.fst.get(word).map(|id| &self.postings[id as usize])).collect::<Option<Vec<_>>>() else { return Vec::new() };
+        lists.sort_by_key(|postings| postings.len());
+        let mut iters: Vec<PostingsIter> = lists.iter().map(|postings| postings.iter()).collect();
+        let mut records = Vec::new();
+        let Some(mut record) = iters.first().and_then(|iter| iter.peek_line()) else { return records };
+        'records: loop {
+            for iter in iters.iter_mut() {
+                iter.seek(record);
+                match iter.peek_line() {
+                    None => break 'records,
+                    Some(line) if line > record => {
+                        record = line;
+                        continue 'records;
+                    },
+                    Some(_) => ()
+                }
+            }
+            records.push(record);
+            match record.checked_add(1) {
+                Some(next) => record = next,
+                None => break
+            }
+        }
+        records
+    }
+
+    pub fn postings_memory(&self) -> (usize, usize) {
+        // heap memory of the posting lists as (bytes when stored as Vec<WordLoc>, bytes compressed)
+        let uncompressed = self.postings.iter().map(|p| p.len() * std::mem::size_of::<WordLoc>()).sum();
+        let compressed = self.postings.iter().map(|p| p.heap_size()).sum();
+        (uncompressed, compressed)
+    }
+
+    fn fold_completions<I: Iterator<Item = (String, u32)>>(&self, terms: I, num_completions: usize, start: Instant) -> CompletionsRec {
+        let mut completions_rec = terms
+            .fold(CompletionsRec::new(num_completions), |state, (term, id)| top_completions_count(state, &term, self.postings[id as usize].len()));
+        completions_rec.duration = start.elapsed();
+        completions_rec
+    }
+
+    pub fn find_completions(&self, check_word: &str, num_completions: usize) -> CompletionsRec {
+        // the 'num_completions' most frequent terms starting with 'check_word'
+        let start = Instant::now();
+        self.fold_completions(self.fst.prefix_range(check_word), num_completions, start)
+    }
+
+    pub fn find_dl_completions(&self, check_word: &str, num_completions: usize, max_dist: usize) -> CompletionsRec {
+        // as 'WordIndex::find_dl_completions', but the Fst is only explored as far as the prefix distance stays within 'max_dist'
+        let start = Instant::now();
+        let matches = self.fst.search(&DamLevAutomaton::new(check_word, max_dist, true));
+        self.fold_completions(matches.into_iter().filter(|(term, _)| !term.starts_with(check_word)), num_completions, start)
+    }
+
+    pub fn find_regex_completions(&self, pattern: &str, num_completions: usize) -> Result<CompletionsRec, String> {
+        // the most frequent terms that fully match the regular expression 'pattern'
+        let start = Instant::now();
+        let matches = self.fst.search(&RegexAutomaton::new(pattern)?);
+        Ok(self.fold_completions(matches.into_iter(), num_completions, start))
+    }
+
+    pub fn save_to_file(&self, filename: &str) -> std::io::Result<()> {
+        // Write the frozen index as gzipped json. Finishing the encoder and flushing the file report the errors of the
+        // last writes.
+        let mut encoder = GzEncoder::new(BufWriter::new(File::create(filename)?), Compression::default());
+        serde_json::to_writer(&mut encoder, self)?;
+        encoder.finish()?.flush()
+    }
+
+    pub fn load_from_file(filename: &str) -> std::io::Result<Self> {
+        // A file of a newer format version or of another tokenizer is rejected; a file without header is migrated to the
+        // current version.
+        let decoder = GzDecoder::new(BufReader::new(File::open(filename)?));
+        let value: serde_json::Value = serde_json::from_reader(BufReader::new(decoder))?;
+        if let Some(version) = value.pointer("/header/format_version").and_then(|v| v.as_u64()) {
+            check_format_version(version as u32)?;
+        }
+        let mut frozen = FrozenWordIndex::deserialize(value)?;
+        let header = match frozen.header.take() {
+            Some(header) => header.migrated(),
+            None => {
+                println!("Migrating an index without header (format version 1) to format version {FORMAT_VERSION}");
+                IndexHeader::for_version_1(frozen.record_count, frozen.word_count, frozen.duration, IndexOptions::default())
+            }
+        };
+        check_tokenizer(&header.tokenizer)?;
+        frozen.header = Some(header);
+        Ok(frozen)
+    }
+}
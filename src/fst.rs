@@ -0,0 +1,437 @@
+// A read-only vocabulary stored as a minimal acyclic finite-state automaton over the UTF-8 bytes of the terms.
+//
+// Terms that share a prefix share the states of that prefix, and terms that share a suffix share the states of that suffix,
+// so the vocabulary takes far less memory than owned String keys in a BTreeMap.
+// Every state also records how many terms are accepted below it. That turns the automaton into a transducer that maps each
+// term to its rank in sorted order, which is used as the id of its posting list.
+//
+// The automaton is built from sorted keys with the incremental algorithm of Daciuk et al.: the states of the previous key that
+// are not shared with the next key can no longer change, so they are minimized immediately against a register of frozen states.
+//
+// Queries:
+//  - get: the id of a term.
+//  - prefix_range: all terms with a prefix, in sorted order.
+//  - search: all terms accepted by an Automaton (for example a DamLevAutomaton or a RegexAutomaton), by walking both at once
+//    and pruning every branch that the Automaton can no longer match.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use regex_automata::{dfa::{dense, Automaton as DfaAutomaton}, util::{primitives::StateID, start}, Anchored};
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FstState {
+    is_final: bool,
+    num_terms: u32,    // number of terms accepted in the sub-automaton starting at this state
+    trans_start: u32,  // transitions of this state are labels/targets[trans_start..trans_start + trans_len], ordered by label
+    trans_len: u16
+}
+
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Fst {
+    states: Vec<FstState>,
+    labels: Vec<u8>,
+    targets: Vec<u32>,
+    root: u32,
+    len: usize
+}
+
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+struct UnfinishedState {
+    is_final: bool,
+    trans: Vec<(u8, u32)>
+}
+
+
+struct FstBuilder {
+    fst: Fst,
+    register: HashMap<UnfinishedState, u32>,
+    unfinished: Vec<UnfinishedState>,  // the states along the path of the last key; unfinished[0] is the root
+    last_key: Vec<u8>
+}
+
+
+impl FstBuilder {
+    fn new() -> Self {
+        FstBuilder{ fst: Fst::default(), register: HashMap::new(), unfinished: vec![UnfinishedState::default()], last_key: Vec::new() }
+    }
+
+    fn freeze(&mut self, state: UnfinishedState) -> u32 {
+        // return the id of an equivalent frozen state, or add it as a new state
+        if let Some(&id) = self.register.get(&state) {
+            return id;
+        }
+        let id = self.fst.states.len() as u32;
+        let num_terms = state.is_final as u32 + state.trans.iter().map(|&(_, t)| self.fst.states[t as usize].num_terms).sum::<u32>();
+        self.fst.states.push(FstState{
+            is_final: state.is_final,
+            num_terms,
+            trans_start: self.fst.labels.len() as u32,
+            trans_len: state.trans.len() as u16
+        });
+        for &(label, target) in &state.trans {
+            self.fst.labels.push(label);
+            self.fst.targets.push(target);
+        }
+        self.register.insert(state, id);
+        id
+    }
+
+    fn freeze_down_to(&mut self, depth: usize) {
+        // freeze the unfinished states deeper than 'depth' and link them into their parent
+        while self.unfinished.len() > depth + 1 {
+            let state = self.unfinished.pop().unwrap();
+            let id = self.freeze(state);
+            let parent = self.unfinished.last_mut().unwrap();
+            parent.trans.last_mut().unwrap().1 = id;
+        }
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        // keys have to be inserted in strictly increasing order
+        assert!(self.fst.len == 0 || key > self.last_key.as_slice(), "keys must be sorted and unique");
+        let common = key.iter().zip(self.last_key.iter()).take_while(|(a, b)| a == b).count();
+        self.freeze_down_to(common);
+        for &byte in &key[common..] {
+            self.unfinished.last_mut().unwrap().trans.push((byte, u32::MAX));
+            self.unfinished.push(UnfinishedState::default());
+        }
+        self.unfinished.last_mut().unwrap().is_final = true;
+        self.last_key = key.to_vec();
+        self.fst.len += 1;
+    }
+
+    fn finish(mut self) -> Fst {
+        self.freeze_down_to(0);
+        let root = self.unfinished.pop().unwrap();
+        self.fst.root = self.freeze(root);
+        self.fst
+    }
+}
+
+
+impl Fst {
+    pub fn from_sorted_keys<'a, I: IntoIterator<Item = &'a str>>(keys: I) -> Self {
+        // Build the automaton; panics when the keys are not sorted and unique (as they are for the keys of a BTreeMap).
+        let mut builder = FstBuilder::new();
+        for key in keys {
+            builder.insert(key.as_bytes());
+        }
+        builder.finish()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.states.capacity() * std::mem::size_of::<FstState>() + self.labels.capacity() + self.targets.capacity() * 4
+    }
+
+    fn transitions(&self, state: u32) -> impl Iterator<Item = (u8, u32)> + '_ {
+        let s = &self.states[state as usize];
+        let range = s.trans_start as usize..s.trans_start as usize + s.trans_len as usize;
+        self.labels[range.clone()].iter().copied().zip(self.targets[range].iter().copied())
+    }
+
+    fn walk(&self, key: &[u8]) -> Option<(u32, u32)> {
+        // follow 'key' from the root. Returns the state reached and the number of terms that sort before 'key'.
+        if self.is_empty() {
+            return None;
+        }
+        let mut state = self.root;
+        let mut rank = 0;
+        for &byte in key {
+            if self.states[state as usize].is_final {
+                rank += 1;
+            }
+            let mut next = None;
+            for (label, target) in self.transitions(state) {
+                if label < byte {
+                    rank += self.states[target as usize].num_terms;
+                } else {
+                    if label == byte {
+                        next = Some(target);
+                    }
+                    break;
+                }
+            }
+            state = next?;
+        }
+        Some((state, rank))
+    }
+
+    pub fn get(&self, key: &str) -> Option<u32> {
+        // the id (rank in sorted order) of 'key'
+        match self.walk(key.as_bytes()) {
+            Some((state, rank)) if self.states[state as usize].is_final => Some(rank),
+            _ => None
+        }
+    }
+
+    pub fn prefix_range(&self, prefix: &str) -> FstStream<'_> {
+        // all terms starting with 'prefix' together with their ids, in sorted order
+        let mut stream = FstStream{ fst: self, stack: Vec::new(), key: prefix.as_bytes().to_vec(), next_id: 0 };
+        if let Some((state, rank)) = self.walk(prefix.as_bytes()) {
+            stream.stack.push((state, None));
+            stream.next_id = rank;
+        }
+        stream
+    }
+
+    pub fn search<A: Automaton>(&self, aut: &A) -> Vec<(String, u32)> {
+        // all terms (with their ids) accepted by 'aut', in sorted order
+        let mut results = Vec::new();
+        if self.is_empty() {
+            return results;
+        }
+        let mut key = Vec::new();
+        self.search_from(self.root, 0, aut.start(), aut, &mut key, &mut results);
+        results
+    }
+
+    fn search_from<A: Automaton>(&self, state: u32, rank: u32, aut_state: A::State, aut: &A, key: &mut Vec<u8>, results: &mut Vec<(String, u32)>) {
+        let mut rank = rank;
+        if self.states[state as usize].is_final {
+            if aut.is_match(&aut_state) {
+                results.push((String::from_utf8(key.clone()).unwrap(), rank));
+            }
+            rank += 1;
+        }
+        for (label, target) in self.transitions(state) {
+            let next = aut.accept(&aut_state, label);
+            if aut.can_match(&next) {
+                key.push(label);
+                self.search_from(target, rank, next, aut, key, results);
+                key.pop();
+            }
+            rank += self.states[target as usize].num_terms;
+        }
+    }
+}
+
+
+pub struct FstStream<'a> {
+    fst: &'a Fst,
+    stack: Vec<(u32, Option<usize>)>,  // (state, index of the transition taken to go deeper) along the current path
+    key: Vec<u8>,
+    next_id: u32
+}
+
+
+impl Iterator for FstStream<'_> {
+    type Item = (String, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // depth-first traversal in label order; a final state is reported when it is entered
+        while let Some((state, taken)) = self.stack.pop() {
+            let s = &self.fst.states[state as usize];
+            let next_trans = match taken {
+                None => 0,
+                Some(idx) => {
+                    self.key.pop();
+                    idx + 1
+                }
+            };
+            if next_trans < s.trans_len as usize {
+                let pos = s.trans_start as usize + next_trans;
+                self.stack.push((state, Some(next_trans)));
+                self.key.push(self.fst.labels[pos]);
+                self.stack.push((self.fst.targets[pos], None));
+            }
+            if taken.is_none() && s.is_final {
+                let id = self.next_id;
+                self.next_id += 1;
+                let key_len = if next_trans < s.trans_len as usize { self.key.len() - 1 } else { self.key.len() };
+                return Some((String::from_utf8(self.key[..key_len].to_vec()).unwrap(), id));
+            }
+        }
+        None
+    }
+}
+
+
+// An automaton over bytes that can be intersected with an Fst.
+pub trait Automaton {
+    type State: Clone;
+
+    fn start(&self) -> Self::State;
+    fn is_match(&self, state: &Self::State) -> bool;
+    fn can_match(&self, state: &Self::State) -> bool;  // false when no extension of the input can match anymore
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State;
+}
+
+
+pub struct DamLevAutomaton {
+    // Accepts the terms within 'max_dist' (optimal string alignment distance) of 'query', or terms that have a prefix within 'max_dist' when 'prefix' is set.
+    query: Vec<char>,
+    max_dist: usize,
+    prefix: bool
+}
+
+
+#[derive(Clone)]
+pub struct DamLevState {
+    row: Vec<usize>,       // row[i] = distance between query[..i] and the input so far
+    prev_row: Vec<usize>,
+    prev_char: Option<char>,
+    pending: Vec<u8>,      // bytes of an incomplete UTF-8 character
+    best: usize            // smallest row[query.len()] seen so far (for prefix matching)
+}
+
+
+impl DamLevAutomaton {
+    pub fn new(query: &str, max_dist: usize, prefix: bool) -> Self {
+        DamLevAutomaton{ query: query.chars().collect(), max_dist, prefix }
+    }
+
+    fn step(&self, state: &DamLevState, ch: char) -> DamLevState {
+        let n = self.query.len();
+        let mut row = vec![0; n + 1];
+        row[0] = state.row[0] + 1;
+        for i in 1..=n {
+            let cost = if self.query[i - 1] == ch { 0 } else { 1 };
+            row[i] = (state.row[i - 1] + cost).min(state.row[i] + 1).min(row[i - 1] + 1);
+            if i > 1 && state.prev_char == Some(self.query[i - 1]) && self.query[i - 2] == ch {
+                row[i] = row[i].min(state.prev_row[i - 2] + 1);
+            }
+        }
+        let best = state.best.min(row[n]);
+        DamLevState{ prev_row: state.row.clone(), row, prev_char: Some(ch), pending: Vec::new(), best }
+    }
+}
+
+
+impl Automaton for DamLevAutomaton {
+    type State = DamLevState;
+
+    fn start(&self) -> DamLevState {
+        let row: Vec<usize> = (0..=self.query.len()).collect();
+        DamLevState{ best: row[self.query.len()], prev_row: row.clone(), row, prev_char: None, pending: Vec::new() }
+    }
+
+    fn is_match(&self, state: &DamLevState) -> bool {
+        state.pending.is_empty() && (state.row[self.query.len()] <= self.max_dist || (self.prefix && state.best <= self.max_dist))
+    }
+
+    fn can_match(&self, state: &DamLevState) -> bool {
+        // a transposition can still continue from the previous row
+        (self.prefix && state.best <= self.max_dist)
+            || state.row.iter().min().is_some_and(|&d| d <= self.max_dist)
+            || state.prev_row.iter().min().is_some_and(|&d| d < self.max_dist)
+    }
+
+    fn accept(&self, state: &DamLevState, byte: u8) -> DamLevState {
+        let mut pending = state.pending.clone();
+        pending.push(byte);
+        match std::str::from_utf8(&pending) {
+            Ok(s) => self.step(state, s.chars().next().unwrap()),
+            Err(_) => DamLevState{ pending, ..state.clone() }
+        }
+    }
+}
+
+
+pub struct RegexAutomaton {
+    // Accepts the terms that fully match a regular expression.
+    dfa: dense::DFA<Vec<u32>>
+}
+
+
+impl RegexAutomaton {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let dfa = dense::DFA::new(pattern).map_err(|e| e.to_string())?;
+        Ok(RegexAutomaton{ dfa })
+    }
+}
+
+
+impl Automaton for RegexAutomaton {
+    type State = StateID;
+
+    fn start(&self) -> StateID {
+        // anchored at the start; the end is anchored by only reporting a match at the end of the term
+        self.dfa.start_state(&start::Config::new().anchored(Anchored::Yes)).expect("anchored start state")
+    }
+
+    fn is_match(&self, state: &StateID) -> bool {
+        self.dfa.is_match_state(self.dfa.next_eoi_state(*state))
+    }
+
+    fn can_match(&self, state: &StateID) -> bool {
+        !self.dfa.is_dead_state(*state)
+    }
+
+    fn accept(&self, state: &StateID, byte: u8) -> StateID {
+        self.dfa.next_state(*state, byte)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{DamLevAutomaton, Fst, RegexAutomaton};
+    use crate::levenshtein::dam_lev_prefix;
+
+    fn words() -> Vec<&'static str> {
+        let mut words = vec!["a", "an", "and", "hand", "band", "bands", "the", "then", "them", "these", "themselves", "those", "théâtre", "zz", "zzzzzzzzzz", "ähnlich"];
+        words.sort();
+        words
+    }
+
+    #[test]
+    fn test_get_and_minimality() {
+        let words = words();
+        let fst = Fst::from_sorted_keys(words.iter().copied());
+        assert_eq!(fst.len(), words.len());
+        for (id, word) in words.iter().enumerate() {
+            assert_eq!(fst.get(word), Some(id as u32), "{word}");
+        }
+        assert_eq!(fst.get("th"), None);
+        assert_eq!(fst.get("thesex"), None);
+        assert_eq!(fst.get(""), None);
+        // "hand" and "band" share their suffix states, so there are fewer states than bytes
+        assert!(fst.num_states() < words.iter().map(|w| w.len()).sum::<usize>());
+        assert!(Fst::from_sorted_keys(Vec::<&str>::new()).get("a").is_none());
+    }
+
+    #[test]
+    fn test_prefix_range() {
+        let words = words();
+        let fst = Fst::from_sorted_keys(words.iter().copied());
+        for prefix in ["", "th", "the", "them", "z", "ä", "x", "thé"] {
+            let expected: Vec<(String, u32)> = words.iter().enumerate()
+                .filter(|(_, w)| w.starts_with(prefix))
+                .map(|(id, w)| (w.to_string(), id as u32))
+                .collect();
+            assert_eq!(fst.prefix_range(prefix).collect::<Vec<_>>(), expected, "prefix '{prefix}'");
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let words = words();
+        let fst = Fst::from_sorted_keys(words.iter().copied());
+
+        let found: Vec<String> = fst.search(&DamLevAutomaton::new("thse", 1, true)).into_iter().map(|(w, _)| w).collect();
+        let expected: Vec<&str> = words.iter().copied().filter(|w| dam_lev_prefix("thse", w, 1).is_some()).collect();
+        assert_eq!(found, expected);
+
+        let found: Vec<String> = fst.search(&DamLevAutomaton::new("thatre", 2, false)).into_iter().map(|(w, _)| w).collect();
+        assert_eq!(found, vec!["théâtre"]);
+
+        let found: Vec<(String, u32)> = fst.search(&RegexAutomaton::new("th.s.*").unwrap());
+        assert_eq!(found, vec![("these".to_string(), fst.get("these").unwrap()), ("those".to_string(), fst.get("those").unwrap())]);
+        assert!(RegexAutomaton::new("(").is_err());
+    }
+}
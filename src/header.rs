@@ -0,0 +1,257 @@
+// The header that is stored in front of every persisted index.
+//
+// It describes how the index was produced: the version of the file format, the source file (path, size, modification time
+// and a content hash), the tokenizer and the options of 'build_index', the counts and the time needed to build it. A loader
+// uses the format version to reject files written by a newer version and to migrate files written by an older version.
+//
+// Format versions:
+//  1: no header (the JSON array of 'WordLocationsEntry' written by the old 'save_index' and the first binary format)
+//  2: the header is stored in front of the postings
+//  3: a location refers to a field of a record and the header names the fields (see adapter.rs)
+
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::index::{IndexOptions, LEADING_PUNCTUATION, TRAILING_PUNCTUATION};
+use crate::section::SectionTable;
+
+
+pub const FORMAT_VERSION: u32 = 3;
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceInfo {
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,  // seconds since the Unix epoch
+    pub hash: String    // FNV-1a (64 bits) of the contents as hex
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Staleness {
+    Fresh,     // the source has not changed (it may have been touched)
+    Appended,  // lines were added at the end of the source, so the index can be updated incrementally
+    Changed,   // the source changed otherwise and the index has to be rebuilt
+    Missing,   // the source does not exist anymore
+    Unknown    // the index does not record its source
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenizerConfig {
+    pub split: String,            // how a line is split into words
+    pub case_sensitive: bool,
+    pub strip_leading: String,    // characters removed from the start of a word
+    pub strip_trailing: String    // characters removed from the end of a word
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexHeader {
+    pub format_version: u32,
+    pub crate_version: String,
+    pub source: Option<SourceInfo>,
+    pub tokenizer: TokenizerConfig,
+    pub options: IndexOptions,
+    pub record_count: usize,
+    pub word_count: usize,
+    pub build_duration: Duration,
+    pub built_at: u64,  // seconds since the Unix epoch
+    #[serde(default)]
+    pub fields: Vec<String>,  // names of the fields of the records, empty for plain text
+    #[serde(default, skip_serializing_if = "SectionTable::is_empty")]
+    pub sections: SectionTable,  // the sections of the records (see section.rs)
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub removed: BTreeSet<u32>  // the records that were deleted: they keep their number, but have no locations
+}
+
+
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+
+pub fn crate_version() -> String {
+    env!("CARGO_PKG_VERSION").to_string()
+}
+
+
+pub fn content_hash<R: Read>(mut reader: R) -> io::Result<String> {
+    // FNV-1a is not a cryptographic hash, but it is stable across Rust versions and platforms (contrary to DefaultHasher)
+    // and good enough to notice that a file changed.
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash = OFFSET_BASIS;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for &byte in &buf[..n] {
+            hash = (hash ^ byte as u64).wrapping_mul(PRIME);
+        }
+    }
+    Ok(format!("{hash:016x}"))
+}
+
+
+impl SourceInfo {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        // the absolute path, so that the source can still be found when the index is used from another directory
+        let path = fs::canonicalize(path.as_ref())?;
+        let metadata = fs::metadata(&path)?;
+        Ok(SourceInfo{
+            path: path.display().to_string(),
+            size: metadata.len(),
+            modified: metadata.modified().map_or(0, unix_seconds),
+            hash: content_hash(BufReader::new(File::open(&path)?))?
+        })
+    }
+
+    pub fn staleness(&self) -> io::Result<Staleness> {
+        // Compare the source as it is now with this description. The size and the modification time are checked first;
+        // the contents are only hashed when one of them differs. A source that grew is 'Appended' when its first 'size'
+        // bytes still have the recorded hash and end at a line break, so that the old lines are unchanged.
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Staleness::Missing),
+            Err(err) => return Err(err)
+        };
+        let size = metadata.len();
+        if size == self.size && metadata.modified().map_or(0, unix_seconds) == self.modified {
+            return Ok(Staleness::Fresh);
+        }
+        if size == self.size {
+            let hash = content_hash(BufReader::new(File::open(&self.path)?))?;
+            return Ok(if hash == self.hash { Staleness::Fresh } else { Staleness::Changed });
+        }
+        if size > self.size {
+            let mut prefix = BufReader::new(File::open(&self.path)?).take(self.size);
+            let hash = content_hash(&mut prefix)?;
+            let mut next = [0u8];
+            let mut file = prefix.into_inner();
+            if self.size > 0 {
+                file.seek_relative(-1)?;
+                file.read_exact(&mut next)?;
+            }
+            if hash == self.hash && (self.size == 0 || next[0] == b'\n') {
+                return Ok(Staleness::Appended);
+            }
+        }
+        Ok(Staleness::Changed)
+    }
+}
+
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        TokenizerConfig::current()
+    }
+}
+
+
+impl TokenizerConfig {
+    pub fn current() -> Self {
+        // the tokenizer of 'WordIndex::build_index'
+        TokenizerConfig{
+            split: "whitespace".to_string(),
+            case_sensitive: true,
+            strip_leading: LEADING_PUNCTUATION.to_string(),
+            strip_trailing: TRAILING_PUNCTUATION.to_string()
+        }
+    }
+}
+
+
+pub fn check_format_version(format_version: u32) -> io::Result<()> {
+    // Files of a newer format can not be read; older ones are migrated by the loaders.
+    if format_version > FORMAT_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "index format version {format_version} is not supported by text_index {} (supports up to version {FORMAT_VERSION}); rebuild the index or upgrade text_index",
+            env!("CARGO_PKG_VERSION"))));
+    }
+    Ok(())
+}
+
+
+pub fn check_tokenizer(tokenizer: &TokenizerConfig) -> io::Result<()> {
+    // An index that was built with another tokenizer does not find the words as this version splits them.
+    if *tokenizer != TokenizerConfig::current() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "the index was built with another tokenizer ({tokenizer:?}) than the one of text_index {} ({:?}); rebuild the index",
+            env!("CARGO_PKG_VERSION"), TokenizerConfig::current())));
+    }
+    Ok(())
+}
+
+
+impl IndexHeader {
+    pub fn for_version_1(record_count: usize, word_count: usize, build_duration: Duration, options: IndexOptions) -> Self {
+        // The header of an index without header (format version 1). The tokenizer has not changed since, so the current
+        // configuration applies; the source is unknown. The caller reports the migration.
+        IndexHeader{
+            format_version: FORMAT_VERSION,
+            crate_version: crate_version(),
+            source: None,
+            tokenizer: TokenizerConfig::current(),
+            options,
+            record_count,
+            word_count,
+            build_duration,
+            built_at: 0,
+            fields: Vec::new(),
+            sections: SectionTable::default(),
+            removed: BTreeSet::new()
+        }
+    }
+
+    pub fn migrated(self) -> Self {
+        // the header after migrating the index to the current format version
+        IndexHeader{ format_version: FORMAT_VERSION, ..self }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{content_hash, SourceInfo, Staleness};
+    use crate::testutil::TempPath;
+
+    #[test]
+    fn test_source_info() {
+        assert_eq!(content_hash("".as_bytes()).unwrap(), "cbf29ce484222325");
+        assert_eq!(content_hash("a".as_bytes()).unwrap(), "af63dc4c8601ec8c");
+
+        let path = TempPath::new("source.txt");
+        std::fs::write(&path, "to be or not to be\n").unwrap();
+        let info = SourceInfo::from_path(&path).unwrap();
+        assert_eq!(info.size, 19);
+        assert_eq!(info.hash, content_hash("to be or not to be\n".as_bytes()).unwrap());
+        assert!(info.modified > 0);
+    }
+
+    #[test]
+    fn test_staleness() {
+        let path = TempPath::new("staleness.txt");
+        std::fs::write(&path, "to be or not to be\n").unwrap();
+        let info = SourceInfo::from_path(&path).unwrap();
+        assert_eq!(info.staleness().unwrap(), Staleness::Fresh);
+        let touched = SourceInfo{ modified: info.modified - 10, ..info.clone() };
+        assert_eq!(touched.staleness().unwrap(), Staleness::Fresh);
+
+        std::fs::write(&path, "to be or not to be\nthat is the question\n").unwrap();
+        assert_eq!(info.staleness().unwrap(), Staleness::Appended);
+        std::fs::write(&path, "to be or not to be, that is the question\n").unwrap();
+        assert_eq!(info.staleness().unwrap(), Staleness::Changed);
+        std::fs::write(&path, "to be or not to go\n").unwrap();
+        assert_eq!(touched.staleness().unwrap(), Staleness::Changed);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(info.staleness().unwrap(), Staleness::Missing);
+    }
+}
@@ -1,20 +1,36 @@
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::OnceLock;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
 use std::mem;
-use std::{time::{Instant, Duration}, 
+use std::ops::{Bound, Range};
+use std::{time::{Instant, Duration, SystemTime}, 
         io::{Write, stdout}};
-use crate::time_aux::saved_duration;
-use crate::type_aux::*;
+use crate::levenshtein::{DamLevAlgorithm, EditCosts};
+use crate::bktree::{self, BkTree};
+use crate::symspell::{SymSpell, SymSpellConfig};
+use crate::phonetic::{PhoneticAlgorithm, PhoneticIndex};
+use crate::fst::Fst;
+use crate::frozen::FrozenWordIndex;
+use crate::postings::CompressedPostings;
+use crate::memory::{btree_node_estimate, MemoryUsage};
+use crate::char_aux::{fold_str, prefix_range};
+use crate::trie::CompletionTrie;
+use crate::ngram::NGramModel;
+use crate::collocation::{cooccurrences, AssociationMeasure, Collocation};
+use crate::stats::{CorpusStats, GROWTH_SAMPLE_INTERVAL};
+use crate::export::{export_to_file, read_binary_file, read_json_file, ExportFormat, ExportSource, LoadedIndex};
+use crate::adapter::{RecordReader, SourceFormat};
+use crate::boundary::{group_records, RecordBoundary};
+use crate::section::{SectionRules, SectionTable};
+use crate::query::{idf, term_score, FieldStats, Query, QueryHit};
+use crate::header::{check_tokenizer, crate_version, unix_seconds, IndexHeader, SourceInfo, Staleness, TokenizerConfig, FORMAT_VERSION};
 use serde::{Deserialize, Serialize};
-use serde_json::Result;
 
-// for the gz-encryption
-use std::io::BufWriter;
-use flate2::write::GzEncoder;
-use flate2::Compression;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 
 //mod super::levenshtein;
 
@@ -22,93 +38,764 @@ pub struct WordIndex {
     bt: BTreeMap<String, Vec<WordLoc>>,
     pub duration: Duration,
     pub record_count: usize,
-    pub word_count: usize
+    pub word_count: usize,
+    #[serde(default)]
+    options: IndexOptions,
+    #[serde(default)]
+    source: Option<SourceInfo>,  // set when the index is built via 'build_index_from_file'
+    #[serde(default)]
+    built_at: u64,  // seconds since the Unix epoch
+    #[serde(default)]
+    tokenizer: TokenizerConfig,  // the tokenizer that built the index, as stored in the header of a loaded index
+    #[serde(default = "crate_version")]
+    crate_version: String,  // the version of text_index that built the index
+    #[serde(default)]
+    fields: Vec<String>,  // names of the fields of the records (see adapter.rs), empty for plain text
+    #[serde(default)]
+    sections: SectionTable,  // the sections detected by 'IndexOptions::sections' (see section.rs)
+    #[serde(default)]
+    deleted: BTreeSet<u32>,  // tombstones: records that are deleted but whose locations are still in 'bt' until 'purge'
+    #[serde(default)]
+    hidden: HashMap<String, usize>,  // number of locations of a word in deleted records
+    #[serde(default)]
+    purged: BTreeSet<u32>,  // records that were deleted and purged: they keep their number, but have no locations
+    #[serde(skip)]
+    field_counts: Vec<FieldCounts>,  // per field, counted when the index is built or loaded and kept up to date by the updates
+    #[serde(default)]
+    vocabulary_growth: Vec<(usize, usize)>,  // (word_count, number of distinct words), sampled every GROWTH_SAMPLE_INTERVAL records
+    #[serde(default, with = "bktree::lazy")]
+    bk_tree: OnceLock<BkTree>,  // built on the first fuzzy query via 'bk_tree()'
+    #[serde(skip)]
+    symspell: OnceLock<SymSpell>,  // built on first use via 'symspell_with()'
+    #[serde(skip)]
+    phonetic: Option<PhoneticIndex>,  // optional, built via 'build_phonetic_index()'
+    #[serde(skip)]
+    folded: OnceLock<BTreeMap<String, Vec<String>>>,  // folded form -> surface variants, built on first use
+    #[serde(skip)]
+    trie: OnceLock<CompletionTrie>,  // completion trie with cached top-k per node, built on first use via 'completion_trie()'
+    #[serde(default)]
+    ngrams: Option<NGramModel>  // bigram and trigram counts, collected when 'IndexOptions::ngrams' is set
 }
 
 
-fn write_to_file(filename: &str, data: &str) {
-    let start = Instant::now();
-    let mut file = File::create(filename).unwrap();
-
-    // Write a &str in the file (ignoring the result).
-    writeln!(&mut file, "{}", data).unwrap();
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexOptions {
+    pub ngrams: bool,  // collect bigram and trigram counts for 'find_next_words'
+    pub format: SourceFormat,  // how the source is split into records and fields
+    pub records: RecordBoundary,  // the unit of text that is a record: lines, paragraphs, sentences, ... (see boundary.rs)
+    pub sections: SectionRules,  // the headings that start sections, e.g. acts and scenes (see section.rs)
+    #[serde(skip)]
+    pub quiet: bool    // no progress output while building or refreshing, e.g. when a background thread refreshes the index
+}
 
-    let duration = start.elapsed();
-    println!("Time elapsed to write {}: {:?}\n", filename, duration);
 
+// the counts of a field behind 'FieldStats'
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct FieldCounts {
+    records: usize,
+    tokens: usize,
+    distinct_words: usize
 }
 
 
-fn write_to_gz_file(filename: &str, data: &str) {
-    let start = Instant::now();
-    //let mut file = BufWriter::new(File::create(filename).unwrap());
-    let mut file = File::create(filename).unwrap();
+// number of completions cached per node of the completion trie
+pub const TRIE_TOP_K: usize = 10;
 
-    let mut encoder = GzEncoder::new(file, Compression::default());
-    let res = encoder.write_all(data.as_bytes());
-    
-    let duration = start.elapsed();
-    println!("Time elapsed to write {}: {:?} with results {:?}\n", filename, duration, res);
+// collocates that co-occur less often are dropped by 'collocations', as the measures are unreliable for very small counts
+pub const MIN_COLLOCATION_FREQ: usize = 3;
 
-}
 
 impl WordIndex {
     pub fn len(&self) -> usize {
         self.bt.len()
     }
 
-    pub fn find_matches(&self, search_str: &str) -> Option<&Vec<WordLoc>> {
-        self.bt.get(search_str)
+    pub fn is_empty(&self) -> bool {
+        self.bt.is_empty()
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        // Breakdown of the heap memory used by the index (see memory.rs).
+        let mut usage = MemoryUsage{ tree_nodes: btree_node_estimate::<String, Vec<WordLoc>>(self.bt.len()), ..Default::default() };
+        for (k, v) in self.bt.iter() {
+            usage.keys += k.capacity();
+            usage.postings += v.len() * mem::size_of::<WordLoc>();
+            usage.postings_spare += (v.capacity() - v.len()) * mem::size_of::<WordLoc>();
+        }
+        usage.auxiliary = self.bk_tree.get().map_or(0, |t| t.heap_size())
+            + self.symspell.get().map_or(0, |s| s.heap_size())
+            + self.phonetic.as_ref().map_or(0, |p| p.heap_size())
+            + self.folded.get().map_or(0, |f| btree_node_estimate::<String, Vec<String>>(f.len())
+                + f.iter().map(|(k, v)| k.capacity() + v.capacity() * mem::size_of::<String>() + v.iter().map(|w| w.capacity()).sum::<usize>()).sum::<usize>())
+            + self.trie.get().map_or(0, |t| t.heap_size())
+            + self.ngrams.as_ref().map_or(0, |n| n.heap_size())
+            + self.sections.heap_size();
+        usage
+    }
+
+    pub fn stats(&self) -> CorpusStats {
+        // The corpus statistics of the indexed text (see stats.rs). The growth curve ends with the current size of the index.
+        let mut growth = self.vocabulary_growth.clone();
+        if growth.last().is_none_or(|&(n, _)| n < self.word_count) {
+            growth.push((self.word_count, self.bt.len()));
+        }
+        CorpusStats::new(self.live_counts(), self.live_record_count(), growth, self.memory_usage())
+    }
+
+    pub fn find_matches(&self, search_str: &str) -> Option<Cow<'_, Vec<WordLoc>>> {
+        // The locations of the word, None when it does not occur (outside of deleted records). Only the locations of a word
+        // that occurs in a deleted record are copied.
+        let locs = self.bt.get(search_str)?;
+        if !self.hidden.contains_key(search_str) {
+            return Some(Cow::Borrowed(locs));
+        }
+        let live: Vec<WordLoc> = locs.iter().filter(|loc| !self.deleted.contains(&loc.line)).copied().collect();
+        (!live.is_empty()).then_some(Cow::Owned(live))
+    }
+
+    fn live_count(&self, word: &str, locs: &[WordLoc]) -> usize {
+        // the number of locations of the word outside of deleted records
+        if self.hidden.is_empty() { locs.len() } else { locs.len() - self.hidden.get(word).copied().unwrap_or(0) }
+    }
+
+    fn live_entry(&self, word: &str) -> Option<(&String, usize)> {
+        self.bt.get_key_value(word).map(|(w, locs)| (w, self.live_count(w, locs))).filter(|&(_, count)| count > 0)
+    }
+
+    fn live_counts(&self) -> impl Iterator<Item = (&String, usize)> {
+        // the words with their number of locations, without the words that only occur in deleted records
+        self.bt.iter().map(|(w, locs)| (w, self.live_count(w, locs))).filter(|&(_, count)| count > 0)
+    }
+
+    pub fn is_deleted(&self, record: u32) -> bool {
+        // whether the record was deleted, purged or not
+        self.deleted.contains(&record) || self.purged.contains(&record)
+    }
+
+    pub fn live_record_count(&self) -> usize {
+        // the number of records that are not deleted
+        self.record_count - self.deleted.len() - self.purged.len()
+    }
+
+    pub fn deleted_count(&self) -> usize {
+        // the number of records that are deleted, but not purged yet
+        self.deleted.len()
     }
 
-    pub fn save_index(self) {
+    pub fn delete_record(&mut self, record: u32) -> bool {
+        // Mark the record as deleted. Its locations are hidden from the queries and counts right away, but stay in the
+        // posting vectors until 'purge'. Its bigrams and trigrams are subtracted from the n-grams. The postings are sorted by record, so the locations of the record are found by a
+        // binary search per word. Returns false when the record does not exist or is already deleted.
+        if record as usize >= self.record_count || self.purged.contains(&record) || !self.deleted.insert(record) {
+            return false;
+        }
+        let mut removed = 0;
+        let mut words: Vec<(u16, u16, &str)> = Vec::new();  // (field, position, word) of the record
+        for (word, locs) in self.bt.iter() {
+            let start = locs.partition_point(|loc| loc.line < record);
+            let count = locs[start..].partition_point(|loc| loc.line == record);
+            if count == 0 {
+                continue;
+            }
+            words.extend(locs[start..start + count].iter().map(|loc| (loc.field, loc.word, word.as_str())));
+            let hidden = self.hidden.entry(word.clone()).or_default();
+            *hidden += count;
+            removed += count;
+            if let Some(trie) = self.trie.get_mut() {
+                trie.set_count(word, locs.len() - *hidden);
+            }
+        }
+        words.sort_unstable();
+        for field in words.chunk_by(|a, b| a.0 == b.0) {
+            let mut field_words: Vec<&str> = field.iter().map(|&(_, _, word)| word).collect();
+            if let Some(ngrams) = self.ngrams.as_mut() {
+                // the n-grams were counted per field, in the order of the words
+                ngrams.remove_line(&field_words);
+            }
+            let field_idx = field[0].0;
+            field_words.sort_unstable();
+            field_words.dedup();
+            let distinct_gone = field_words.iter().filter(|word| !self.occurs_in_field(word, field_idx)).count();
+            let counts = &mut self.field_counts[field_idx as usize];
+            counts.records -= 1;
+            counts.tokens -= field.len();
+            counts.distinct_words -= distinct_gone;
+        }
+        self.word_count -= removed;
+        self.symspell.take();
+        true
+    }
+
+    pub fn update_record_fields<S: AsRef<str>>(&mut self, record: u32, fields: &[S]) -> Option<u32> {
+        // Replace a record. The record keeps its number, and so its section and its line in the source: the old version is
+        // deleted, its locations are removed from the posting vectors and the new version is inserted at the position of
+        // the record. Returns the number of the record, None when the record does not exist or is deleted.
+        if !self.delete_record(record) {
+            return None;
+        }
+        let mut removed_words = false;
+        for (word, hidden) in self.hidden.iter_mut() {
+            let Some(locs) = self.bt.get_mut(word) else { continue };
+            let start = locs.partition_point(|loc| loc.line < record);
+            let count = locs[start..].partition_point(|loc| loc.line == record);
+            locs.drain(start..start + count);
+            *hidden -= count;
+            if locs.is_empty() {
+                self.bt.remove(word);
+                removed_words = true;
+            }
+        }
+        self.hidden.retain(|_, hidden| *hidden > 0);
+        self.deleted.remove(&record);
+        if removed_words {
+            self.vocabulary_changed();
+        }
+        self.insert_record_fields(record, fields);
+        Some(record)
+    }
+
+    fn vocabulary_changed(&mut self) {
+        // words were removed: the structures over the vocabulary still contain them
+        self.bk_tree.take();
+        self.symspell.take();
+        self.folded.take();
+        self.trie.take();
+        if let Some(algorithm) = self.phonetic.as_ref().map(|p| p.algorithm()) {
+            self.phonetic = Some(PhoneticIndex::build(self.bt.keys(), algorithm));
+        }
+    }
+
+    pub fn purge(&mut self) -> usize {
+        // Remove the locations of the deleted records from the posting vectors and the words that only occurred in them
+        // from the vocabulary. The records keep their numbers and stay deleted. Returns the number of removed locations.
+        if self.deleted.is_empty() {
+            return 0;
+        }
         let start = Instant::now();
-        let wc: Vec<WordCount> = self.bt.iter().map(|(k, v)| WordCount{word: k.clone(), count: v.len()}).collect();
-        let json = serde_json::to_string(&wc).unwrap();
-        let duration = start.elapsed();
-        println!("Time elapsed for computing json of WordCount {:?}\n", duration);
+        let mut removed = 0;
+        let mut removed_words = false;
+        for word in self.hidden.keys() {
+            let Some(locs) = self.bt.get_mut(word) else { continue };
+            let before = locs.len();
+            locs.retain(|loc| !self.deleted.contains(&loc.line));
+            removed += before - locs.len();
+            if locs.is_empty() {
+                self.bt.remove(word);
+                removed_words = true;
+            }
+        }
+        if removed_words {
+            self.vocabulary_changed();
+        }
+        self.purged.append(&mut self.deleted);
+        self.hidden.clear();
+        if !self.options.quiet {
+            println!("Time elapsed to purge {removed} locations of deleted records: {:?}", start.elapsed());
+        }
+        removed
+    }
 
-        let filename = "index_wc.json";
-        write_to_file(filename, &json);
-        write_to_gz_file(&(filename.to_owned()+".gz"), &json);
+    pub fn freeze(mut self) -> FrozenWordIndex {
+        // Turn the finished index into a read-only index with an Fst as vocabulary. Deleted records are purged first.
+        self.purge();
+        let start = Instant::now();
+        let fst = Fst::from_sorted_keys(self.bt.keys().map(|k| k.as_str()));
+        let postings: Vec<CompressedPostings> = self.bt.values().map(|locs| CompressedPostings::from_slice(locs)).collect();
+        println!("Time elapsed to freeze the index into an automaton with {} states: {:?}", fst.num_states(), start.elapsed());
+        FrozenWordIndex::new(fst, postings, self.header())
+    }
+
+    pub fn header(&self) -> IndexHeader {
+        IndexHeader{
+            format_version: FORMAT_VERSION,
+            crate_version: self.crate_version.clone(),
+            source: self.source.clone(),
+            tokenizer: self.tokenizer.clone(),
+            options: self.options.clone(),
+            record_count: self.record_count,
+            word_count: self.word_count,
+            build_duration: self.duration,
+            built_at: self.built_at,
+            fields: self.fields.clone(),
+            sections: self.sections.clone(),
+            // the locations of deleted records are not persisted, so a loaded index has them as removed
+            removed: self.purged.union(&self.deleted).copied().collect()
+        }
+    }
+
+    pub fn fields(&self) -> &[String] {
+        // the names of the fields of the records, indexed by 'WordLoc::field'; empty for plain text
+        &self.fields
+    }
+
+    pub fn field_name(&self, loc: &WordLoc) -> Option<&str> {
+        self.fields.get(loc.field as usize).map(|f| f.as_str())
+    }
+
+    pub fn sections(&self) -> &SectionTable {
+        &self.sections
+    }
+
+    pub fn section_path(&self, loc: &WordLoc) -> Option<String> {
+        // the path of the innermost section of the location, e.g. "Hamlet › Act III › Scene 1"
+        self.sections.section_of(loc.line).map(|section| self.sections.path(section))
+    }
+
+    pub fn section_facets(&self, search_str: &str, level: Option<u8>) -> Vec<(Option<String>, usize)> {
+        // The number of occurrences of the word per section at 'level' (the innermost sections when None) as path and
+        // count, most occurrences first. The occurrences outside of a section at that level are counted for None.
+        let locs = self.find_matches(search_str).unwrap_or_default();
+        self.sections.facets(&locs, level).into_iter().map(|(section, count)| (section.map(|s| self.sections.path(s)), count)).collect()
+    }
+
+    pub fn find_matches_in_section(&self, search_str: &str, section: &str) -> Vec<WordLoc> {
+        // the occurrences of the word in the sections whose path matches 'section' (see 'SectionTable::matches')
+        let locs = self.find_matches(search_str).unwrap_or_default();
+        locs.iter().filter(|loc| self.sections.section_of(loc.line).is_some_and(|s| self.sections.matches(s, section))).copied().collect()
+    }
+
+    pub fn field_id(&self, name: &str) -> Result<u16, String> {
+        match self.fields.iter().position(|f| f == name) {
+            Some(idx) => Ok(idx as u16),
+            None if self.fields.is_empty() => Err(format!("the index has no fields, so '{name}:' can not be used")),
+            None => Err(format!("unknown field '{name}' (the fields are {})", self.fields.join(", ")))
+        }
+    }
+
+    pub fn field_stats(&self) -> Vec<FieldStats> {
+        // The statistics of every field, indexed by 'WordLoc::field'. The counts are kept up to date, so this is cheap.
+        (0..self.fields.len().max(self.field_counts.len()).max(1)).map(|idx| {
+            let counts = self.field_counts.get(idx).copied().unwrap_or_default();
+            FieldStats{ name: self.fields.get(idx).cloned().unwrap_or_default(), records: counts.records, tokens: counts.tokens,
+                distinct_words: counts.distinct_words, avg_length: if counts.records > 0 { counts.tokens as f64 / counts.records as f64 } else { 0.0 } }
+        }).collect()
+    }
+
+    fn count_fields(&self) -> Vec<FieldCounts> {
+        // The counts of every field from the postings, so the cost is linear in the number of words of the index.
+        let num_fields = self.fields.len().max(1);
+        let mut counts = vec![FieldCounts::default(); num_fields];
+        let mut seen = vec![vec![false; self.record_count]; num_fields];
+        let mut in_field = vec![false; num_fields];
+        for locs in self.bt.values() {
+            in_field.fill(false);
+            for loc in locs.iter().filter(|loc| !self.deleted.contains(&loc.line)) {
+                let field = loc.field as usize;
+                counts[field].tokens += 1;
+                in_field[field] = true;
+                if !seen[field][loc.line as usize] {
+                    seen[field][loc.line as usize] = true;
+                    counts[field].records += 1;
+                }
+            }
+            for (field, found) in in_field.iter().enumerate() {
+                counts[field].distinct_words += *found as usize;
+            }
+        }
+        counts
+    }
+
+    fn with_field_counts(mut self) -> Self {
+        self.field_counts = self.count_fields();
+        self
+    }
+
+    fn occurs_in_field(&self, word: &str, field: u16) -> bool {
+        // whether the word has a location in the field outside of deleted records; for a single field the count tells
+        let Some(locs) = self.bt.get(word) else { return false };
+        if self.fields.len() <= 1 {
+            self.live_count(word, locs) > 0
+        } else {
+            locs.iter().any(|loc| loc.field == field && !self.deleted.contains(&loc.line))
+        }
+    }
+
+    pub fn term_locs(&self, field: Option<&str>, word: &str) -> Result<Vec<WordLoc>, String> {
+        // the locations of the word, only those in 'field' when it is given
+        let field = field.map(|name| self.field_id(name)).transpose()?;
+        let locs = self.find_matches(word).unwrap_or_default();
+        Ok(locs.iter().filter(|loc| field.is_none_or(|f| loc.field == f)).copied().collect())
+    }
+
+    pub fn search(&self, query: &Query) -> Result<Vec<QueryHit>, String> {
+        // The records that match the query (see query.rs), best first. Fails when the query uses an unknown field.
+        let mut hits: Vec<QueryHit> = self.evaluate(query)?.into_iter()
+            .map(|(record, mut locs)| {
+                locs.sort();
+                locs.dedup();
+                QueryHit{ record, score: 0.0, locs }
+            })
+            .collect();
+        let stats = self.field_stats();
+        for (field, word) in query.terms() {
+            // the score of the term in every field of every record it occurs in
+            let mut counts: BTreeMap<(u32, u16), usize> = BTreeMap::new();
+            for loc in self.term_locs(field, word)? {
+                *counts.entry((loc.line, loc.field)).or_default() += 1;
+            }
+            let mut records_with_word = vec![0; stats.len()];
+            for &(_, field) in counts.keys() {
+                records_with_word[field as usize] += 1;
+            }
+            for hit in hits.iter_mut() {
+                for (&(_, field), &tf) in counts.range((hit.record, 0)..=(hit.record, u16::MAX)) {
+                    let field = field as usize;
+                    hit.score += term_score(tf, idf(records_with_word[field], stats[field].records));
+                }
+            }
+        }
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.record.cmp(&b.record)));
+        Ok(hits)
+    }
+
+    fn evaluate(&self, query: &Query) -> Result<BTreeMap<u32, Vec<WordLoc>>, String> {
+        // the matching records with the locations of the terms that matched
+        Ok(match query {
+            Query::Term{ field, word } => {
+                let mut records: BTreeMap<u32, Vec<WordLoc>> = BTreeMap::new();
+                for loc in self.term_locs(field.as_deref(), word)? {
+                    records.entry(loc.line).or_default().push(loc);
+                }
+                records
+            },
+            Query::And(parts) => {
+                // the negated parts only remove records
+                let (negated, positive): (Vec<&Query>, Vec<&Query>) = parts.iter().partition(|p| matches!(p, Query::Not(_)));
+                let mut records = match positive.split_first() {
+                    Some((first, rest)) => {
+                        let mut records = self.evaluate(first)?;
+                        for part in rest {
+                            let other = self.evaluate(part)?;
+                            records.retain(|record, _| other.contains_key(record));
+                            for (record, locs) in other {
+                                if let Some(found) = records.get_mut(&record) {
+                                    found.extend(locs);
+                                }
+                            }
+                        }
+                        records
+                    },
+                    None => self.all_records()
+                };
+                for part in negated {
+                    let Query::Not(inner) = part else { unreachable!() };
+                    let excluded = self.evaluate(inner)?;
+                    records.retain(|record, _| !excluded.contains_key(record));
+                }
+                records
+            },
+            Query::Or(parts) => {
+                let mut records: BTreeMap<u32, Vec<WordLoc>> = BTreeMap::new();
+                for part in parts {
+                    for (record, locs) in self.evaluate(part)? {
+                        records.entry(record).or_default().extend(locs);
+                    }
+                }
+                records
+            },
+            Query::Not(inner) => {
+                let excluded = self.evaluate(inner)?;
+                let mut records = self.all_records();
+                records.retain(|record, _| !excluded.contains_key(record));
+                records
+            }
+        })
+    }
+
+    fn all_records(&self) -> BTreeMap<u32, Vec<WordLoc>> {
+        (0..self.record_count as u32).filter(|&record| !self.is_deleted(record)).map(|record| (record, Vec::new())).collect()
+    }
+
+    pub fn find_field_completions(&self, field: &str, check_word: &str, num_completions: usize) -> Result<CompletionsRec, String> {
+        // The 'num_completions' completions that are most common in 'field', counting only the occurrences in that field.
+        let field = self.field_id(field)?;
+        let (begin, end) = prefix_range(check_word);
 
-        // now as tuples with word-count only
         let start = Instant::now();
-        let wc: Vec<_> = self.bt.iter().map(|(k, v)| (k.clone(), v.len())).collect();
-        let json = serde_json::to_string(&wc).unwrap();
-        let duration = start.elapsed();
-        println!("Time elapsed for computing json of WordCount {:?}\n", duration);
+        let mut completions_rec = self.bt
+            .range::<str, _>((begin, end.as_ref().map(|s| s.as_str())))
+            .map(|(word, locs)| (word, locs.iter().filter(|loc| loc.field == field && !self.deleted.contains(&loc.line)).count()))
+            .filter(|&(_, count)| count > 0)
+            .fold(CompletionsRec::new(num_completions), top_completions);
+        completions_rec.duration = start.elapsed();
+
+        Ok(completions_rec)
+    }
 
-        let filename = "index_wc_tuple.json";
-        write_to_file(filename, &json);
-        write_to_gz_file(&(filename.to_owned()+".gz"), &json);
+    pub fn set_quiet(&mut self, quiet: bool) {
+        // suppress the progress output of later refreshes and lazily built structures (see 'IndexOptions::quiet')
+        self.options.quiet = quiet;
+    }
 
+    pub fn staleness(&self) -> io::Result<Staleness> {
+        // whether the source file changed after the index was built (see 'SourceInfo::staleness')
+        match &self.source {
+            Some(source) => source.staleness(),
+            None => Ok(Staleness::Unknown)
+        }
+    }
+
+    pub fn refresh_from_source(&mut self) -> io::Result<Staleness> {
+        // Bring the index up to date with its source: lines appended to the source are added as new records, any other
+        // change rebuilds the index with the same options. When the records are not lines the last record may continue
+        // in the appended lines, so then the index is rebuilt as well. Returns the staleness that was found.
+        let staleness = self.staleness()?;
+        let Some(source) = self.source.clone() else { return Ok(staleness) };
+        match staleness {
+            Staleness::Fresh | Staleness::Missing | Staleness::Unknown => (),
+            Staleness::Appended if self.can_append() => {
+                let start = Instant::now();
+                let old_records = self.record_count;
+                let mut records = RecordReader::new(BufReader::new(File::open(&source.path)?), &self.options.format).with_sections(&self.options.sections.0)?;
+                for record in records.by_ref().skip(old_records) {
+                    let record = record?;
+                    for heading in &record.headings {
+                        self.sections.start(self.record_count as u32, heading);
+                    }
+                    self.add_record_fields(&record.fields);
+                }
+                self.fields = records.field_names().to_vec();
+                self.source = Some(SourceInfo::from_path(&source.path)?);
+                if !self.options.quiet {
+                    println!("Time elapsed to add {} appended lines to the index: {:?}", self.record_count - old_records, start.elapsed());
+                }
+            },
+            Staleness::Appended | Staleness::Changed => *self = self.rebuilt_from_source()?
+        }
+        Ok(staleness)
+    }
 
-        // now as tuples with full index
+    pub fn can_append(&self) -> bool {
+        // whether lines appended to the source can be added as new records (see 'refresh_from_source')
+        self.options.records == RecordBoundary::Line
+    }
+
+    pub fn rebuilt_from_source(&self) -> io::Result<WordIndex> {
+        // a new index of the source with the same options, and a phonetic index when this index has one
+        let Some(source) = &self.source else { return Err(io::Error::new(io::ErrorKind::NotFound, "the index has no source")) };
+        let mut word_index = WordIndex::build_index_from_file(&source.path, self.options.clone())?;
+        if let Some(algorithm) = self.phonetic.as_ref().map(|p| p.algorithm()) {
+            word_index.build_phonetic_index(algorithm);
+        }
+        Ok(word_index)
+    }
+
+    pub fn export<P: AsRef<Path>>(&self, path: P, format: ExportFormat, compress: bool) -> io::Result<()> {
+        // Write the index to 'path' in 'format', gzip-compressed when 'compress' is set (see export.rs).
         let start = Instant::now();
-        let wc: Vec<_> = self.bt.iter().map(|(k, v)| (k.clone(), v.iter().map(|wl| WordLocTuple(wl.line, wl.word)).collect::<Vec<WordLocTuple>>())).collect();
-        let json = serde_json::to_string(&wc).unwrap();
-        let duration = start.elapsed();
-        println!("Time elapsed for computing json of WordCount {:?}\n", duration);
+        if self.deleted.is_empty() {
+            export_to_file(&self.export_source(&self.bt), path.as_ref(), format, compress)?;
+        } else {
+            // the locations of deleted records are not written
+            let live: BTreeMap<String, Vec<WordLoc>> = self.live_counts().filter_map(|(word, _)| self.find_matches(word).map(|locs| (word.clone(), locs.into_owned()))).collect();
+            export_to_file(&self.export_source(&live), path.as_ref(), format, compress)?;
+        }
+        println!("Time elapsed to export the index to {}: {:?}", path.as_ref().display(), start.elapsed());
+        Ok(())
+    }
 
-        let filename = "index_tuple.json";
-        write_to_file(filename, &json);
-        write_to_gz_file(&(filename.to_owned()+".gz"), &json);
+    fn export_source<'a>(&'a self, bt: &'a BTreeMap<String, Vec<WordLoc>>) -> ExportSource<'a> {
+        ExportSource{
+            header: self.header(),
+            bt,
+            duration: self.duration,
+            record_count: self.record_count,
+            word_count: self.word_count,
+            vocabulary_growth: &self.vocabulary_growth,
+            ngrams: self.ngrams.as_ref(),
+            bk_tree: self.bk_tree.get().filter(|_| self.deleted.is_empty())  // the tree may contain words of deleted records
+        }
+    }
 
-        let start = Instant::now();   
-        let wl: WordLocations = self.bt.into_iter().map(|(k, v)| WordLocationsEntry{word: k, locations: v}).collect();
-        let json = serde_json::to_string(&wl).unwrap();
+    pub fn load_binary<P: AsRef<Path>>(path: P) -> io::Result<WordIndex> {
+        // Read an index that was exported in the binary format, including the n-grams and the BK-tree when present.
+        let start = Instant::now();
+        let word_index = WordIndex::from_loaded(read_binary_file(path.as_ref())?)?;
+        println!("Time elapsed to load the index from {}: {:?}", path.as_ref().display(), start.elapsed());
+        Ok(word_index)
+    }
+
+    pub fn load_json<P: AsRef<Path>>(path: P) -> io::Result<WordIndex> {
+        // Read an index exported as postings JSON or JSON Lines (optionally gzipped). The headerless JSON array written by
+        // older versions is migrated.
+        let start = Instant::now();
+        let word_index = WordIndex::from_loaded(read_json_file(path.as_ref())?)?;
+        println!("Time elapsed to load the index from {}: {:?}", path.as_ref().display(), start.elapsed());
+        Ok(word_index)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<WordIndex> {
+        // Read a saved index in the format that matches the extension of 'path', binary when it has no known extension.
+        match ExportFormat::from_path(path.as_ref()) {
+            Some(ExportFormat::Binary) | None => WordIndex::load_binary(path),
+            _ => WordIndex::load_json(path)
+        }
+    }
+
+    pub fn merge(indexes: Vec<WordIndex>) -> Result<WordIndex, String> {
+        // Combine the indexes of consecutive parts of a text into the index of the whole text without reading the text.
+        // The records of an index follow the records of the indexes before it, so their numbers are shifted by the number of
+        // records before them and the posting lists are concatenated in order. Fields are matched by name. The counts, the
+        // vocabulary growth, the sections and the n-grams (when all indexes have them) are recomputed, so the result equals
+        // the index built over the concatenated text, as long as the records are lines: a paragraph or sentence does not
+        // continue in the next part. The indexes must have been built with the same source format, record boundary, section
+        // rules and tokenizer. Deleted records are purged first. The merged index has no source.
+        let start = Instant::now();
+        let Some(first) = indexes.first() else { return Err("there are no indexes to merge".to_string()) };
+        let plain_text = first.fields.is_empty();
+        let options = IndexOptions{ ngrams: indexes.iter().all(|index| index.ngrams.is_some()), ..first.options.clone() };
+        let tokenizer = first.tokenizer.clone();
+        let mut bt: BTreeMap<String, Vec<WordLoc>> = BTreeMap::new();
+        let mut fields: Vec<String> = Vec::new();
+        let mut sections = SectionTable::default();
+        let mut purged = BTreeSet::new();
+        let mut ngrams = options.ngrams.then(NGramModel::new);
+        let mut record_count = 0;
+        let mut word_count = 0;
+        for mut index in indexes {
+            if index.fields.is_empty() != plain_text {
+                return Err("an index of plain text can not be merged with an index of records with fields".to_string());
+            }
+            // a merged index has to equal a single build, so the records and words have to be found the same way
+            let mismatch = [("source format", index.options.format != options.format), ("record boundary", index.options.records != options.records),
+                ("section rules", index.options.sections != options.sections), ("tokenizer", index.tokenizer != tokenizer)];
+            if let Some((setting, _)) = mismatch.iter().find(|(_, differs)| *differs) {
+                return Err(format!("the indexes were built with a different {setting} and can not be merged"));
+            }
+            if record_count + index.record_count > u32::MAX as usize {
+                return Err(format!("the merged index would have more than {} records", u32::MAX));
+            }
+            index.purge();
+            // the field of every field of the index in the merged index, empty for plain text
+            let field_map: Vec<u16> = index.fields.iter().map(|name| match fields.iter().position(|f| f == name) {
+                Some(idx) => idx as u16,
+                None => {
+                    fields.push(name.clone());
+                    fields.len() as u16 - 1
+                }
+            }).collect();
+            let offset = record_count as u32;
+            let identity = field_map.iter().enumerate().all(|(idx, &field)| idx == field as usize);
+            for (word, locs) in index.bt {
+                let mut block: Vec<WordLoc> = locs.into_iter()
+                    .map(|loc| WordLoc{ line: loc.line + offset, field: field_map.get(loc.field as usize).copied().unwrap_or(loc.field), word: loc.word })
+                    .collect();
+                if !identity {
+                    // the locations of a record are ordered by their new field
+                    block.sort_unstable();
+                }
+                bt.entry(word).or_default().extend(block);
+            }
+            sections.append(&index.sections, offset);
+            purged.extend(index.purged.iter().map(|record| record + offset));
+            if let (Some(merged), Some(other)) = (ngrams.as_mut(), index.ngrams) {
+                merged.merge(other);
+            }
+            record_count += index.record_count;
+            word_count += index.word_count;
+        }
+        let vocabulary_growth = vocabulary_growth_of(&bt, record_count);
         let duration = start.elapsed();
-        println!("Time elapsed for computing json {:?}\n", duration);
+        if !options.quiet {
+            println!("Time elapsed to merge the indexes into an index with {record_count} records and {word_count} words: {duration:?}");
+        }
+        Ok(WordIndex{bt, duration, record_count, word_count, options, source: None, built_at: unix_seconds(SystemTime::now()), tokenizer, crate_version: crate_version(), fields, sections,
+            deleted: BTreeSet::new(), hidden: HashMap::new(), purged, field_counts: Vec::new(), vocabulary_growth, bk_tree: OnceLock::new(), symspell: OnceLock::new(), phonetic: None,
+            folded: OnceLock::new(), trie: OnceLock::new(), ngrams}.with_field_counts())
+    }
+
+    pub(crate) fn shard(&self, words: (Bound<&str>, Bound<&str>), records: Range<u32>) -> WordIndex {
+        // The part of the index with the words in 'words' and the locations in 'records' (see shard.rs). The records keep
+        // their numbers, so the shard has 'records.end' records of which the ones before 'records.start' are empty. The
+        // locations of deleted records are left out. A shard has no n-grams.
+        let mut bt = BTreeMap::new();
+        let mut word_count = 0;
+        for (word, locs) in self.bt.range::<str, _>(words) {
+            let start = locs.partition_point(|loc| loc.line < records.start);
+            let end = locs.partition_point(|loc| loc.line < records.end);
+            let part: Vec<WordLoc> = locs[start..end].iter().filter(|loc| !self.deleted.contains(&loc.line)).copied().collect();
+            if !part.is_empty() {
+                word_count += part.len();
+                bt.insert(word.clone(), part);
+            }
+        }
+        let record_count = records.end as usize;
+        let vocabulary_growth = vocabulary_growth_of(&bt, record_count);
+        let purged = self.purged.union(&self.deleted).copied().filter(|record| records.contains(record)).collect();
+        WordIndex{bt, duration: self.duration, record_count, word_count, options: IndexOptions{ngrams: false, ..self.options.clone()}, source: self.source.clone(),
+            built_at: self.built_at, tokenizer: self.tokenizer.clone(), crate_version: self.crate_version.clone(), fields: self.fields.clone(), sections: self.sections.clone(), deleted: BTreeSet::new(), hidden: HashMap::new(), purged, field_counts: Vec::new(), vocabulary_growth,
+            bk_tree: OnceLock::new(), symspell: OnceLock::new(), phonetic: None, folded: OnceLock::new(), trie: OnceLock::new(), ngrams: None}.with_field_counts()
+    }
+
+    pub fn prefix_counts(&self, prefix: &str) -> Vec<(&str, usize)> {
+        // all words that start with 'prefix' with their counts, in the order of the words (the input of 'find_completions')
+        let (begin, end) = prefix_range(prefix);
+        self.bt.range::<str, _>((begin, end.as_ref().map(|s| s.as_str())))
+            .map(|(word, locs)| (word.as_str(), self.live_count(word, locs)))
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    pub fn dl_prefix_counts(&self, check_word: &str, max_dist: usize) -> Vec<(&str, usize)> {
+        // all words within prefix distance 'max_dist' that do not start with 'check_word', with their counts, in the order of
+        // the words (the input of 'find_dl_completions')
+        use crate::levenshtein::dam_lev_prefix_with;
+
+        self.live_counts()
+            .filter(|&(s, _)| !s.starts_with(check_word))
+            .filter(|&(s, _)| dam_lev_prefix_with(check_word, s, max_dist, DamLevAlgorithm::default()).is_some())
+            .map(|(s, count)| (s.as_str(), count))
+            .collect()
+    }
+
+    fn from_loaded(contents: LoadedIndex) -> io::Result<WordIndex> {
+        // The index of a loaded file, which keeps the tokenizer and the version of text_index from its header. A file that
+        // was built with another tokenizer is rejected.
+        let header = match contents.header {
+            Some(header) => header.migrated(),
+            None => {
+                println!("Migrating an index without header (format version 1) to format version {FORMAT_VERSION}");
+                IndexHeader::for_version_1(contents.record_count, contents.word_count, contents.duration,
+                    IndexOptions{ngrams: contents.ngrams.is_some(), ..Default::default()})
+            }
+        };
+        check_tokenizer(&header.tokenizer)?;
+        let purged = header.removed;
+        let bk_tree = OnceLock::new();
+        if let Some(tree) = contents.bk_tree {
+            let _ = bk_tree.set(tree);
+        }
+        Ok(WordIndex{bt: contents.bt, duration: header.build_duration, record_count: header.record_count, word_count: header.word_count,
+            options: header.options, source: header.source, built_at: header.built_at, tokenizer: header.tokenizer, crate_version: header.crate_version, fields: header.fields, sections: header.sections, deleted: BTreeSet::new(), hidden: HashMap::new(), purged, field_counts: Vec::new(), vocabulary_growth: contents.vocabulary_growth,
+            bk_tree, symspell: OnceLock::new(), phonetic: None, folded: OnceLock::new(), trie: OnceLock::new(), ngrams: contents.ngrams}.with_field_counts())
+    }
 
-        let filename = "index.json";
-        write_to_file(filename, &json);
-        write_to_gz_file(&(filename.to_owned()+".gz"), &json);
+    pub fn build_index<R: BufRead>(reader: R) -> WordIndex {
+        WordIndex::build_index_with(reader, IndexOptions::default())
+    }
+
+    pub fn build_index_from_file<P: AsRef<Path>>(path: P, options: IndexOptions) -> io::Result<WordIndex> {
+        // Build the index of a file and record the file in the header, so that a persisted index can be traced back to its source.
+        let source = SourceInfo::from_path(&path)?;
+        let mut word_index = WordIndex::try_build_index_with(BufReader::new(File::open(&path)?), options)?;
+        word_index.source = Some(source);
+        Ok(word_index)
+    }
 
+    pub fn build_index_with<R: BufRead>(reader: R, options: IndexOptions) -> WordIndex {
+        WordIndex::try_build_index_with(reader, options).unwrap()
     }
 
-    pub fn build_index(reader: BufReader<File>) -> WordIndex {
+    pub fn try_build_index_with<R: BufRead>(reader: R, options: IndexOptions) -> io::Result<WordIndex> {
+        // Index the records that the adapter of 'options.format' reads (see adapter.rs), grouped by 'options.records'
+        // (see boundary.rs). Fails when the input can not be read or does not match the format.
+        if options.records != RecordBoundary::Line && matches!(options.format, SourceFormat::Csv{..} | SourceFormat::JsonLines{..}) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the records of {} are rows, they can not be grouped by {}", options.format, options.records)));
+        }
         let mut word_index = BTreeMap::new();
+        let mut ngrams = options.ngrams.then(NGramModel::new);
+        let mut vocabulary_growth = Vec::new();
     
         let start = Instant::now();   
         //    println!("Dynamic usage of tree is {}", word_count.dynamic_usage());
@@ -116,40 +803,208 @@ impl WordIndex {
         let mut record_count = 0;
         let mut word_count = 0;
         let mut stdout = stdout();
-        for (line_idx, line) in reader.lines().enumerate() {
+        let mut sections = SectionTable::default();
+        let mut records = RecordReader::new(reader, &options.format).with_sections(&options.sections.0)?;
+        for (line_idx, record) in group_records(records.by_ref(), &options.records)?.enumerate() {
             record_count += 1;
-            for (word_idx, word) in line
-                                    .unwrap()
-    //                                .to_lowercase()
-                                    .split_whitespace()
-                                    .filter_map(remove_interpunction)
-                                    .enumerate() {
-                word_count += 1;
-                let w_string = word.to_string();
-                let WordLoc = WordLoc{line: line_idx as u32, word: word_idx as u16};
-                (*word_index.entry(w_string).or_insert(Vec::new())).push(WordLoc);
-            }
-            if record_count % 1000 == 0 {
+            let record = record?;
+            for heading in &record.headings {
+                sections.start(line_idx as u32, heading);
+            }
+            for (field_idx, field) in record.fields.iter().enumerate() {
+                let words: Vec<String> = field
+        //                                .to_lowercase()
+                                        .split_whitespace()
+                                        .filter_map(remove_interpunction)
+                                        .collect();
+                if let Some(ngrams) = ngrams.as_mut() {
+                    ngrams.add_line(&words);
+                }
+                for (word_idx, word) in words.iter().enumerate() {
+                    word_count += 1;
+                    let w_string = word.to_string();  // a copy without the spare capacity of 'word'
+                    let word_loc = WordLoc{line: line_idx as u32, field: field_idx as u16, word: word_idx as u16};
+                    (*word_index.entry(w_string).or_insert(Vec::new())).push(word_loc);
+                }
+            }
+            if record_count % GROWTH_SAMPLE_INTERVAL == 0 {
+                vocabulary_growth.push((word_count, word_index.len()));
+            }
+            if record_count % 1000 == 0 && !options.quiet {
                 print!(".");
-                stdout.flush();
+                stdout.flush().unwrap();
             }
         }
+        if vocabulary_growth.last().is_none_or(|&(n, _)| n < word_count) {
+            vocabulary_growth.push((word_count, word_index.len()));
+        }
         let duration = start.elapsed();
-        println!("\nTime elapsed to index the full file with {} lines and {} words. Duration: {:?}", record_count, word_count, duration);
+        if !options.quiet {
+            println!("\nTime elapsed to index the full file with {} records and {} words. Duration: {:?}", record_count, word_count, duration);
+        }
     
-        return WordIndex{bt: word_index, duration, record_count, word_count};
+        let built_at = unix_seconds(SystemTime::now());
+        let fields = records.field_names().to_vec();
+        Ok(WordIndex{bt: word_index, duration, record_count, word_count, options, source: None, built_at, tokenizer: TokenizerConfig::current(), crate_version: crate_version(), fields, sections, deleted: BTreeSet::new(), hidden: HashMap::new(), purged: BTreeSet::new(), field_counts: Vec::new(), vocabulary_growth, bk_tree: OnceLock::new(), symspell: OnceLock::new(), phonetic: None, folded: OnceLock::new(), trie: OnceLock::new(), ngrams}.with_field_counts())
     }
     
 
-    pub fn find_completions(&self, check_word: &String, num_completions: usize) -> CompletionsRec {
+    pub fn add_record(&mut self, line: &str) {
+        // Append a line of plain text to the index as the next record.
+        self.add_record_fields(&[line]);
+    }
+
+    pub fn add_record_fields<S: AsRef<str>>(&mut self, fields: &[S]) {
+        // Append a record with the given fields to the index. The structures that were already built are kept in sync:
+        // the completion trie, the BK-tree, the phonetic index and the n-grams are updated, the others are rebuilt on next use.
+        let record = self.record_count as u32;
+        self.record_count += 1;
+        self.insert_record_fields(record, fields);
+        if self.record_count.is_multiple_of(GROWTH_SAMPLE_INTERVAL) {
+            self.vocabulary_growth.push((self.word_count, self.bt.len()));
+        }
+    }
+
+    fn insert_record_fields<S: AsRef<str>>(&mut self, record: u32, fields: &[S]) {
+        // Add the locations of a record that has no locations, in order: at the end of the posting vectors for a new
+        // record and in the middle for a replaced one.
+        let mut new_words = false;
+        if self.field_counts.len() < fields.len() {
+            self.field_counts.resize(fields.len(), FieldCounts::default());
+        }
+        for (field_idx, field) in fields.iter().enumerate() {
+            let words: Vec<String> = field.as_ref().split_whitespace().filter_map(remove_interpunction).collect();
+            if let Some(ngrams) = self.ngrams.as_mut() {
+                ngrams.add_line(&words);
+            }
+            if !words.is_empty() {
+                self.field_counts[field_idx].records += 1;
+                self.field_counts[field_idx].tokens += words.len();
+            }
+            for (word_idx, word) in words.iter().enumerate() {
+                self.word_count += 1;
+                if !self.occurs_in_field(word, field_idx as u16) {
+                    self.field_counts[field_idx].distinct_words += 1;
+                }
+                if let Some(trie) = self.trie.get_mut() {
+                    trie.add_count(word, 1);
+                }
+                if !self.bt.contains_key(word) {
+                    new_words = true;
+                    if let Some(bk_tree) = self.bk_tree.get_mut() {
+                        bk_tree.insert(word);
+                    }
+                    if let Some(phonetic) = self.phonetic.as_mut() {
+                        phonetic.insert(word);
+                    }
+                }
+                let loc = WordLoc{line: record, field: field_idx as u16, word: word_idx as u16};
+                let locs = self.bt.entry(word.to_string()).or_default();
+                locs.insert(locs.partition_point(|l| *l < loc), loc);
+            }
+        }
+        if new_words {
+            self.symspell.take();
+            self.folded.take();
+        }
+    }
+
+    pub fn has_ngrams(&self) -> bool {
+        self.ngrams.is_some()
+    }
+
+    pub fn load_ngrams<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        // Use the n-grams of an index of the same text that was exported in the binary format. Returns false if the file
+        // can not be read or has no n-grams.
+        match read_binary_file(path.as_ref()).map(|contents| contents.ngrams) {
+            Ok(Some(ngrams)) => {
+                self.ngrams = Some(ngrams);
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn find_next_words(&self, context: &str, num_words: usize) -> Vec<NextWord> {
+        // The words most likely to follow the last words of 'context', ranked by conditional frequency.
+        // Empty when the index was built without n-grams.
+        let Some(ngrams) = &self.ngrams else { return Vec::new() };
+        let words: Vec<String> = context.split_whitespace().filter_map(remove_interpunction).collect();
+        let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+        ngrams.predict(&words, num_words)
+            .into_iter()
+            .map(|(word, count, probability)| NextWord{word: word.to_string(), count: count as usize, probability})
+            .collect()
+    }
+
+    pub fn collocations(&self, term: &str, window: usize, measure: AssociationMeasure, num_collocations: usize) -> Vec<Collocation> {
+        self.collocations_with(term, window, measure, num_collocations, MIN_COLLOCATION_FREQ)
+    }
+
+    pub fn collocations_with(&self, term: &str, window: usize, measure: AssociationMeasure, num_collocations: usize, min_freq: usize) -> Vec<Collocation> {
+        // The words that co-occur at least 'min_freq' times with 'term' within 'window' words on the same line, ranked by
+        // 'measure' (see collocation.rs). Empty when the term does not occur.
+        let Some(term_locs) = self.find_matches(term) else { return Vec::new() };
+
+        let start = Instant::now();
+        let mut collocations: Vec<Collocation> = cooccurrences(&term_locs, self.bt.iter(), window)
+            .into_iter()
+            .filter(|&(_, observed, _)| observed >= min_freq)
+            .map(|(word, observed, frequency)| {
+                let frequency = frequency - self.hidden.get(word).copied().unwrap_or(0);
+                Collocation{
+                    word: word.clone(),
+                    cooccurrences: observed,
+                    frequency,
+                    score: measure.score(observed, term_locs.len(), frequency, window, self.word_count)}
+            })
+            .collect();
+        collocations.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.word.cmp(&b.word)));
+        collocations.truncate(num_collocations);
+        if !self.options.quiet {
+            println!("Time elapsed to compute the collocations of '{term}': {:?}", start.elapsed());
+        }
+
+        collocations
+    }
+
+    pub fn completion_trie(&self) -> &CompletionTrie {
+        // the completion trie over the vocabulary, which is built on first use.
+        self.trie.get_or_init(|| {
+            let start = Instant::now();
+            let trie = CompletionTrie::build(self.live_counts(), TRIE_TOP_K);
+            if !self.options.quiet {
+                println!("Time elapsed to build the completion trie over {} words: {:?}", trie.len(), start.elapsed());
+            }
+            trie
+        })
+    }
+
+    pub fn find_trie_completions(&self, check_word: &str, num_completions: usize) -> CompletionsRec {
+        // Same results as 'find_completions', but the cost does not grow with the number of words that share the prefix
+        // (as long as 'num_completions' <= TRIE_TOP_K).
+        let trie = self.completion_trie();
+
+        let start = Instant::now();
+        let (completions, total_count) = trie.completions(check_word, num_completions);
+        let mut completions_rec = CompletionsRec::new(num_completions);
+        completions_rec.compl.extend(completions.into_iter().map(|(word, count)| Completion{completion: word.to_string(), count}));
+        completions_rec.total_count = total_count;
+        completions_rec.duration = start.elapsed();
+
+        completions_rec
+    }
+
+    pub fn find_completions(&self, check_word: &str, num_completions: usize) -> CompletionsRec {
         // Find the 'num_completions'  completions that are most common in the indexed text.
-        let mut end_range: String = check_word.clone();
-        end_range.push_str("zzzzzzzz");
+        let (begin, end) = prefix_range(check_word);
     
         let start = Instant::now();   
         let mut completions_rec: CompletionsRec = self.bt
-                .range(check_word.to_owned()..end_range)
-                .fold(CompletionsRec::new(num_completions), top_completions).into();
+                .range::<str, _>((begin, end.as_ref().map(|s| s.as_str())))
+                .map(|(word, locs)| (word, self.live_count(word, locs)))
+                .filter(|&(_, count)| count > 0)
+                .fold(CompletionsRec::new(num_completions), top_completions);
         let duration = start.elapsed();
         completions_rec.duration = duration;
         println!("\nTime elapsed to compute completions is + size: {:?}", duration);
@@ -158,50 +1013,231 @@ impl WordIndex {
         completions_rec
     }
 
+    fn folded(&self) -> &BTreeMap<String, Vec<String>> {
+        // the vocabulary grouped by its lowercase, diacritic-free form; built on first use.
+        self.folded.get_or_init(|| {
+            let mut folded: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            for word in self.bt.keys() {
+                folded.entry(fold_str(word)).or_default().push(word.clone());
+            }
+            folded
+        })
+    }
+
+    pub fn surface_variants(&self, word: &str) -> Vec<(&str, usize)> {
+        // all spellings of 'word' that only differ in case or diacritics, with their counts, most frequent first.
+        let mut variants: Vec<(&str, usize)> = self.folded()
+            .get(&fold_str(word))
+            .map(|words| words.iter().filter_map(|w| self.live_entry(w)).map(|(w, count)| (w.as_str(), count)).collect())
+            .unwrap_or_default();
+        variants.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        variants
+    }
+
+    pub fn find_completions_folded(&self, check_word: &str, num_completions: usize) -> CompletionsRec {
+        // Case- and diacritic-insensitive completions. The surface variants of a word are grouped: the completion shows the
+        // most frequent variant and the count is the total of all variants (see 'surface_variants' for the details).
+        let folded_word = fold_str(check_word);
+        let (begin, end) = prefix_range(&folded_word);
+
+        let start = Instant::now();
+        let mut completions_rec = self.folded()
+            .range::<str, _>((begin, end.as_ref().map(|s| s.as_str())))
+            .fold(CompletionsRec::new(num_completions), |state, (_, words)| {
+                let (best, count) = words.iter()
+                    .filter_map(|w| self.live_entry(w))
+                    .fold((None, 0), |(best, total): (Option<(&String, usize)>, usize), (w, c)| (if best.is_none_or(|(_, b)| c > b) { Some((w, c)) } else { best }, total + c));
+                match best {
+                    Some((best, _)) => top_completions_count(state, best, count),
+                    None => state  // all variants only occur in deleted records
+                }
+            });
+        completions_rec.duration = start.elapsed();
+
+        completions_rec
+    }
+
     pub fn find_dl_completions(&self, check_word: &String, num_completions: usize, max_dist: usize) -> CompletionsRec {
         // look over full index for words that are within 'max_dist' and order by frequency.
-        use crate::levenshtein::dam_lev_prefix;
+        self.find_dl_completions_with(check_word, num_completions, max_dist, DamLevAlgorithm::default())
+    }
+
+    pub fn find_dl_completions_with(&self, check_word: &String, num_completions: usize, max_dist: usize, algorithm: DamLevAlgorithm) -> CompletionsRec {
+        // as 'find_dl_completions', but with a choice of the prefix-distance algorithm (e.g. the fast greedy one).
+        use crate::levenshtein::dam_lev_prefix_with;
     
         let start = Instant::now();   
-        let mut completions_rec: CompletionsRec =  self.bt
-            .iter()
+        let mut completions_rec: CompletionsRec =  self.live_counts()
             .filter(|&(s, _)| !s.starts_with(check_word)) 
-            .fold(CompletionsRec::new(num_completions), |state, kv| if let Some(_) = dam_lev_prefix(check_word, kv.0, max_dist) {top_completions(state, kv)} else {state});
+            .fold(CompletionsRec::new(num_completions), |state, (word, count)| if dam_lev_prefix_with(check_word, word, max_dist, algorithm).is_some() {top_completions_count(state, word, count)} else {state});
         let duration = start.elapsed();
         completions_rec.duration = duration;
         println!("Time elapsed {:?}\n", duration);
     
         completions_rec
     }
-    
-    
+
+    pub fn find_weighted_completions(&self, check_word: &str, num_completions: usize, max_cost: f64, costs: &EditCosts) -> CompletionsRec {
+        // Fuzzy completions with the weighted prefix distance. Contrary to the other completions these are ranked on a
+        // combination of distance and frequency: score = ln(1 + count) - costs.distance_penalty * distance.
+        use crate::levenshtein::weighted_dam_lev_prefix;
+
+        let start = Instant::now();
+        let mut candidates: Vec<(&String, usize, f64)> = self.live_counts()
+            .filter(|&(s, _)| !s.starts_with(check_word))
+            .filter_map(|(s, count)| weighted_dam_lev_prefix(check_word, s, max_cost, costs).map(|dist| (s, count, dist)))
+            .collect();
+        let score = |count: usize, dist: f64| (1.0 + count as f64).ln() - costs.distance_penalty * dist;
+        candidates.sort_by(|a, b| score(b.1, b.2).total_cmp(&score(a.1, a.2)).then_with(|| a.0.cmp(b.0)));
+
+        let mut completions_rec = CompletionsRec::new(num_completions);
+        completions_rec.total_count = candidates.len();
+        completions_rec.compl.extend(candidates.into_iter().take(num_completions).map(|(s, count, _)| Completion{completion: s.clone(), count}));
+        completions_rec.duration = start.elapsed();
+
+        completions_rec
+    }
+
+    pub fn bk_tree(&self) -> &BkTree {
+        // the BK-tree over the vocabulary, which is built on first use.
+        self.bk_tree.get_or_init(|| {
+            let start = Instant::now();
+            let tree = BkTree::from_words(self.bt.keys());
+            if !self.options.quiet {
+                println!("Time elapsed to build the BK-tree over {} words: {:?}", tree.len(), start.elapsed());
+            }
+            tree
+        })
+    }
+
+    pub fn load_bk_tree<P: AsRef<Path>>(&self, path: P) -> bool {
+        // Use the BK-tree of an index that was exported in the binary format instead of building it. Returns false if the
+        // file can not be used, for example because it has no tree or the tree was built for another vocabulary, or when
+        // the tree was already present.
+        let tree = match read_binary_file(path.as_ref()).map(|contents| contents.bk_tree) {
+            Ok(Some(tree)) => tree,
+            _ => return false
+        };
+        // the words of the tree are distinct, so they are the vocabulary when there are as many and all are in it
+        let same_vocabulary = tree.len() == self.bt.len() && tree.words().all(|word| self.bt.contains_key(word));
+        same_vocabulary && self.bk_tree.set(tree).is_ok()
+    }
+
+    pub fn find_bk_completions(&self, check_word: &str, num_completions: usize, max_dist: usize) -> CompletionsRec {
+        // Alternative for 'find_dl_completions' that uses the BK-tree. Note that this compares full words instead of prefixes.
+        let bk_tree = self.bk_tree();
+
+        let start = Instant::now();
+        let mut completions_rec = bk_tree
+            .find_within(check_word, max_dist)
+            .into_iter()
+            .filter(|(word, _)| *word != check_word)
+            .filter_map(|(word, _)| self.live_entry(word))
+            .fold(CompletionsRec::new(num_completions), top_completions);
+        completions_rec.duration = start.elapsed();
+
+        completions_rec
+    }
+
+    pub fn did_you_mean(&self, word: &str, max_dist: usize, num_suggestions: usize) -> Vec<Suggestion> {
+        // Spelling corrections for a word that has no exact match, using the full-word distance via the BK-tree.
+        // Ordered by distance first and by number of occurrences second.
+        let mut suggestions: Vec<Suggestion> = self.bk_tree()
+            .find_within(word, max_dist)
+            .into_iter()
+            .filter(|(w, _)| *w != word)
+            .filter_map(|(w, distance)| self.live_entry(w).map(|(_, count)| Suggestion{word: w.to_string(), distance, count}))
+            .collect();
+        suggestions.sort_by(|a, b| a.distance.cmp(&b.distance).then(b.count.cmp(&a.count)).then_with(|| a.word.cmp(&b.word)));
+        suggestions.truncate(num_suggestions);
+        suggestions
+    }
+
+    pub fn symspell_with(&self, config: SymSpellConfig) -> &SymSpell {
+        // the deletion index over the vocabulary. It is built on first use, so 'config' is ignored once it exists.
+        self.symspell.get_or_init(|| {
+            let start = Instant::now();
+            let sym = SymSpell::build(self.live_counts(), config);
+            if !self.options.quiet {
+                println!("Time elapsed to build the deletion index with {} deletes: {:?}", sym.num_deletes(), start.elapsed());
+            }
+            sym
+        })
+    }
+
+    pub fn find_symspell_completions(&self, check_word: &str, num_completions: usize, max_dist: usize) -> CompletionsRec {
+        // Spelling suggestions via the deletion index (default configuration if it was not built yet), ranked like the other completions.
+        let sym = self.symspell_with(SymSpellConfig::default());
+
+        let start = Instant::now();
+        let mut completions_rec = sym
+            .lookup(check_word, max_dist)
+            .into_iter()
+            .filter(|(word, _)| *word != check_word)
+            .filter_map(|(word, _)| self.live_entry(word))
+            .fold(CompletionsRec::new(num_completions), top_completions);
+        completions_rec.duration = start.elapsed();
+
+        completions_rec
+    }
+
+    pub fn build_phonetic_index(&mut self, algorithm: PhoneticAlgorithm) {
+        // build the phonetic key index alongside 'bt', so that 'find_phonetic_completions' returns results.
+        let start = Instant::now();
+        self.phonetic = Some(PhoneticIndex::build(self.bt.keys(), algorithm));
+        if !self.options.quiet {
+            println!("Time elapsed to build the phonetic index: {:?}", start.elapsed());
+        }
+    }
+
+    pub fn has_phonetic_index(&self) -> bool {
+        self.phonetic.is_some()
+    }
+
+    pub fn find_phonetic_completions(&self, check_word: &str, num_completions: usize) -> CompletionsRec {
+        // words that sound like 'check_word' ordered by frequency. Empty when no phonetic index was built.
+        let start = Instant::now();
+        let mut completions_rec = match &self.phonetic {
+            Some(phonetic) => phonetic
+                .sounds_like(check_word)
+                .iter()
+                .filter(|word| *word != check_word)
+                .filter_map(|word| self.live_entry(word))
+                .fold(CompletionsRec::new(num_completions), top_completions),
+            None => CompletionsRec::new(num_completions)
+        };
+        completions_rec.duration = start.elapsed();
+
+        completions_rec
+    }
 }
 
 
 
 pub fn build_indexes_from_file_name(filename: &str, num_btrees: u32) -> Vec<WordIndex> {
     // only for testing purposes.  Measure memory rqequirements as a function of number of btrees.
+    // See shard.rs for an index that is split into shards.
     let mut store = Vec::new();
     for _ in 0..num_btrees {
         let reader = BufReader::new(File::open(filename).expect("Cannot open file."));
         store.push(WordIndex::build_index(reader))
     }
-    return store;
+    store
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct WordLoc {
-    line: u32,
-    word: u16  // the size of this struct is 8 bytes anyway due to alignment, so this could also be a u32
+    pub line: u32,  // the record (a line for plain text)
+    #[serde(default, skip_serializing_if = "is_first_field")]
+    pub field: u16, // the field of the record (see adapter.rs), 0 for plain text. Fits in the padding of this struct.
+    pub word: u16
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct WordLocTuple (
-    u32,
-    u16  // the size of this struct is 8 bytes anyway due to alignment, so this could also be a u32
-);
+fn is_first_field(field: &u16) -> bool {
+    *field == 0
+}
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -211,10 +1247,6 @@ pub struct WordLocationsEntry {
 }
 
 
-//#[derive(Debug, Serialize, Deserialize)]
-type WordLocations = Vec<WordLocationsEntry>;
-
-
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WordCount {
@@ -228,6 +1260,20 @@ pub struct Completion {
     pub count: usize
 }
 
+#[derive(Debug, PartialEq)]
+pub struct Suggestion {
+    pub word: String,
+    pub distance: usize,
+    pub count: usize
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NextWord {
+    pub word: String,
+    pub count: usize,
+    pub probability: f64  // count / number of times the context was followed by a word
+}
+
 #[derive(Debug)]
 pub struct CompletionsRec {
     pub compl: Vec::<Completion>,
@@ -235,7 +1281,7 @@ pub struct CompletionsRec {
     pub duration: Duration
 }
 
-trait NewCompl {
+pub(crate) trait NewCompl {
     fn new(num_compl: usize) -> Self;
 }
 
@@ -248,18 +1294,52 @@ impl NewCompl for CompletionsRec {
 }
 
 
-fn top_completions(mut state: CompletionsRec, kv: (&String, &Vec<WordLoc>)) -> CompletionsRec {
+fn vocabulary_growth_of(bt: &BTreeMap<String, Vec<WordLoc>>, record_count: usize) -> Vec<(usize, usize)> {
+    // The vocabulary growth curve as 'try_build_index_with' samples it, recomputed from the postings: a word is new in the
+    // record of its first location.
+    let mut words = vec![0; record_count];
+    let mut new_words = vec![0; record_count];
+    for locs in bt.values() {
+        if let Some(first) = locs.first() {
+            new_words[first.line as usize] += 1;
+        }
+        for loc in locs {
+            words[loc.line as usize] += 1;
+        }
+    }
+    let mut growth = Vec::new();
+    let (mut word_count, mut distinct) = (0, 0);
+    for record in 0..record_count {
+        word_count += words[record];
+        distinct += new_words[record];
+        if (record + 1) % GROWTH_SAMPLE_INTERVAL == 0 {
+            growth.push((word_count, distinct));
+        }
+    }
+    if growth.last().is_none_or(|&(n, _)| n < word_count) {
+        growth.push((word_count, distinct));
+    }
+    growth
+}
+
+
+pub(crate) fn top_completions(state: CompletionsRec, kv: (&String, usize)) -> CompletionsRec {
     // find the series of most frequent completions where the number of completions selected is state.compl.capacity and count the total number of completions.
     // internal function to be mapped over a iterable with results.
+    top_completions_count(state, kv.0, kv.1)
+}
+
+
+pub(crate) fn top_completions_count(mut state: CompletionsRec, word: &str, count: usize) -> CompletionsRec {
+    // as 'top_completions' with the word and its count as separate arguments.
     state.total_count += 1;
-    if state.compl.len() < state.compl.capacity() || state.compl[state.compl.capacity() -1].count < (*kv.1).len() {
+    if state.compl.len() < state.compl.capacity() || state.compl[state.compl.capacity() -1].count < count {
         if state.compl.len() == state.compl.capacity() {
             _ = state.compl.pop();
         }
 
         // the new completion should be inserted in the proper position to retain ordering
-        let count = (*kv.1).len();
-        let new_compl = Completion{completion: kv.0.clone(), count};
+        let new_compl = Completion{completion: word.to_string(), count};
         for i in (0..=state.compl.len()).rev() {
             if i == 0 || count <= state.compl[i-1].count  {  // when i==0 we insert at first position
                 //println!("For completions={:?} adding {:?} at position {}", &state, &new_compl, i);
@@ -275,6 +1355,11 @@ fn top_completions(mut state: CompletionsRec, kv: (&String, &Vec<WordLoc>)) -> C
 
 
 
+// the interpunction 'remove_interpunction' strips from the start and the end of a word (recorded in the index header)
+pub const LEADING_PUNCTUATION: &str = "\"'[({";
+pub const TRAILING_PUNCTUATION: &str = ";.,\"'?!)]}";
+
+
 fn remove_interpunction(s: &str) -> Option<String> {
     // for a word remove heading and trailing interpunction. To be used with filter_map to drop empty strings.
     let chs: Vec<char> = s.chars().collect();
@@ -286,15 +1371,12 @@ fn remove_interpunction(s: &str) -> Option<String> {
     // };
     let mut start_idx = 0;
     
-    while start_idx < chs.len() &&
-      (chs[start_idx] == '"' || chs[start_idx] == '\u{27}' || chs[start_idx] == '[' || chs[start_idx] == '(' || chs[start_idx] == '{') {
+    while start_idx < chs.len() && LEADING_PUNCTUATION.contains(chs[start_idx]) {
         start_idx += 1;
       };
 
     let mut end_idx = chs.len()-1;
-    while end_idx > 0 && 
-      (chs[end_idx] == ';' || chs[end_idx] == '.' || chs[end_idx] == ',' || chs[end_idx] == '"' || chs[end_idx] == '\u{27}' 
-       || chs[end_idx] == '?' || chs[end_idx] == '!' || chs[end_idx] == ')' || chs[end_idx] == ']' || chs[end_idx] == '}') {
+    while end_idx > 0 && TRAILING_PUNCTUATION.contains(chs[end_idx]) {
        end_idx -= 1;
     };
     end_idx += 1;
@@ -307,34 +1389,10 @@ fn remove_interpunction(s: &str) -> Option<String> {
 }
 
 
-#[cfg(test)]
-mod tests {
-    use super::{find_completions_internal, WordLoc, CompletionsRec};
-    use std::time::Duration;
-
-    #[test]
-    fn test_find_completions() {
-        let state = CompletionsRec{ compl: Vec::<super::Completion>::with_capacity(2), total_count: 0, duration: Duration::default()};
-
-        // add the first item to 'state'
-        let state = find_completions_internal(state, (&"initial-value".to_string(), &(vec!(WordLoc{line: 1, word: 1}, WordLoc{line: 2, word: 2}, WordLoc{line: 3, word: 3}))));
-        assert_eq!(state.compl[0].count, 3);
-        // // add the second item to 'state'
-        let state = find_completions_internal(state, (&"at end".to_string(), &vec!(WordLoc{line: 3, word: 3})));
-        assert_eq!(state.compl[0].count, 3);
-        assert_eq!(state.compl[1].count, 1);
-        // and append a third item
-        let state = find_completions_internal(state, (&"at start".to_string(), &vec!(WordLoc{line: 4, word: 3}, WordLoc{line: 5, word: 3}, WordLoc{line: 6, word: 3}, WordLoc{line: 7, word: 3})));
-        assert_eq!(state.compl[0].count, 4);
-        assert_eq!(state.compl[1].count, 3);
-    }
-}
-
-
 
 pub fn test_index(reader: BufReader<File>) {
  
-    let mut word_count = WordIndex::build_index(reader);
+    let word_count = WordIndex::build_index(reader);
 
     println!("The datastructure contains {} items", word_count.bt.len());
     println!(
@@ -342,6 +1400,7 @@ pub fn test_index(reader: BufReader<File>) {
         mem::size_of::<BTreeMap<String, i32>>(),
         mem::size_of_val(&word_count)
     );
+    println!("Heap memory of the index: {}", word_count.memory_usage());
     //    println!("Dynamic usage of tree is {}", word_count.dynamic_usage());
     let check_word = "the".to_string();
     match word_count.bt.get(&check_word) {
@@ -350,7 +1409,13 @@ pub fn test_index(reader: BufReader<File>) {
     }
 
     let check_word = "the".to_string();
-    let cr = word_count.find_completions(&check_word, 10);
+    let _cr = word_count.find_completions(&check_word, 10);
+
+    for check_word in ["t", "th", "the"] {
+        let range_duration = word_count.find_completions(check_word, 10).duration;
+        let trie_duration = word_count.find_trie_completions(check_word, 10).duration;
+        println!("Completions of '{check_word}' via the range: {:?}  via the trie: {:?}", range_duration, trie_duration);
+    }
 
     // let start4 = Instant::now();   
     // let check_word = "the".to_string();
@@ -375,10 +1440,579 @@ pub fn test_index(reader: BufReader<File>) {
             let start5 = Instant::now();   
             let (num_dl_match, num_total) = word_count.bt
                 .iter()
-                .fold((0, 0), |(num, num_total), kv| if let Some(_) = dam_lev_prefix(check_word, kv.0, max_dist) {(num+1, num_total+1)} else {(num, num_total+1)});
+                .fold((0, 0), |(num, num_total), kv| if dam_lev_prefix(check_word, kv.0, max_dist).is_some() {(num+1, num_total+1)} else {(num, num_total+1)});
             let duration5 = start5.elapsed();
             let fraction = 100.0 * num_dl_match as f64/(num_total as f64);
             println!("Time elapsed {:?} and found {num_dl_match} entries out of {num_total} at distance {max_dist} ({fraction:.1}%)\n", duration5);
             }
     }
+
+    let frozen = word_count.freeze();
+    let (uncompressed, compressed) = frozen.postings_memory();
+    println!("Posting lists take {compressed} bytes compressed instead of {uncompressed} bytes as Vec<WordLoc> ({:.1}% saved)",
+        100.0 * (uncompressed - compressed) as f64 / uncompressed as f64);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{top_completions, WordIndex, WordLoc, CompletionsRec};
+    use crate::collocation::AssociationMeasure;
+    use crate::testutil::TempPath;
+    use std::time::Duration;
+
+    // the corpus of the tests that need a few lines with some shared words, prefixes and an accented variant
+    const SMALL_TEXT: &str = "the cat sat on the mat\nthe bat and the hat\nthat cat thé\n";
+
+    fn small_index() -> WordIndex {
+        WordIndex::build_index(SMALL_TEXT.as_bytes())
+    }
+
+    #[test]
+    fn test_find_completions() {
+        let state = CompletionsRec{ compl: Vec::<super::Completion>::with_capacity(2), total_count: 0, duration: Duration::default()};
+
+        // add the first item to 'state'
+        let state = top_completions(state, (&"initial-value".to_string(), 3));
+        assert_eq!(state.compl[0].count, 3);
+        // // add the second item to 'state'
+        let state = top_completions(state, (&"at end".to_string(), 1));
+        assert_eq!(state.compl[0].count, 3);
+        assert_eq!(state.compl[1].count, 1);
+        // and append a third item
+        let state = top_completions(state, (&"at start".to_string(), 4));
+        assert_eq!(state.compl[0].count, 4);
+        assert_eq!(state.compl[1].count, 3);
+    }
+
+    #[test]
+    fn test_find_bk_completions() {
+        let word_index = small_index();
+        let compl_rec = word_index.find_bk_completions("cat", 10, 1);
+        let found: Vec<(&str, usize)> = compl_rec.compl.iter().map(|c| (c.completion.as_str(), c.count)).collect();
+        assert_eq!(found, vec![("bat", 1), ("hat", 1), ("mat", 1), ("sat", 1)]);
+        assert_eq!(word_index.bk_tree().len(), word_index.len());
+
+        // a saved tree is only used for the same vocabulary, not for another one of the same size
+        let path = TempPath::new("bk_tree.bin");
+        word_index.export(&path, crate::export::ExportFormat::Binary, false).unwrap();
+        assert!(!WordIndex::build_index(SMALL_TEXT.replace("that", "than").as_bytes()).load_bk_tree(&path));
+        assert!(small_index().load_bk_tree(&path));
+    }
+
+    #[test]
+    fn test_find_symspell_completions() {
+        let word_index = small_index();
+        let compl_rec = word_index.find_symspell_completions("cta", 2, 1);
+        let found: Vec<(&str, usize)> = compl_rec.compl.iter().map(|c| (c.completion.as_str(), c.count)).collect();
+        assert_eq!(found, vec![("cat", 2)]);
+        assert_eq!(compl_rec.total_count, 1);
+    }
+
+    #[test]
+    fn test_find_phonetic_completions() {
+        let text = "Desdemona Desdemona\nDesdemon and Iago\n";
+        let mut word_index = WordIndex::build_index(text.as_bytes());
+        assert_eq!(word_index.find_phonetic_completions("Desdemonah", 5).compl.len(), 0);
+
+        word_index.build_phonetic_index(super::PhoneticAlgorithm::Metaphone);
+        let compl_rec = word_index.find_phonetic_completions("Desdemonah", 5);
+        let found: Vec<(&str, usize)> = compl_rec.compl.iter().map(|c| (c.completion.as_str(), c.count)).collect();
+        assert_eq!(found, vec![("Desdemona", 2), ("Desdemon", 1)]);
+    }
+
+    #[test]
+    fn test_find_weighted_completions() {
+        // 'thw' is one adjacent-key typo away from 'the', and a full edit away from both 'this' and 'thaw', of which 'this' is more frequent
+        let text = "the the the this this this this this this\nthe thaw\n";
+        let word_index = WordIndex::build_index(text.as_bytes());
+        let compl_rec = word_index.find_weighted_completions("thw", 5, 1.5, &super::EditCosts::default());
+        let found: Vec<&str> = compl_rec.compl.iter().map(|c| c.completion.as_str()).collect();
+        assert_eq!(found, vec!["the", "this", "thaw"]);
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        let word_index = small_index();
+        assert!(word_index.find_matches("cst").is_none());
+        let suggestions = word_index.did_you_mean("cst", 1, 2);
+        assert_eq!(suggestions, vec![super::Suggestion{word: "cat".to_string(), distance: 1, count: 2}]);
+        let words: Vec<(String, usize)> = word_index.did_you_mean("tha", 2, 4).into_iter().map(|s| (s.word, s.distance)).collect();
+        assert_eq!(words, vec![("the".to_string(), 1), ("that".to_string(), 1), ("thé".to_string(), 1), ("hat".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let word_index = small_index();
+        let completions = |rec: super::CompletionsRec| rec.compl.into_iter().map(|c| (c.completion, c.count)).collect::<Vec<_>>();
+        let expected_compl = completions(word_index.find_completions("th", 3));
+        let expected_dl = completions(word_index.find_dl_completions(&"cta".to_string(), 5, 1));
+        let expected_len = word_index.len();
+
+        let frozen = word_index.freeze();
+        assert_eq!(frozen.len(), expected_len);
+        assert_eq!(frozen.find_matches("cat").map(|locs| locs.collect::<Vec<_>>()), Some(vec![WordLoc{line: 0, field: 0, word: 1}, WordLoc{line: 2, field: 0, word: 1}]));
+        assert!(frozen.find_matches("ca").is_none());
+        assert_eq!(completions(frozen.find_completions("th", 3)), expected_compl);
+        assert_eq!(completions(frozen.find_dl_completions("cta", 5, 1)), expected_dl);
+        assert_eq!(completions(frozen.find_regex_completions("[a-z]at", 2).unwrap()), vec![("cat".to_string(), 2), ("bat".to_string(), 1)]);
+
+        let filename = TempPath::new("freeze.json.gz");
+        frozen.save_to_file(filename.to_str().unwrap()).unwrap();
+        let loaded = super::FrozenWordIndex::load_from_file(filename.to_str().unwrap()).unwrap();
+        assert_eq!(loaded.len(), expected_len);
+        assert_eq!(loaded.find_matches("hat").map(|locs| locs.len()), Some(1));
+
+        // the intersection of posting lists that span many blocks
+        let lines: Vec<String> = (0..3000).map(|i| format!("{} {} {} x", if i % 2 == 0 { "a" } else { "" }, if i % 3 == 0 { "b" } else { "" }, if i % 500 == 7 { "c" } else { "" })).collect();
+        let frozen = WordIndex::build_index(lines.join("\n").as_bytes()).freeze();
+        let expected = |step: u32, rest: u32| (0..3000).filter(|i| i % step == rest).collect::<Vec<u32>>();
+        assert_eq!(frozen.records_with_all(&["a", "b"]), expected(6, 0));
+        assert_eq!(frozen.records_with_all(&["x", "b", "x"]), expected(3, 0));
+        assert_eq!(frozen.records_with_all(&["c", "a"]), Vec::<u32>::new());
+        assert_eq!(frozen.records_with_all(&["c", "x"]), expected(500, 7));
+        assert!(frozen.records_with_all(&["a", "missing"]).is_empty() && frozen.records_with_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let word_index = small_index();
+        let usage = word_index.memory_usage();
+        assert_eq!(usage.keys, "the cat sat on mat bat and hat that thé".split(' ').map(|w| w.len()).sum::<usize>());
+        assert_eq!(usage.postings, word_index.word_count * std::mem::size_of::<WordLoc>());
+        assert!(usage.tree_nodes > 0);
+        assert_eq!(usage.auxiliary, 0);
+
+        word_index.bk_tree();
+        assert!(word_index.memory_usage().auxiliary > 0);
+    }
+
+    #[test]
+    fn test_find_completions_unicode() {
+        let text = "thé thé the theatre thz thzzzzzzzzzz thλ Über über über uber\nλόγος λόγοι λέξη Москва Москвы\n";
+        let word_index = WordIndex::build_index(text.as_bytes());
+        let completions = |rec: super::CompletionsRec| rec.compl.into_iter().map(|c| (c.completion, c.count)).collect::<Vec<_>>();
+        let words = |rec: super::CompletionsRec| { let mut w: Vec<String> = rec.compl.into_iter().map(|c| c.completion).collect(); w.sort(); w };
+
+        assert_eq!(words(word_index.find_completions("th", 10)), vec!["the", "theatre", "thz", "thzzzzzzzzzz", "thé", "thλ"]);
+        assert_eq!(words(word_index.find_completions("thz", 10)), vec!["thz", "thzzzzzzzzzz"]);
+        assert_eq!(words(word_index.find_completions("λό", 10)), vec!["λόγοι", "λόγος"]);
+        assert_eq!(words(word_index.find_completions("Моск", 10)), vec!["Москва", "Москвы"]);
+        assert_eq!(words(word_index.find_completions("Ü", 10)), vec!["Über"]);
+        assert_eq!(word_index.find_completions("", 100).total_count, word_index.len());
+
+        // folded completions group the surface variants
+        assert_eq!(completions(word_index.find_completions_folded("UB", 10)), vec![("über".to_string(), 4)]);
+        assert_eq!(completions(word_index.find_completions_folded("the", 2)), vec![("thé".to_string(), 3), ("theatre".to_string(), 1)]);
+        assert_eq!(word_index.surface_variants("uber"), vec![("über", 2), ("uber", 1), ("Über", 1)]);
+        assert_eq!(words(word_index.find_completions_folded("ΛΌΓ", 10)), vec!["λόγοι", "λόγος"]);
+    }
+
+    #[test]
+    fn test_trie_completions_in_sync() {
+        let mut word_index = small_index();
+        let completions = |rec: super::CompletionsRec| (rec.compl.into_iter().map(|c| (c.completion, c.count)).collect::<Vec<_>>(), rec.total_count);
+        for prefix in ["", "t", "th", "ca", "x"] {
+            assert_eq!(completions(word_index.find_trie_completions(prefix, 3)), completions(word_index.find_completions(prefix, 3)));
+        }
+
+        word_index.add_record("that that that thing");
+        assert_eq!(word_index.record_count, 4);
+        assert_eq!(word_index.find_matches("thing").as_deref(), Some(&vec![WordLoc{line: 3, field: 0, word: 3}]));
+        for prefix in ["", "t", "th", "tha", "thi"] {
+            assert_eq!(completions(word_index.find_trie_completions(prefix, 3)), completions(word_index.find_completions(prefix, 3)));
+        }
+        assert_eq!(completions(word_index.find_trie_completions("th", 2)), (vec![("that".to_string(), 4), ("the".to_string(), 4)], 4));
+    }
+
+    #[test]
+    fn test_find_next_words() {
+        let text = "to be or not to be\nto be, the best\nnot to be or\n";
+        let mut word_index = super::WordIndex::build_index_with(text.as_bytes(), super::IndexOptions{ngrams: true, ..Default::default()});
+        let next_words = |word_index: &WordIndex, context| word_index.find_next_words(context, 2).into_iter().map(|n| (n.word, n.count)).collect::<Vec<_>>();
+        assert_eq!(next_words(&word_index, "to be"), vec![("or".to_string(), 2), ("the".to_string(), 1)]);
+        assert_eq!(next_words(&word_index, "(not"), vec![("to".to_string(), 2)]);
+
+        word_index.add_record("to be the one");
+        assert_eq!(next_words(&word_index, "to be"), vec![("or".to_string(), 2), ("the".to_string(), 2)]);
+        assert!(WordIndex::build_index(text.as_bytes()).find_next_words("to be", 2).is_empty());
+
+        let filename = TempPath::new("ngrams.bin");
+        word_index.export(&filename, crate::export::ExportFormat::Binary, false).unwrap();
+        let mut loaded = WordIndex::build_index(text.as_bytes());
+        assert!(loaded.load_ngrams(&filename));
+        std::fs::remove_file(&filename).unwrap();
+        assert!(!loaded.load_ngrams(&filename));
+        assert_eq!(next_words(&loaded, "to be"), next_words(&word_index, "to be"));
+    }
+
+    #[test]
+    fn test_collocations() {
+        let text = "the old king is dead\nlong live the king\nthe king and the old queen\nthe old man\nking of the castle\n";
+        let word_index = WordIndex::build_index(text.as_bytes());
+        let collocates = |measure| word_index.collocations_with("king", 1, measure, 3, 2).into_iter().map(|c| (c.word, c.cooccurrences)).collect::<Vec<_>>();
+        // "the" directly precedes "king" twice, "old" only once
+        assert_eq!(collocates(AssociationMeasure::Pmi), vec![("the".to_string(), 2)]);
+        assert_eq!(collocates(AssociationMeasure::TScore), vec![("the".to_string(), 2)]);
+        assert_eq!(word_index.collocations_with("king", 2, AssociationMeasure::LogLikelihood, 3, 1).len(), 3);
+        assert_eq!(word_index.collocations_with("king", 2, AssociationMeasure::Pmi, 10, 1).iter().map(|c| c.cooccurrences).sum::<usize>(), 11);
+        assert!(word_index.collocations("queen", 5, AssociationMeasure::Pmi, 10).is_empty());
+        assert!(word_index.collocations("prince", 5, AssociationMeasure::Pmi, 10).is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let text: String = (0..2500).map(|i| format!("w{} w{} the\n", i % 700, i % 13)).collect();
+        let mut word_index = WordIndex::build_index(text.as_bytes());
+        word_index.add_record("a brand new line");
+        let stats = word_index.stats();
+        assert_eq!((stats.records, stats.tokens, stats.types), (2501, 7504, 705));
+        assert_eq!(stats.vocabulary_growth, vec![(3000, 701), (6000, 701), (7500, 701), (7504, 705)]);
+        assert_eq!(stats.hapaxes, vec!["a", "brand", "line", "new"]);
+        assert_eq!(stats.memory, word_index.memory_usage());
+    }
+
+    #[test]
+    fn test_export() {
+        use crate::export::ExportFormat;
+
+        let word_index = WordIndex::build_index_with(SMALL_TEXT.as_bytes(), super::IndexOptions{ngrams: true, ..Default::default()});
+        word_index.bk_tree();
+        let read = |name: &str, format, compress| {
+            let path = TempPath::new(name);
+            word_index.export(&path, format, compress).unwrap();
+            let mut contents = String::new();
+            let file = std::fs::File::open(&path).unwrap();
+            if compress {
+                std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(file), &mut contents).unwrap();
+            } else {
+                std::io::Read::read_to_string(&mut { file }, &mut contents).unwrap();
+            }
+            contents
+        };
+
+        assert!(read("export.csv", ExportFormat::WordCountCsv, false).starts_with("word,count\nand,1\nbat,1\ncat,2\n"));
+        assert!(read("export.tsv.gz", ExportFormat::WordCountTsv, true).ends_with("the\t4\nthé\t1\n"));
+        let counts: Vec<super::WordCount> = serde_json::from_str(&read("export_wc.json", ExportFormat::WordCountJson, false)).unwrap();
+        assert_eq!(counts.len(), word_index.len());
+        let document: serde_json::Value = serde_json::from_str(&read("export.json.gz", ExportFormat::PostingsJson, true)).unwrap();
+        let entries: Vec<super::WordLocationsEntry> = serde_json::from_value(document["entries"].clone()).unwrap();
+        assert_eq!(entries.iter().find(|e| e.word == "cat").map(|e| e.locations.clone()), word_index.find_matches("cat").map(|locs| locs.into_owned()));
+        assert_eq!(document["header"]["word_count"], word_index.word_count);
+        let lines = read("export.jsonl", ExportFormat::JsonLines, false);
+        assert_eq!(lines.lines().count(), word_index.len() + 1);
+        assert!(serde_json::from_str::<super::WordLocationsEntry>(lines.lines().nth(1).unwrap()).is_ok());
+
+        for compress in [false, true] {
+            let path = TempPath::new(&format!("export_{compress}.bin"));
+            word_index.export(&path, ExportFormat::Binary, compress).unwrap();
+            let loaded = WordIndex::load_binary(&path).unwrap();
+            assert_eq!(loaded.bt, word_index.bt);
+            assert_eq!((loaded.record_count, loaded.word_count), (word_index.record_count, word_index.word_count));
+            assert_eq!(loaded.find_next_words("the", 1), word_index.find_next_words("the", 1));
+            assert_eq!(loaded.bk_tree.get().map(|t| t.len()), Some(word_index.len()));
+        }
+        assert_eq!(ExportFormat::from_path(std::path::Path::new("out/index.jsonl.gz")), Some(ExportFormat::JsonLines));
+    }
+
+    #[test]
+    fn test_header_and_migration() {
+        use crate::export::ExportFormat;
+        use crate::header::{TokenizerConfig, FORMAT_VERSION};
+
+        let source = TempPath::new("header.txt");
+        std::fs::write(&source, "the cat sat on the mat\nthe bat and the hat\n").unwrap();
+        let word_index = WordIndex::build_index_from_file(&source, super::IndexOptions::default()).unwrap();
+        let header = word_index.header();
+        assert_eq!(header.source.as_ref().map(|s| s.size), Some(43));
+        assert_eq!((header.record_count, header.word_count, header.format_version), (2, 11, FORMAT_VERSION));
+
+        for (name, format) in [("header.bin", ExportFormat::Binary), ("header.jsonl", ExportFormat::JsonLines), ("header.json", ExportFormat::PostingsJson)] {
+            let path = TempPath::new(name);
+            word_index.export(&path, format, false).unwrap();
+            let loaded = if format == ExportFormat::Binary { WordIndex::load_binary(&path) } else { WordIndex::load_json(&path) }.unwrap();
+            assert_eq!(loaded.header(), header);
+            assert_eq!(loaded.bt, word_index.bt);
+        }
+
+        // the headerless array of format version 1 is migrated
+        let legacy = TempPath::new("legacy.json");
+        std::fs::write(&legacy, r#"[{"word":"cat","locations":[{"line":0,"word":1},{"line":4,"word":0}]},{"word":"the","locations":[{"line":0,"word":0}]}]"#).unwrap();
+        let migrated = WordIndex::load_json(&legacy).unwrap();
+        let header = migrated.header();
+        assert_eq!((header.format_version, header.record_count, header.word_count), (FORMAT_VERSION, 5, 3));
+        assert_eq!(header.tokenizer, TokenizerConfig::current());
+        assert!(header.source.is_none());
+
+        // a newer version is rejected with a clear error
+        let mut json = String::new();
+        word_index.export(&legacy, ExportFormat::PostingsJson, false).unwrap();
+        std::io::Read::read_to_string(&mut std::fs::File::open(&legacy).unwrap(), &mut json).unwrap();
+        // the version of text_index that built the index is kept, an index of another tokenizer is rejected
+        let version = format!("\"crate_version\":\"{}\"", env!("CARGO_PKG_VERSION"));
+        std::fs::write(&legacy, json.replacen(&version, "\"crate_version\":\"0.0.1\"", 1)).unwrap();
+        assert_eq!(WordIndex::load_json(&legacy).unwrap().header().crate_version, "0.0.1");
+        std::fs::write(&legacy, json.replacen("\"case_sensitive\":true", "\"case_sensitive\":false", 1)).unwrap();
+        assert!(WordIndex::load_json(&legacy).err().unwrap().to_string().starts_with("the index was built with another tokenizer"));
+        std::fs::write(&legacy, json.replacen(&format!("\"format_version\":{FORMAT_VERSION}"), "\"format_version\":99", 1)).unwrap();
+        let err = WordIndex::load_json(&legacy).err().unwrap();
+        assert!(err.to_string().starts_with("index format version 99 is not supported"), "{err}");
+        std::fs::write(&legacy, b"TIDX\x63").unwrap();
+        assert!(WordIndex::load_binary(&legacy).err().unwrap().to_string().starts_with("index format version 99 is not supported"));
+
+        let frozen = word_index.freeze();
+        assert_eq!(frozen.header().word_count, 11);
+    }
+
+    #[test]
+    fn test_refresh_from_source() {
+        use crate::header::Staleness;
+
+        let path = TempPath::new("refresh.txt");
+        std::fs::write(&path, "the cat sat on the mat\n").unwrap();
+        let mut word_index = WordIndex::build_index_from_file(&path, super::IndexOptions{ngrams: true, ..Default::default()}).unwrap();
+        word_index.completion_trie();
+        assert_eq!(word_index.refresh_from_source().unwrap(), Staleness::Fresh);
+
+        std::fs::write(&path, "the cat sat on the mat\nthe bat and the hat\n").unwrap();
+        assert_eq!(word_index.staleness().unwrap(), Staleness::Appended);
+        assert_eq!(word_index.refresh_from_source().unwrap(), Staleness::Appended);
+        assert_eq!(word_index.find_matches("hat").as_deref(), Some(&vec![WordLoc{line: 1, field: 0, word: 4}]));
+        assert_eq!(word_index.find_trie_completions("th", 1).compl[0].count, 4);
+        assert_eq!(word_index.staleness().unwrap(), Staleness::Fresh);
+
+        std::fs::write(&path, "a hat\nthe bat and the hat\n").unwrap();
+        assert_eq!(word_index.refresh_from_source().unwrap(), Staleness::Changed);
+        assert_eq!(word_index.find_matches("hat").as_deref(), Some(&vec![WordLoc{line: 0, field: 0, word: 1}, WordLoc{line: 1, field: 0, word: 4}]));
+        assert!(word_index.find_matches("cat").is_none());
+        assert!(word_index.has_ngrams());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(word_index.refresh_from_source().unwrap(), Staleness::Missing);
+        assert_eq!(WordIndex::build_index("a b".as_bytes()).staleness().unwrap(), Staleness::Unknown);
+    }
+
+    #[test]
+    fn test_structured_sources() {
+        use crate::adapter::SourceFormat;
+        use crate::export::ExportFormat;
+
+        let csv = "id,title,body\n1,Hamlet,\"the prince, of Denmark\"\n2,The Tempest,the island\n";
+        let options = super::IndexOptions{format: "csv:title,body".parse().unwrap(), ..Default::default()};
+        let word_index = WordIndex::try_build_index_with(csv.as_bytes(), options.clone()).unwrap();
+        assert_eq!((word_index.record_count, word_index.fields()), (2, &["title".to_string(), "body".to_string()][..]));
+        let locs = word_index.find_matches("the").unwrap().into_owned();
+        assert_eq!(locs, vec![WordLoc{line: 0, field: 1, word: 0}, WordLoc{line: 1, field: 1, word: 0}]);
+        assert_eq!(word_index.find_matches("Tempest").as_deref(), Some(&vec![WordLoc{line: 1, field: 0, word: 1}]));
+        assert_eq!(word_index.field_name(&locs[0]), Some("body"));
+
+        let path = TempPath::new("fields.bin");
+        word_index.export(&path, ExportFormat::Binary, false).unwrap();
+        let loaded = WordIndex::load_binary(&path).unwrap();
+        assert_eq!(loaded.bt, word_index.bt);
+        assert_eq!(loaded.header().options.format, options.format);
+        assert_eq!(loaded.fields(), word_index.fields());
+        assert_eq!(loaded.freeze().find_matches("the").map(|locs| locs.collect::<Vec<_>>()), Some(locs));
+
+        let html = "<h1>Hamlet</h1>\n<script>var the = 1;</script><p>the <b>prince</b></p>\n";
+        let word_index = WordIndex::try_build_index_with(html.as_bytes(), super::IndexOptions{format: SourceFormat::Html, ..Default::default()}).unwrap();
+        assert_eq!(word_index.find_matches("the").as_deref(), Some(&vec![WordLoc{line: 1, field: 0, word: 0}]));
+        assert!(word_index.find_matches("var").is_none());
+        assert!(WordIndex::try_build_index_with("{".as_bytes(), super::IndexOptions{format: "jsonl".parse().unwrap(), ..Default::default()}).is_err());
+    }
+
+    #[test]
+    fn test_record_boundaries() {
+        use crate::header::Staleness;
+
+        let text = "The king is dead.\nLong live the king!\n\nWho is the king? Nobody\nknows.\n";
+        let options = |records: &str| super::IndexOptions{records: records.parse().unwrap(), ..Default::default()};
+        let paragraphs = WordIndex::try_build_index_with(text.as_bytes(), options("paragraph")).unwrap();
+        assert_eq!(paragraphs.record_count, 2);
+        assert_eq!(paragraphs.find_matches("king").as_deref(), Some(&vec![WordLoc{line: 0, field: 0, word: 1}, WordLoc{line: 0, field: 0, word: 7}, WordLoc{line: 1, field: 0, word: 3}]));
+        let sentences = WordIndex::try_build_index_with(text.as_bytes(), options("sentence")).unwrap();
+        assert_eq!(sentences.record_count, 4);
+        assert_eq!(sentences.find_matches("knows").as_deref(), Some(&vec![WordLoc{line: 3, field: 0, word: 1}]));
+        let windows = WordIndex::try_build_index_with(text.as_bytes(), options("window:4")).unwrap();
+        assert_eq!((windows.record_count, windows.word_count), (4, 14));
+        let csv = super::IndexOptions{format: "csv".parse().unwrap(), ..options("paragraph")};
+        assert!(WordIndex::try_build_index_with("a,b\n".as_bytes(), csv).is_err());
+
+        // appended lines may continue the last paragraph, so the index is rebuilt
+        let path = TempPath::new("boundaries.txt");
+        std::fs::write(&path, "the cat\n").unwrap();
+        let mut word_index = WordIndex::build_index_from_file(&path, options("paragraph")).unwrap();
+        std::fs::write(&path, "the cat\nsat on the mat\n").unwrap();
+        assert_eq!(word_index.refresh_from_source().unwrap(), Staleness::Appended);
+        assert_eq!((word_index.record_count, word_index.find_matches("mat").as_deref()), (1, Some(&vec![WordLoc{line: 0, field: 0, word: 5}])));
+    }
+
+    #[test]
+    fn test_sections() {
+        use crate::export::ExportFormat;
+        use crate::header::Staleness;
+
+        let text = "THE TRAGEDY OF HAMLET\nACT I. SCENE I. Elsinore.\nWho's there, the ghost?\nSCENE II. A room of state.\nThe king speaks.\nACT II\nSCENE I. A room.\nThe ghost walks.\n";
+        let options = super::IndexOptions{sections: "shakespeare".parse().unwrap(), ..Default::default()};
+        let mut word_index = WordIndex::try_build_index_with(text.as_bytes(), options.clone()).unwrap();
+        assert_eq!(word_index.sections().len(), 6);
+        // a replaced record stays in its section
+        assert_eq!(word_index.update_record_fields(2, &["Who's there, the ghost?"]), Some(2));
+        let ghosts = word_index.find_matches("ghost").unwrap();
+        assert_eq!(word_index.section_path(&ghosts[0]).as_deref(), Some("THE TRAGEDY OF HAMLET › ACT I › SCENE I"));
+        assert_eq!(word_index.section_path(&ghosts[1]).as_deref(), Some("THE TRAGEDY OF HAMLET › ACT II › SCENE I"));
+        assert_eq!(word_index.section_facets("ghost", Some(2)), vec![(Some("THE TRAGEDY OF HAMLET › ACT I".to_string()), 1), (Some("THE TRAGEDY OF HAMLET › ACT II".to_string()), 1)]);
+        assert_eq!(word_index.find_matches_in_section("ghost", "act ii"), vec![ghosts[1]]);
+        assert_eq!(word_index.find_matches_in_section("ghost", "act iii"), vec![]);
+
+        // the sections are persisted and continued by appended lines
+        let source = TempPath::new("sections.txt");
+        let path = TempPath::new("sections.bin");
+        std::fs::write(&source, text).unwrap();
+        WordIndex::build_index_from_file(&source, options.clone()).unwrap().export(&path, ExportFormat::Binary, false).unwrap();
+        let mut loaded = WordIndex::load_binary(&path).unwrap();
+        assert_eq!(loaded.sections(), word_index.sections());
+        std::fs::write(&source, format!("{text}SCENE II. The castle.\nThe ghost again.\n")).unwrap();
+        assert_eq!(loaded.refresh_from_source().unwrap(), Staleness::Appended);
+        let ghosts = loaded.find_matches("ghost").unwrap();
+        assert_eq!(loaded.section_path(&ghosts[2]).as_deref(), Some("THE TRAGEDY OF HAMLET › ACT II › SCENE II"));
+    }
+
+    #[test]
+    fn test_field_queries() {
+        let jsonl = concat!(
+            "{\"title\":\"The Tempest\",\"body\":\"a storm at sea and a king\"}\n",
+            "{\"title\":\"King Lear\",\"body\":\"a storm on the heath, the king rages in the storm\"}\n",
+            "{\"title\":\"storm\",\"body\":\"no king here\"}\n",
+            "{\"title\":\"Hamlet\",\"body\":\"the king is dead\"}\n");
+        let options = super::IndexOptions{format: "jsonl:title,body".parse().unwrap(), ..Default::default()};
+        let word_index = WordIndex::try_build_index_with(jsonl.as_bytes(), options.clone()).unwrap();
+        let records = |query: &str| word_index.search(&query.parse().unwrap()).map(|hits| hits.iter().map(|hit| hit.record).collect::<Vec<_>>());
+        assert_eq!(records("storm"), Ok(vec![2, 1, 0]));  // a title match is rarer than a match in the body
+        assert_eq!(records("title:storm"), Ok(vec![2]));
+        assert_eq!(records("body:storm AND body:king"), Ok(vec![1, 0]));
+        assert_eq!(records("king NOT body:storm"), Ok(vec![2, 3]));
+        assert_eq!(records("title:Hamlet OR title:storm"), Ok(vec![2, 3]));
+        assert!(records("author:storm").is_err());
+        let hits = word_index.search(&"title:King AND body:storm".parse().unwrap()).unwrap();
+        assert_eq!(hits[0].locs, vec![WordLoc{line: 1, field: 0, word: 0}, WordLoc{line: 1, field: 1, word: 1}, WordLoc{line: 1, field: 1, word: 10}]);
+
+        let completions = word_index.find_field_completions("title", "", 10).unwrap();
+        assert_eq!(completions.compl.iter().map(|c| (c.completion.as_str(), c.count)).collect::<Vec<_>>(),
+            vec![("Hamlet", 1), ("King", 1), ("Lear", 1), ("Tempest", 1), ("The", 1), ("storm", 1)]);
+        assert_eq!(word_index.find_field_completions("body", "s", 10).unwrap().compl[0].completion, "storm");
+        let stats = word_index.field_stats();
+        assert_eq!((stats[0].name.as_str(), stats[0].records, stats[0].tokens), ("title", 4, 6));
+        assert_eq!((stats[1].records, stats[1].tokens, stats[1].avg_length), (4, 25, 6.25));
+
+        // the statistics are kept up to date by the updates
+        let mut word_index = WordIndex::try_build_index_with(jsonl.as_bytes(), options.clone()).unwrap();
+        word_index.add_record_fields(&["Macbeth", "the witches and the king"]);
+        word_index.delete_record(1);
+        word_index.update_record_fields(2, &["storm again", ""]);
+        word_index.delete_record(3);
+        assert_eq!(word_index.field_counts, word_index.count_fields());
+        assert_eq!(word_index.field_stats()[1].records, 2);
+        word_index.purge();
+        assert_eq!(word_index.field_counts, word_index.count_fields());
+        assert!(WordIndex::build_index("a b\n".as_bytes()).search(&"title:a".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_delete_records() {
+        use crate::export::ExportFormat;
+
+        let mut word_index = WordIndex::build_index("the cat sat\nthe dog\nthe cat and the hat\n".as_bytes());
+        word_index.completion_trie();
+        let count = |word_index: &WordIndex, prefix: &str| word_index.find_completions(prefix, 10).compl.iter().map(|c| (c.completion.clone(), c.count)).collect::<Vec<_>>();
+        assert!(word_index.delete_record(2));
+        assert!(!word_index.delete_record(2) && !word_index.delete_record(3));
+        assert_eq!(word_index.find_matches("cat").as_deref(), Some(&vec![WordLoc{line: 0, field: 0, word: 1}]));
+        assert!(word_index.find_matches("hat").is_none());
+        assert_eq!(count(&word_index, "th"), vec![("the".to_string(), 2)]);
+        assert_eq!(word_index.find_trie_completions("th", 10).compl.iter().map(|c| c.count).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(word_index.find_trie_completions("h", 10).compl.len(), 0);
+        assert_eq!((word_index.word_count, word_index.stats().types), (5, 4));
+
+        // the n-grams of a deleted record are not predicted anymore
+        let mut with_ngrams = WordIndex::build_index_with("to be or not\nto go home\n".as_bytes(), super::IndexOptions{ngrams: true, ..Default::default()});
+        assert!(with_ngrams.delete_record(1));
+        assert_eq!(with_ngrams.find_next_words("to", 5).iter().map(|n| (n.word.as_str(), n.count)).collect::<Vec<_>>(), vec![("be", 1)]);
+        assert!(with_ngrams.find_next_words("to go", 5).is_empty());
+
+        // a replaced record keeps its number
+        assert_eq!(word_index.update_record_fields(1, &["the dog barks"]), Some(1));
+        assert_eq!(word_index.find_matches("dog").as_deref(), Some(&vec![WordLoc{line: 1, field: 0, word: 1}]));
+        assert_eq!(word_index.find_matches("the").as_deref(), Some(&vec![WordLoc{line: 0, field: 0, word: 0}, WordLoc{line: 1, field: 0, word: 0}]));
+        assert_eq!(count(&word_index, "th"), vec![("the".to_string(), 2)]);
+        assert_eq!((word_index.record_count, word_index.deleted_count(), word_index.word_count), (3, 1, 6));
+        assert_eq!(word_index.field_counts, word_index.count_fields());
+        assert_eq!(word_index.update_record_fields(1, &["the cow"]), Some(1));
+        assert!(word_index.find_matches("dog").is_none() && word_index.find_matches("barks").is_none());
+        assert_eq!(word_index.update_record_fields(1, &["the dog barks"]), Some(1));
+
+        let path = TempPath::new("deleted.bin");
+        word_index.export(&path, ExportFormat::Binary, false).unwrap();
+        let loaded = WordIndex::load_binary(&path).unwrap();
+        assert!(loaded.find_matches("hat").is_none() && loaded.find_matches("dog").is_some_and(|locs| locs.len() == 1));
+
+        let locs_before: Vec<(String, Vec<WordLoc>)> = ["the", "cat", "dog"].iter().map(|w| (w.to_string(), word_index.find_matches(w).unwrap().into_owned())).collect();
+        assert_eq!(word_index.purge(), 5);
+        assert_eq!((word_index.deleted_count(), word_index.len()), (0, 5));
+        for (word, locs) in locs_before {
+            assert_eq!(word_index.find_matches(&word).as_deref(), Some(&locs));
+        }
+        assert_eq!(count(&word_index, ""), count(&loaded, ""));
+
+        // a purged record stays deleted, also in a saved index
+        let records = |word_index: &WordIndex, query: &str| {
+            let mut records: Vec<u32> = word_index.search(&query.parse().unwrap()).unwrap().iter().map(|hit| hit.record).collect();
+            records.sort_unstable();
+            records
+        };
+        for word_index in [&word_index, &loaded] {
+            assert!(word_index.is_deleted(2));
+            assert_eq!((word_index.stats().records, word_index.live_record_count()), (2, 2));
+            assert_eq!(records(word_index, "NOT cow"), vec![0, 1]);
+        }
+        assert!(!word_index.delete_record(2));
+        assert_eq!((word_index.deleted_count(), word_index.stats().records), (0, 2));
+        assert_eq!(word_index.update_record_fields(2, &["the hat"]), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        use crate::export::ExportFormat;
+
+        let lines: Vec<String> = (0..2500).map(|i| if i % 700 == 0 { format!("# Part {i}") } else { format!("w{} w{} shared word{}", i % 37, i % 101, i) }).collect();
+        let options = super::IndexOptions{ngrams: true, sections: "markdown".parse().unwrap(), ..Default::default()};
+        let build = |lines: &[String]| WordIndex::try_build_index_with(lines.join("\n").as_bytes(), options.clone()).unwrap();
+        let whole = build(&lines);
+        let merged = WordIndex::merge(vec![build(&lines[..1200]), build(&lines[1200..1201]), build(&lines[1201..])]).unwrap();
+        assert_eq!(merged.bt, whole.bt);
+        assert_eq!((merged.record_count, merged.word_count), (whole.record_count, whole.word_count));
+        assert_eq!(merged.vocabulary_growth, whole.vocabulary_growth);
+        assert_eq!(merged.sections(), whole.sections());
+        assert_eq!(merged.ngrams, whole.ngrams);
+
+        // fields are matched by name, and saved indexes can be merged
+        let jsonl = |text: &str| WordIndex::try_build_index_with(text.as_bytes(), super::IndexOptions{format: "jsonl".parse().unwrap(), ..Default::default()}).unwrap();
+        let path = TempPath::new("merge.bin");
+        jsonl("{\"title\":\"storm\"}\n").export(&path, ExportFormat::Binary, false).unwrap();
+        let first = WordIndex::load(&path).unwrap();
+        let mut second = jsonl("{\"body\":\"a storm\",\"title\":\"Lear\"}\n{\"title\":\"gone\"}\n");
+        second.delete_record(1);
+        let merged = WordIndex::merge(vec![first, second]).unwrap();
+        let whole = jsonl("{\"title\":\"storm\"}\n{\"body\":\"a storm\",\"title\":\"Lear\"}\n{}\n");
+        assert_eq!((merged.fields(), &merged.bt), (whole.fields(), &whole.bt));
+        assert_eq!(merged.find_matches("storm").as_deref(), Some(&vec![WordLoc{line: 0, field: 0, word: 0}, WordLoc{line: 1, field: 1, word: 1}]));
+        // a record whose fields get other numbers in the merged index
+        let merged = WordIndex::merge(vec![jsonl("{\"title\":\"storm\"}\n"), jsonl("{\"body\":\"storm\",\"title\":\"storm\"}\n")]).unwrap();
+        let whole = jsonl("{\"title\":\"storm\"}\n{\"body\":\"storm\",\"title\":\"storm\"}\n");
+        assert_eq!((merged.fields(), &merged.bt), (whole.fields(), &whole.bt));
+        assert!(WordIndex::merge(vec![merged, WordIndex::build_index("a\n".as_bytes())]).is_err());
+        let paragraphs = WordIndex::build_index_with("a\n".as_bytes(), super::IndexOptions{records: "paragraph".parse().unwrap(), ..Default::default()});
+        assert!(WordIndex::merge(vec![WordIndex::build_index("a\n".as_bytes()), paragraphs]).err().unwrap().contains("record boundary"));
+        assert!(WordIndex::merge(Vec::new()).is_err());
+    }
 }
@@ -1,19 +1,73 @@
 // This module computes the Damerau–Levenshtein distance.
 //
-// The normal Levenshtein distance only considers insertions and deletions. The Damerau-Levenshtein distance is a metric also considers transposition of adjacent characters. 
+// The normal Levenshtein distance only considers insertions and deletions. The Damerau-Levenshtein distance is a metric also considers transposition of adjacent characters.
 //
 // It operates on a prefix as it is intended to be used in a context of a search-tool, where the user might only have input part of the string to be searched.
+//
+// Three algorithms are available:
+//  - Fast: the original greedy scan. Cheap, but it does not backtrack, so it can overestimate the distance when several edits are close together.
+//  - OptimalStringAlignment: exact dynamic programming where each substring is edited at most once (the usual 'restricted' Damerau-Levenshtein).
+//  - Unrestricted: the true Damerau-Levenshtein distance (Lowrance-Wagner), which allows edits between transposed characters and is a proper metric.
+// The exact variants only compute the diagonal band of width 2*max_dist+1, as cells outside that band always exceed 'max_dist'.
+//
+// Finally 'weighted_dam_lev_prefix' computes an optimal string alignment distance where the cost of an edit depends on the
+// characters involved (see EditCosts), so a typo on an adjacent key is cheaper than a random substitution.
+
+use std::collections::HashMap;
+use crate::char_aux::{fold_diacritic, KeyboardLayout};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DamLevAlgorithm {
+    Fast,
+    #[default]
+    OptimalStringAlignment,
+    Unrestricted
+}
 
-use std::cmp;
 
 pub fn dam_lev_prefix(prefix_str: &str, word_str: &str, max_dist: usize) -> Option<usize> {
     // Compute the Damerau-Levenshtein for a prefix up to a maximum. The return value is 0 if the strings are equal, otherwise it is the actual distance or None.
     // The None value signals the distance exceeds the 'max_dist'.
     //
-    // Note: this version does not do full backtracking, so it might miss out on complex patterns. We should make a recursive version with full back-tracking.
+    // This is the exact optimal string alignment distance between 'prefix_str' and the best matching prefix of 'word_str'.
+    dam_lev_prefix_with(prefix_str, word_str, max_dist, DamLevAlgorithm::default())
+}
+
+
+pub fn dam_lev_prefix_with(prefix_str: &str, word_str: &str, max_dist: usize, algorithm: DamLevAlgorithm) -> Option<usize> {
+    // Compute the prefix distance with the selected algorithm (see the module header for the differences).
+    let prefix: Vec<char> = prefix_str.chars().collect();
+    let word = word_str.chars().collect::<Vec<char>>();
+
+    match algorithm {
+        DamLevAlgorithm::Fast => dam_lev_prefix_fast_chars(&prefix, &word, max_dist),
+        DamLevAlgorithm::OptimalStringAlignment => banded_distance(&prefix, &word, max_dist, true, false),
+        DamLevAlgorithm::Unrestricted => banded_distance(&prefix, &word, max_dist, true, true)
+    }
+}
+
+
+pub fn dam_lev_distance(a_str: &str, b_str: &str, max_dist: usize) -> Option<usize> {
+    // Compute the unrestricted Damerau-Levenshtein distance between two full words, or None when it exceeds 'max_dist'.
+    // Contrary to the prefix distance this is a metric, so it can be used to build metric trees.
+    let a: Vec<char> = a_str.chars().collect();
+    let b: Vec<char> = b_str.chars().collect();
+    banded_distance(&a, &b, max_dist, false, true)
+}
+
+
+pub fn dam_lev_prefix_fast(prefix_str: &str, word_str: &str, max_dist: usize) -> Option<usize> {
+    // The original greedy prefix distance.
+    //
+    // Note: this version does not do full backtracking, so it might miss out on complex patterns. Use 'dam_lev_prefix' when the exact distance is needed.
     let prefix: Vec<char> = prefix_str.chars().collect();
-    let word = word_str.chars().collect::<Vec<char>>(); 
-    
+    let word = word_str.chars().collect::<Vec<char>>();
+    dam_lev_prefix_fast_chars(&prefix, &word, max_dist)
+}
+
+
+fn dam_lev_prefix_fast_chars(prefix: &[char], word: &[char], max_dist: usize) -> Option<usize> {
     let mut dist = 0;
 
     let mut skip_char = false;
@@ -33,9 +87,9 @@ pub fn dam_lev_prefix(prefix_str: &str, word_str: &str, max_dist: usize) -> Opti
             continue;
         }
         if prefix[idx] != word[word_idx] {
-            dist += 1;            
+            dist += 1;
 
-            let check_word_idx = word_idx + 1;            
+            let check_word_idx = word_idx + 1;
             if word.len() > check_word_idx  && prefix[idx] == word[check_word_idx] {
                 // consider deletion in word, a transpose.
                 if  prefix.len() > idx+1 && prefix[idx + 1] == word[word_idx] {
@@ -43,8 +97,8 @@ pub fn dam_lev_prefix(prefix_str: &str, word_str: &str, max_dist: usize) -> Opti
                     skip_char = true;
                     } else {
                         // drop a character from prefix
-                        word_offset += 1 
-                    } 
+                        word_offset += 1
+                    }
                 } else {
                     // consider a drop of a character from prefix or a replace
                     if prefix.len() > idx+1 &&  prefix[idx + 1] == word[word_idx] {
@@ -59,20 +113,199 @@ pub fn dam_lev_prefix(prefix_str: &str, word_str: &str, max_dist: usize) -> Opti
                 }
         }
     }
-    
+
     if dist > max_dist {
         return None;
     }
 
     Some(dist)
-} 
+}
+
+
+fn banded_distance(a: &[char], b: &[char], max_dist: usize, prefix_mode: bool, unrestricted: bool) -> Option<usize> {
+    // Dynamic programming over the matrix d[i][j] = distance(a[..i], b[..j]), restricted to the band |i - j| <= max_dist.
+    // Every value is capped at 'cap' = max_dist + 1. Cells outside the band have a true distance of at least |i - j| > max_dist,
+    // so storing 'cap' for them is exact under the capping, and all additions in the recurrence preserve it.
+    // In 'prefix_mode' the result is the minimum over the last row (best prefix of b), otherwise it is d[a.len()][b.len()].
+    let n = a.len();
+    let m = if prefix_mode { b.len().min(n + max_dist) } else { b.len() };
+    if !prefix_mode && n.abs_diff(m) > max_dist {
+        return None;
+    }
+    let cap = max_dist + 1;
+    let width = m + 1;
+    let mut d = vec![cap; (n + 1) * width];
+    let idx = |i: usize, j: usize| i * width + j;
+
+    for j in 0..=m.min(max_dist) {
+        d[idx(0, j)] = j;
+    }
+    // last row (index in a) where a character was seen, only needed for the unrestricted variant
+    let mut last_row: HashMap<char, usize> = HashMap::new();
+
+    for i in 1..=n {
+        let j_lo = i.saturating_sub(max_dist).max(1);
+        let j_hi = (i + max_dist).min(m);
+        if i <= max_dist {
+            d[idx(i, 0)] = i;
+        }
+        let mut row_min = d[idx(i, 0)];
+        // last column (index in b) in this row where b[j-1] == a[i-1]
+        let mut last_col = 0;
+        for j in j_lo..=j_hi {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut val = (d[idx(i - 1, j - 1)] + cost)
+                .min(d[idx(i - 1, j)] + 1)
+                .min(d[idx(i, j - 1)] + 1);
+            if unrestricted {
+                let k = last_row.get(&b[j - 1]).copied().unwrap_or(0);
+                let l = last_col;
+                if k > 0 && l > 0 {
+                    // transpose a[k-1] and b[l-1] with everything in between deleted or inserted
+                    val = val.min(d[idx(k - 1, l - 1)] + (i - k - 1) + 1 + (j - l - 1));
+                }
+                if cost == 0 {
+                    last_col = j;
+                }
+            } else if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                val = val.min(d[idx(i - 2, j - 2)] + 1);
+            }
+            val = val.min(cap);
+            d[idx(i, j)] = val;
+            row_min = row_min.min(val);
+        }
+        if unrestricted {
+            last_row.insert(a[i - 1], i);
+        }
+        if row_min > max_dist {
+            return None;
+        }
+    }
+
+    let dist = if prefix_mode {
+        (0..=m).map(|j| d[idx(n, j)]).min().unwrap_or(cap)
+    } else {
+        d[idx(n, m)]
+    };
+    if dist > max_dist {
+        None
+    } else {
+        Some(dist)
+    }
+}
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EditCosts {
+    pub insertion: f64,
+    pub deletion: f64,
+    pub substitution: f64,
+    pub transposition: f64,
+    pub adjacent_key: f64,    // substitution by a neighbouring key on 'layout'
+    pub case_only: f64,       // substitution that only differs in case ('a' <-> 'A')
+    pub diacritic_only: f64,  // substitution that only differs in diacritics ('e' <-> 'é'), possibly also in case
+    pub doubled_letter: f64,  // insertion or deletion of a repeated letter ("leter" <-> "letter")
+    pub layout: Option<KeyboardLayout>,
+    pub distance_penalty: f64 // used for ranking: score = ln(1 + count) - distance_penalty * distance
+}
+
+impl Default for EditCosts {
+    fn default() -> Self {
+        EditCosts{
+            insertion: 1.0,
+            deletion: 1.0,
+            substitution: 1.0,
+            transposition: 0.7,
+            adjacent_key: 0.6,
+            case_only: 0.2,
+            diacritic_only: 0.3,
+            doubled_letter: 0.5,
+            layout: Some(KeyboardLayout::Qwerty),
+            distance_penalty: 2.0
+        }
+    }
+}
+
+
+impl EditCosts {
+    pub fn unit() -> Self {
+        // all edits cost 1, which reduces the weighted distance to the optimal string alignment distance
+        EditCosts{ transposition: 1.0, adjacent_key: 1.0, case_only: 1.0, diacritic_only: 1.0, doubled_letter: 1.0, layout: None, ..Default::default() }
+    }
+
+    fn substitution_cost(&self, a: char, b: char) -> f64 {
+        if a == b {
+            0.0
+        } else if a.to_lowercase().eq(b.to_lowercase()) {
+            self.case_only
+        } else if fold_diacritic(a).to_lowercase().eq(fold_diacritic(b).to_lowercase()) {
+            self.diacritic_only
+        } else if self.layout.is_some_and(|layout| layout.adjacent(a, b)) {
+            self.adjacent_key.min(self.substitution)
+        } else {
+            self.substitution
+        }
+    }
+}
+
+
+pub fn weighted_dam_lev_prefix(prefix_str: &str, word_str: &str, max_cost: f64, costs: &EditCosts) -> Option<f64> {
+    // Weighted optimal string alignment distance between 'prefix_str' and the best matching prefix of 'word_str',
+    // or None when it exceeds 'max_cost'. Cell d[i][j] needs at least |i - j| insertions or deletions, so when those cost
+    // more than zero only the band |i - j| <= max_cost / (cheapest insertion or deletion) is computed, as in
+    // 'banded_distance'; the cells outside it are infinite. Only the last three rows are kept (a transposition looks back
+    // two rows), so a query over the vocabulary does not allocate a full matrix per word.
+    let a: Vec<char> = prefix_str.chars().collect();
+    let b: Vec<char> = word_str.chars().collect();
+    let min_indel = costs.insertion.min(costs.deletion).min(costs.doubled_letter);
+    let band = if min_indel > 0.0 { (max_cost / min_indel).floor() as usize } else { usize::MAX };
+    let n = a.len();
+    let m = b.len().min(n.saturating_add(band));
+    let width = m + 1;
+    let mut d = vec![f64::INFINITY; 3 * width];
+    let idx = |i: usize, j: usize| (i % 3) * width + j;
+
+    let ins_cost = |j: usize| if j > 1 && b[j - 1] == b[j - 2] { costs.doubled_letter } else { costs.insertion };
+    let del_cost = |i: usize| if i > 1 && a[i - 1] == a[i - 2] { costs.doubled_letter } else { costs.deletion };
+    d[idx(0, 0)] = 0.0;
+    for j in 1..=m.min(band) {
+        d[idx(0, j)] = d[idx(0, j - 1)] + ins_cost(j);
+    }
+    for i in 1..=n {
+        d[idx(i, 0)..=idx(i, m)].fill(f64::INFINITY);  // the row still holds row i - 3
+        if i <= band {
+            d[idx(i, 0)] = d[idx(i - 1, 0)] + del_cost(i);
+        }
+        let mut row_min = d[idx(i, 0)];
+        for j in i.saturating_sub(band).max(1)..=i.saturating_add(band).min(m) {
+            let mut val = (d[idx(i - 1, j - 1)] + costs.substitution_cost(a[i - 1], b[j - 1]))
+                .min(d[idx(i - 1, j)] + del_cost(i))
+                .min(d[idx(i, j - 1)] + ins_cost(j));
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] && a[i - 1] != a[i - 2] {
+                val = val.min(d[idx(i - 2, j - 2)] + costs.transposition);
+            }
+            d[idx(i, j)] = val;
+            row_min = row_min.min(val);
+        }
+        if row_min > max_cost {
+            return None;
+        }
+    }
 
+    let dist = d[idx(n, 0)..=idx(n, m)].iter().copied().fold(f64::INFINITY, f64::min);
+    if dist > max_cost {
+        None
+    } else {
+        Some(dist)
+    }
+}
 
 
 #[cfg(test)]
 mod tests {
-    use super::dam_lev_prefix;
-    
+    use super::{dam_lev_distance, dam_lev_prefix, dam_lev_prefix_fast, dam_lev_prefix_with, weighted_dam_lev_prefix, DamLevAlgorithm, EditCosts};
+
     #[test]
     fn test_dam_lev_prefix() {
         // equal strings
@@ -92,7 +325,239 @@ mod tests {
         assert_eq!(dam_lev_prefix("abc", "XYcdef", 2), Some(2));
         assert_eq!(dam_lev_prefix("abc", "XYZdef", 2), None);
 
-        // a single transposition (swap) is counted, instead of 2 replace statements. 
+        // a single transposition (swap) is counted, instead of 2 replace statements.
         assert_eq!(dam_lev_prefix("abc", "acb____", 2), Some(1));
     }
+
+    #[test]
+    fn test_dam_lev_prefix_nearby_edits() {
+        // transpositions and deletions close to each other
+        assert_eq!(dam_lev_prefix("thmeselves", "themselves", 2), Some(1));
+        assert_eq!(dam_lev_prefix("abdc", "abcd", 2), Some(1));
+        assert_eq!(dam_lev_prefix("axbdc", "abcd", 2), Some(2));
+        // OSA can not edit a transposed pair again, the unrestricted distance can
+        let (ca, abc): (Vec<char>, Vec<char>) = ("ca".chars().collect(), "abc".chars().collect());
+        assert_eq!(super::banded_distance(&ca, &abc, 3, false, false), Some(3));
+        assert_eq!(super::banded_distance(&ca, &abc, 3, false, true), Some(2));
+        assert_eq!(dam_lev_distance("ca", "abc", 2), Some(2));
+        assert_eq!(dam_lev_distance("abc", "abc____", 2), None);
+        // the fast path is still available, but it loses track of the alignment after two deletions
+        assert_eq!(dam_lev_prefix_fast("thmelves", "themselves", 2), None);
+        assert_eq!(dam_lev_prefix("thmelves", "themselves", 2), Some(2));
+        assert_eq!(dam_lev_prefix_fast("abc", "acb____", 2), Some(1));
+        assert_eq!(dam_lev_prefix_with("abc", "acb____", 2, DamLevAlgorithm::Fast), Some(1));
+    }
+
+
+    // Reference implementations for the differential tests: straightforward full-matrix versions without banding or early exit.
+
+    fn reference_osa(a: &[char], b: &[char]) -> usize {
+        let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for (i, row) in d.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        for (j, cell) in d[0].iter_mut().enumerate() {
+            *cell = j;
+        }
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+                d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+                }
+            }
+        }
+        d[a.len()][b.len()]
+    }
+
+    fn reference_dl(a: &[char], b: &[char]) -> usize {
+        // Lowrance-Wagner with the extra border row and column
+        let max = a.len() + b.len();
+        let (n, m) = (a.len(), b.len());
+        let mut d = vec![vec![0; m + 2]; n + 2];
+        d[0][0] = max;
+        for i in 0..=n {
+            d[i + 1][0] = max;
+            d[i + 1][1] = i;
+        }
+        for j in 0..=m {
+            d[0][j + 1] = max;
+            d[1][j + 1] = j;
+        }
+        let mut da = std::collections::HashMap::new();
+        for i in 1..=n {
+            let mut db = 0;
+            for j in 1..=m {
+                let k = *da.get(&b[j - 1]).unwrap_or(&0);
+                let l = db;
+                let cost = if a[i - 1] == b[j - 1] { db = j; 0 } else { 1 };
+                d[i + 1][j + 1] = (d[i][j] + cost)
+                    .min(d[i + 1][j] + 1)
+                    .min(d[i][j + 1] + 1)
+                    .min(d[k][l] + (i - k - 1) + 1 + (j - l - 1));
+            }
+            da.insert(a[i - 1], i);
+        }
+        d[n + 1][m + 1]
+    }
+
+    fn reference_prefix(a: &[char], b: &[char], dist: fn(&[char], &[char]) -> usize, max_dist: usize) -> Option<usize> {
+        let best = (0..=b.len()).map(|j| dist(a, &b[..j])).min().unwrap();
+        if best > max_dist { None } else { Some(best) }
+    }
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn word(&mut self, alphabet: &[char], max_len: usize) -> Vec<char> {
+            let len = (self.next() % (max_len as u64 + 1)) as usize;
+            (0..len).map(|_| alphabet[(self.next() % alphabet.len() as u64) as usize]).collect()
+        }
+
+        fn mutate(&mut self, word: &[char], alphabet: &[char], edits: usize) -> Vec<char> {
+            // apply random edits that are close together, which is where the greedy scan goes wrong
+            let mut w = word.to_vec();
+            for _ in 0..edits {
+                let pos = if w.is_empty() { 0 } else { (self.next() % w.len() as u64) as usize };
+                let ch = alphabet[(self.next() % alphabet.len() as u64) as usize];
+                match self.next() % 4 {
+                    0 => w.insert(pos, ch),
+                    1 if !w.is_empty() => { w.remove(pos); },
+                    2 if pos + 1 < w.len() => w.swap(pos, pos + 1),
+                    _ if !w.is_empty() => w[pos] = ch,
+                    _ => w.push(ch)
+                }
+            }
+            w
+        }
+    }
+
+    #[test]
+    fn test_differential_against_reference() {
+        let alphabet = ['a', 'b', 'c', 'd', 'é'];
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..3000 {
+            let b = rng.word(&alphabet, 9);
+            let edits = (rng.next() % 4) as usize;
+            let a = rng.mutate(&b[..b.len().min(6)], &alphabet, edits);
+            let (a_str, b_str): (String, String) = (a.iter().collect(), b.iter().collect());
+            for max_dist in 0..4 {
+                assert_eq!(dam_lev_prefix_with(&a_str, &b_str, max_dist, DamLevAlgorithm::OptimalStringAlignment),
+                    reference_prefix(&a, &b, reference_osa, max_dist), "OSA prefix '{a_str}' vs '{b_str}' (max_dist={max_dist})");
+                assert_eq!(dam_lev_prefix_with(&a_str, &b_str, max_dist, DamLevAlgorithm::Unrestricted),
+                    reference_prefix(&a, &b, reference_dl, max_dist), "DL prefix '{a_str}' vs '{b_str}' (max_dist={max_dist})");
+                let full = reference_dl(&a, &b);
+                assert_eq!(super::banded_distance(&a, &b, max_dist, false, true),
+                    if full > max_dist { None } else { Some(full) }, "DL '{a_str}' vs '{b_str}' (max_dist={max_dist})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_fast_path_never_underestimates() {
+        // the greedy version may overestimate, but each of its results corresponds to an actual edit sequence
+        let alphabet = ['a', 'b', 'c', 'd'];
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000 {
+            let b = rng.word(&alphabet, 8);
+            let a = rng.mutate(&b[..b.len().min(5)], &alphabet, 2);
+            let (a_str, b_str): (String, String) = (a.iter().collect(), b.iter().collect());
+            if let Some(fast) = dam_lev_prefix_fast(&a_str, &b_str, 3) {
+                let exact = reference_prefix(&a, &b, reference_osa, 3).unwrap();
+                assert!(fast >= exact, "fast {fast} < exact {exact} for '{a_str}' vs '{b_str}'");
+            }
+        }
+    }
+
+    #[test]
+    fn test_weighted_dam_lev_prefix() {
+        let costs = EditCosts::default();
+        let approx = |v: Option<f64>| v.map(|v| (v * 100.0).round() / 100.0);
+        assert_eq!(approx(weighted_dam_lev_prefix("teh", "the", 2.0, &costs)), Some(0.7));
+        assert_eq!(approx(weighted_dam_lev_prefix("thw", "the", 2.0, &costs)), Some(0.6));
+        assert_eq!(approx(weighted_dam_lev_prefix("thx", "the", 2.0, &costs)), Some(1.0));
+        assert_eq!(approx(weighted_dam_lev_prefix("The", "the", 2.0, &costs)), Some(0.2));
+        assert_eq!(approx(weighted_dam_lev_prefix("elève", "eleve", 2.0, &costs)), Some(0.3));
+        assert_eq!(approx(weighted_dam_lev_prefix("Élève", "eleve", 2.0, &costs)), Some(0.6));
+        assert_eq!(approx(weighted_dam_lev_prefix("leter", "letter", 2.0, &costs)), Some(0.5));
+        assert_eq!(approx(weighted_dam_lev_prefix("letter", "leter", 2.0, &costs)), Some(0.5));
+        assert_eq!(approx(weighted_dam_lev_prefix("the", "themselves", 2.0, &costs)), Some(0.0));
+        assert_eq!(weighted_dam_lev_prefix("xyz", "the", 2.0, &costs), None);
+    }
+
+    fn reference_weighted_prefix(prefix_str: &str, word_str: &str, max_cost: f64, costs: &EditCosts) -> Option<f64> {
+        // the weighted prefix distance over the full matrix
+        let a: Vec<char> = prefix_str.chars().collect();
+        let b: Vec<char> = word_str.chars().collect();
+        let (n, m) = (a.len(), b.len());
+        let width = m + 1;
+        let mut d = vec![0.0; (n + 1) * width];
+        let idx = |i: usize, j: usize| i * width + j;
+
+        let ins_cost = |j: usize| if j > 1 && b[j - 1] == b[j - 2] { costs.doubled_letter } else { costs.insertion };
+        let del_cost = |i: usize| if i > 1 && a[i - 1] == a[i - 2] { costs.doubled_letter } else { costs.deletion };
+        for j in 1..=m {
+            d[idx(0, j)] = d[idx(0, j - 1)] + ins_cost(j);
+        }
+        for i in 1..=n {
+            d[idx(i, 0)] = d[idx(i - 1, 0)] + del_cost(i);
+            let mut row_min = d[idx(i, 0)];
+            for j in 1..=m {
+                let mut val = (d[idx(i - 1, j - 1)] + costs.substitution_cost(a[i - 1], b[j - 1]))
+                    .min(d[idx(i - 1, j)] + del_cost(i))
+                    .min(d[idx(i, j - 1)] + ins_cost(j));
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] && a[i - 1] != a[i - 2] {
+                    val = val.min(d[idx(i - 2, j - 2)] + costs.transposition);
+                }
+                d[idx(i, j)] = val;
+                row_min = row_min.min(val);
+            }
+            if row_min > max_cost {
+                return None;
+            }
+        }
+
+        let dist = (0..=m).map(|j| d[idx(n, j)]).fold(f64::INFINITY, f64::min);
+        if dist > max_cost {
+            None
+        } else {
+            Some(dist)
+        }
+    }
+
+    #[test]
+    fn test_weighted_band_equals_full_matrix() {
+        let alphabet = ['a', 'b', 'c', 'A', 'é'];
+        let mut rng = XorShift(0x0fed_cba9_8765_4321);
+        let costs = EditCosts::default();
+        for _ in 0..1000 {
+            let b = rng.word(&alphabet, 10);
+            let a = rng.mutate(&b[..b.len().min(6)], &alphabet, 3);
+            let (a_str, b_str): (String, String) = (a.iter().collect(), b.iter().collect());
+            for max_cost in [0.5, 1.0, 2.0] {
+                assert_eq!(weighted_dam_lev_prefix(&a_str, &b_str, max_cost, &costs), reference_weighted_prefix(&a_str, &b_str, max_cost, &costs), "'{a_str}' vs '{b_str}'");
+            }
+        }
+    }
+
+    #[test]
+    fn test_weighted_unit_costs_equal_osa() {
+        let alphabet = ['a', 'b', 'c', 'd'];
+        let mut rng = XorShift(0x1234_5678_9abc_def1);
+        for _ in 0..1000 {
+            let b = rng.word(&alphabet, 8);
+            let a = rng.mutate(&b[..b.len().min(5)], &alphabet, 2);
+            let (a_str, b_str): (String, String) = (a.iter().collect(), b.iter().collect());
+            let expected = reference_prefix(&a, &b, reference_osa, 3).map(|d| d as f64);
+            assert_eq!(weighted_dam_lev_prefix(&a_str, &b_str, 3.0, &EditCosts::unit()), expected, "'{a_str}' vs '{b_str}'");
+        }
+    }
 }
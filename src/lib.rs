@@ -0,0 +1,28 @@
+pub mod time_aux;
+pub mod type_aux;
+pub mod char_aux;
+
+pub mod index;
+pub mod levenshtein;
+pub mod bktree;
+pub mod symspell;
+pub mod phonetic;
+pub mod fst;
+pub mod frozen;
+pub mod postings;
+pub mod memory;
+pub mod trie;
+pub mod ngram;
+pub mod collocation;
+pub mod stats;
+pub mod export;
+pub mod header;
+pub mod watch;
+pub mod adapter;
+pub mod boundary;
+pub mod section;
+pub mod query;
+pub mod shard;
+
+#[cfg(test)]
+mod testutil;
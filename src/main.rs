@@ -1,15 +1,15 @@
 use std::fs::File;
 use std::env;
-use std::io::{BufRead, BufReader,Write, stdout};
+use std::path::Path;
+use std::sync::Arc;
+use std::io::{BufReader,Write, stdout};
 use std::time::Duration;
-use crate::type_aux::type_of;
-use crate::levenshtein::dam_lev_prefix;
 
 extern crate crossterm;
 
 
-use crossterm::{queue, QueueableCommand, cursor, execute, terminal,
-        cursor::{SavePosition, RestorePosition}, 
+use crossterm::{queue, cursor, execute, terminal,
+        cursor::SavePosition, 
             style::Stylize,
             event::{poll, read, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers}, 
             Result};
@@ -20,14 +20,31 @@ use crossterm::{queue, QueueableCommand, cursor, execute, terminal,
 //     terminal::{disable_raw_mode, enable_raw_mode},
 // };
 
+use text_index::index::{self, Completion, NextWord, Suggestion};
+use text_index::collocation::{AssociationMeasure, Collocation};
+use text_index::stats::StatsFormat;
+use text_index::export::ExportFormat;
+use text_index::header::Staleness;
+use text_index::adapter::SourceFormat;
+use text_index::boundary::RecordBoundary;
+use text_index::section::SectionRules;
+use text_index::query::Query;
+use text_index::shard::{ShardStrategy, ShardedIndex};
+use text_index::watch::{IndexWatcher, WatchEvent, POLL_INTERVAL};
+use text_index::phonetic::PhoneticAlgorithm;
+use text_index::levenshtein::EditCosts;
 
-mod time_aux;
-mod type_aux;
+// number of words on both sides of a term that are searched for collocates
+const COLLOCATION_WINDOW: usize = 5;
 
-mod index;
-mod levenshtein;
+// number of sections shown with the locations of a word
+const NUM_FACETS: usize = 5;
 
-use index::Completion;
+// number of records shown for a query
+const NUM_HITS: usize = 20;
+
+// number of shards that 'shard' creates by default
+const NUM_SHARDS: usize = 4;
 
 
 #[derive(PartialEq)]
@@ -38,31 +55,233 @@ enum InputStatus {
     None
 }
 
-fn search_file_via_console(filename: &str) -> Result<()> {
-    let mut stdout = stdout();
+fn open_index(filename: &str, options: index::IndexOptions, saved_index: Option<&str>, rebuild_if_stale: bool) -> std::io::Result<Option<index::WordIndex>> {
+    // Build the index of 'filename', or use the index saved at 'saved_index'. A saved index that is older than its source is
+    // only used after updating it (and saving it again) when 'rebuild_if_stale' is set. None when the index can not be used.
+    let options = index::IndexOptions{ngrams: true, ..options};
+    let Some(path) = saved_index else {
+        println!("{}", "Building the index".magenta());
+        return index::WordIndex::build_index_from_file(filename, options).map(Some);
+    };
+    let format = ExportFormat::from_path(Path::new(path)).unwrap_or(ExportFormat::Binary);
+    let compress = path.ends_with(".gz");
+    if !Path::new(path).exists() {
+        println!("{}", format!("Building the index and saving it to {path}").magenta());
+        let word_index = index::WordIndex::build_index_from_file(filename, options)?;
+        word_index.export(path, format, compress)?;
+        return Ok(Some(word_index));
+    }
+
+    let mut word_index = match format {
+        ExportFormat::Binary => index::WordIndex::load_binary(path)?,
+        _ => index::WordIndex::load_json(path)?
+    };
+    match word_index.staleness()? {
+        Staleness::Fresh => (),
+        Staleness::Unknown => println!("{}", format!("The index {path} does not record its source, so it can not be checked for changes").yellow()),
+        Staleness::Missing => println!("{}", "The source of the index does not exist anymore".yellow()),
+        staleness if rebuild_if_stale => {
+            println!("{}", format!("The source changed after the index was saved ({staleness:?}), updating {path}").magenta());
+            word_index.refresh_from_source()?;
+            word_index.export(path, format, compress)?;
+        },
+        staleness => {
+            println!("The source changed after the index {path} was saved ({staleness:?}). Use --rebuild-if-stale to update it.");
+            return Ok(None);
+        }
+    }
+    Ok(Some(word_index))
+}
+
+
+fn check_index(args: &[String]) {
+    // check <saved index>
+    let Some(path) = args.first() else {
+        println!("Usage: text_index <file> check <saved index>");
+        return;
+    };
+    match index::WordIndex::load(path).and_then(|word_index| word_index.staleness().map(|staleness| (word_index.header(), staleness))) {
+        Ok((header, staleness)) => {
+            println!("Index {path}: format version {}, built by text_index {}, {} records, {} words", header.format_version, header.crate_version,
+                header.record_count, header.word_count);
+            if let Some(source) = header.source {
+                println!("Source {}: {} bytes, hash {}", source.path, source.size, source.hash);
+            }
+            println!("Status: {staleness:?}");
+        },
+        Err(err) => println!("Can not check {path}: {err}")
+    }
+}
+
+
+fn delete_records(args: &[String]) {
+    // delete <saved index> <record>... [--purge]
+    let records: Vec<u32> = args.iter().skip(1).filter(|a| !a.starts_with("--")).filter_map(|a| a.parse().ok()).collect();
+    let (Some(path), false) = (args.first(), records.is_empty()) else {
+        println!("Usage: text_index <file> delete <saved index> <record>... [--purge]");
+        return;
+    };
+    let format = ExportFormat::from_path(Path::new(path)).unwrap_or(ExportFormat::Binary);
+    let mut word_index = match index::WordIndex::load(path) {
+        Ok(word_index) => word_index,
+        Err(err) => {
+            println!("Can not load {path}: {err}");
+            return;
+        }
+    };
+    for record in records {
+        if !word_index.delete_record(record) {
+            println!("Record {record} does not exist or is already deleted");
+        }
+    }
+    if args.iter().any(|a| a == "--purge") {
+        word_index.purge();
+    }
+    // the export leaves out the locations of deleted records
+    if let Err(err) = word_index.export(path, format, path.ends_with(".gz")) {
+        println!("Can not save {path}: {err}");
+    }
+}
+
+
+fn merge_indexes(args: &[String]) {
+    // merge <output> <saved index>...
+    let [output, inputs @ ..] = args else {
+        println!("Usage: text_index <file> merge <output> <saved index>...");
+        return;
+    };
+    let mut indexes = Vec::with_capacity(inputs.len());
+    for path in inputs {
+        match index::WordIndex::load(path) {
+            Ok(word_index) => indexes.push(word_index),
+            Err(err) => {
+                println!("Can not load {path}: {err}");
+                return;
+            }
+        }
+    }
+    let word_index = match index::WordIndex::merge(indexes) {
+        Ok(word_index) => word_index,
+        Err(msg) => {
+            println!("{msg}");
+            return;
+        }
+    };
+    let format = ExportFormat::from_path(Path::new(output)).unwrap_or(ExportFormat::Binary);
+    if let Err(err) = word_index.export(output, format, output.ends_with(".gz")) {
+        println!("Can not save {output}: {err}");
+    }
+}
+
+
+fn build_shards(filename: &str, options: index::IndexOptions, args: &[String]) {
+    // shard <manifest> [n] [records|terms]
+    let Some(manifest) = args.first() else {
+        println!("Usage: text_index <file> shard <manifest> [n] [records|terms]");
+        return;
+    };
+    let num_shards = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(NUM_SHARDS);
+    let strategy = match args.get(2).map(|s| s.parse::<ShardStrategy>()).transpose() {
+        Ok(strategy) => strategy.unwrap_or_default(),
+        Err(msg) => {
+            println!("{msg}");
+            return;
+        }
+    };
+    let sharded = match ShardedIndex::build_from_file(filename, options, num_shards, strategy) {
+        Ok(sharded) => sharded,
+        Err(err) => {
+            println!("Can not index {filename}: {err}");
+            return;
+        }
+    };
+    match sharded.save(manifest) {
+        Ok(()) => println!("Saved {} shards by {strategy} with {} words to {manifest}", sharded.shards().len(), sharded.word_count()),
+        Err(err) => println!("Can not save {manifest}: {err}")
+    }
+}
+
+
+fn search_shards(args: &[String]) {
+    // search-shards <manifest> <term> [k]: the matches, completions and fuzzy completions over all shards
+    let [manifest, term, ..] = args else {
+        println!("Usage: text_index <file> search-shards <manifest> <term> [k]");
+        return;
+    };
+    let k = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(10);
+    let max_dist = if term.chars().count() > 3 {2} else {1};
+    let sharded = match ShardedIndex::load(manifest) {
+        Ok(sharded) => sharded,
+        Err(err) => {
+            println!("Can not load {manifest}: {err}");
+            return;
+        }
+    };
+    println!("{} shards by {}, {} records", sharded.shards().len(), sharded.strategy(), sharded.record_count());
+    println!("'{term}' occurs {} times", sharded.find_matches(term).map_or(0, |locs| locs.len()));
+    for (name, completions) in [("Completions", sharded.find_completions(term, k)), ("Fuzzy completions", sharded.find_dl_completions(term, k, max_dist))] {
+        println!("{name} ({} of {} in {:?}):", completions.compl.len(), completions.total_count, completions.duration);
+        for Completion{completion, count} in &completions.compl {
+            println!("{count:>8}  {completion}");
+        }
+    }
+}
+
+
+fn print_index_summary(word_index: &index::WordIndex, notice: &str) {
+    // the first two rows of the console, 'notice' is shown behind the memory usage
+    print!("{}\r\n", format!("Index compressed {} records containing {} words to an index of {} items in {:?}", 
+        word_index.record_count, word_index.word_count, word_index.len(), word_index.duration).magenta()); 
+    print!("{}  {}\r\n", format!("Memory used: {}", word_index.memory_usage()).magenta(), notice.yellow());
+}
 
-    queue!(stdout,  cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::All));
 
-    // some other code ...
-    println!("{}", "Building the index".magenta()); 
+fn print_query_hits(word_index: &index::WordIndex, query: &Query, num_hits: usize, eol: &str) {
+    // the best records for the query with the locations of the terms; 'eol' is "\r\n" in raw mode
+    match word_index.search(query) {
+        Ok(hits) => {
+            print!("{} records match '{query}'{eol}", hits.len());
+            for hit in hits.iter().take(num_hits) {
+                let locs: Vec<String> = hit.locs.iter()
+                    .map(|loc| format!("{}{}", word_index.field_name(loc).map_or(String::new(), |f| format!("{f}:")), loc.word))
+                    .collect();
+                let path = hit.locs.first().and_then(|loc| word_index.section_path(loc)).map_or(String::new(), |p| format!("  {p}"));
+                print!("record {:>7}  score {:>6.2}  words {}{}{eol}", hit.record, hit.score, locs.join(" "), path);
+            }
+        },
+        Err(msg) => print!("{msg}{eol}")
+    }
+}
+
+
+fn search_file_via_console(mut word_index: index::WordIndex, watch: bool) -> Result<()> {
+    let mut stdout = stdout();
+
+    queue!(stdout,  cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::All))?;
 
     // move operation is performed only if we flush the buffer.
-    stdout.flush();
+    stdout.flush()?;
 
     execute!(stdout, EnableMouseCapture)?;
 
     {
-        let word_index = index::WordIndex::build_index(BufReader::new(File::open(filename).expect("Cannot open file.")));
+        word_index.build_phonetic_index(PhoneticAlgorithm::default());
+        // the structures that are built on first use, e.g. the collocations, must not print into the raw-mode screen
+        word_index.set_quiet(true);
         let num_completions = 10;
+        let edit_costs = EditCosts::default();
 
         // let res = word_index.bt.get("the").unwrap().len();
         // println!("The word 'the' has {} occurences", res);
 
         // return Ok(());
 
-        queue!(stdout,  cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::All));
-        println!("{}", format!("Index compressed {} records containing {} words to an index of {} items in {:?}", 
-            word_index.record_count, word_index.word_count, word_index.len(), word_index.duration).magenta()); 
+        // the watcher shares the index and hands over an updated one after every change of the source
+        let mut word_index = Arc::new(word_index);
+        let watcher = watch.then(|| IndexWatcher::start(Arc::clone(&word_index), POLL_INTERVAL));
+
+        queue!(stdout,  cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::All))?;
+        print_index_summary(&word_index, "");
 
         let mut row: u16 = 0;
         terminal::enable_raw_mode()?;
@@ -70,33 +289,138 @@ fn search_file_via_console(filename: &str) -> Result<()> {
         let mut search_str = String::default();
         let mut most_likely_completion = String::default();
         loop {
+            if let Some(event) = watcher.as_ref().and_then(|w| w.try_next()) {
+                queue!(stdout, SavePosition, cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::CurrentLine))?;
+                let notice = match event {
+                    WatchEvent::Reindexed{index, staleness, new_records} => {
+                        word_index = index;
+                        if staleness == Staleness::Appended { format!("Reindexed {new_records} new lines") } else { "The source changed, rebuilt the index".to_string() }
+                    },
+                    WatchEvent::Error(msg) => msg
+                };
+                queue!(stdout, cursor::MoveTo(0, 1), terminal::Clear(terminal::ClearType::CurrentLine), cursor::MoveTo(0, 0))?;
+                print_index_summary(&word_index, &notice);
+                execute!(stdout, cursor::RestorePosition)?;
+            }
             let status = get_input(&mut search_str, &most_likely_completion)?;
 
             match status {
             InputStatus::Quit => break,
             InputStatus::ShowResults => {
-                queue!(stdout, cursor::MoveTo(0, row));
-                // queue!(stdout, cursor::MoveTo(0, row), terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, row));
-                print!("{}", format!("Locations of the word '{}':\r\n", &search_str).magenta());
-
-                match word_index.find_matches(&search_str) {
-                    Some(occurrences) => {
-                        print!("\r\nObserved {} instances of '{}'\r\n", &occurrences.len(), &search_str);
-                        for (idx, oc) in occurrences.iter().enumerate() {
-                            print!("{}: {:?}\r\n", idx, oc);
+                queue!(stdout, cursor::MoveTo(0, row))?;
+                loop {
+                    // queue!(stdout, cursor::MoveTo(0, row), terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, row));
+                    // a query with fields or operators lists the matching records instead of the locations of a word
+                    // (in plain text a single term is a word, even when it contains a ':')
+                    let is_word = |q: &Query| matches!(q, Query::Term{field: None, ..}) || (word_index.fields().is_empty() && matches!(q, Query::Term{..}));
+                    if let Ok(query) = search_str.parse::<Query>().map_err(|_| ()).and_then(|q| if is_word(&q) { Err(()) } else { Ok(q) }) {
+                        print!("{}", format!("Records matching '{}':\r\n", &query).magenta());
+                        print_query_hits(&word_index, &query, NUM_HITS, "\r\n");
+                        break;
+                    }
+                    print!("{}", format!("Locations of the word '{}':\r\n", &search_str).magenta());
+
+                    match word_index.find_matches(&search_str) {
+                        Some(occurrences) => {
+                            print!("\r\nObserved {} instances of '{}'\r\n", &occurrences.len(), &search_str);
+                            for (idx, oc) in occurrences.iter().enumerate() {
+                                match word_index.field_name(oc) {
+                                    Some(field) => print!("{}: record {}, field '{}', word {}", idx, oc.line, field, oc.word),
+                                    None => print!("{}: {:?}", idx, oc)
+                                }
+                                match word_index.section_path(oc) {
+                                    Some(path) => print!("  {}\r\n", path.grey()),
+                                    None => print!("\r\n")
+                                }
+                            }
+                            if !word_index.sections().is_empty() {
+                                print!("{}", format!("\r\nSections with the most instances of '{}':\r\n", &search_str).magenta());
+                                for (path, count) in word_index.section_facets(&search_str, None).iter().take(NUM_FACETS) {
+                                    print!("{:>6}  {}\r\n", count, path.as_deref().unwrap_or("(no section)"));
+                                }
+                            }
+                            // the collocations scan the postings of the whole vocabulary, so they are only computed on request
+                            print!("\r\nc=Show the collocations of '{}', other keys=continue\r\n", &search_str);
+                            stdout.flush()?;
+                            if get_key()? == Some('c') {
+                                let measure = AssociationMeasure::LogLikelihood;
+                                print!("{}", format!("\r\nCollocations of '{}' ({}, window {}):\r\n", &search_str, measure, COLLOCATION_WINDOW).magenta());
+                                for (idx, Collocation{word, cooccurrences, score, ..}) in word_index.collocations(&search_str, COLLOCATION_WINDOW, measure, num_completions).iter().enumerate() {
+                                    print!("{}: '{}' co-occurs {} times (score {:.2})\r\n", idx + 1, word, cooccurrences, score);
+                                }
+                            }
+                        },
+                        None => {
+                            print!("No matches of '{}' found.\r\n", &search_str);
+                            let max_dist = if search_str.chars().count() > 3 {2} else {1};
+                            let suggestions = word_index.did_you_mean(&search_str, max_dist, 9);
+                            if !suggestions.is_empty() {
+                                print!("{}", "Did you mean:\r\n".green());
+                                for (idx, Suggestion{word, distance, count}) in suggestions.iter().enumerate() {
+                                    print!("{}: '{}' (distance {}) occurs  {} times\r\n", idx + 1, word, distance, count);
+                                }
+                                print!("Enter=Search first suggestion, 1-{}=Search that suggestion, other keys=quit program\r\n", suggestions.len());
+                                stdout.flush()?;
+                                if let Some(choice) = get_choice(suggestions.len())? {
+                                    search_str = suggestions[choice].word.clone();
+                                    print!("\r\n");
+                                    continue;
+                                }
+                            }
                         }
-                    },
-                    None => print!("No matches of '{}' found.\r\n", &search_str)
-                };
+                    };
+                    break
+                }
                 break
             },
             InputStatus::None => continue,
+            InputStatus::Changed if !word_index.fields().is_empty() && search_str.split_whitespace().next_back().is_some_and(|w| w.contains(':')) => {
+                // 'field:prefix' is completed with the words of that field; plain text has no fields, so a ':' is part of a word
+                let (context, current) = search_str.rsplit_once(char::is_whitespace).unwrap_or(("", search_str.as_str()));
+                let (field, prefix) = current.split_once(':').unwrap();
+                queue!(stdout,  cursor::MoveTo(0, 4), terminal::Clear(terminal::ClearType::FromCursorDown))?;
+                most_likely_completion = String::default();
+                match word_index.find_field_completions(field, prefix, num_completions) {
+                    Ok(compl_rec) => {
+                        print!("{}", format!("Completions in field '{}' completed in {:?}\r\n", field, compl_rec.duration).green());
+                        for (idx, Completion{completion, count}) in compl_rec.compl.iter().enumerate() {
+                            print!("{}: completion '{}:{}' occurs  {} times\r\n", idx + 1, field, completion, count);
+                        }
+                        if let Some(first) = compl_rec.compl.first() {
+                            let separator = if context.is_empty() { "" } else { " " };
+                            most_likely_completion = format!("{context}{separator}{field}:{}", first.completion);
+                        }
+                    },
+                    Err(msg) => print!("{msg}\r\n")
+                }
+                print!("\r\n");
+                stdout.flush().unwrap();
+                (_, row) = cursor::position().unwrap();
+            },
+            InputStatus::Changed if search_str.contains(char::is_whitespace) => {
+                // after a space the last word is completed with the words that are likely to follow the context
+                let (context, current) = search_str.rsplit_once(char::is_whitespace).unwrap();
+                let next_words: Vec<NextWord> = word_index.find_next_words(context, 2 * num_completions)
+                    .into_iter()
+                    .filter(|next| next.word.starts_with(current))
+                    .take(num_completions)
+                    .collect();
+                queue!(stdout,  cursor::MoveTo(0, 4), terminal::Clear(terminal::ClearType::FromCursorDown))?;
+                print!("{}", format!("Words likely to follow '{}':\r\n", context.trim_end()).green());
+                for (idx, NextWord{word, count, probability}) in next_words.iter().enumerate() {
+                    print!("{}: next word '{}' follows {} times ({:.1}%)\r\n", idx + 1, word, count, 100.0 * probability);
+                }
+                most_likely_completion = next_words.first().map_or(String::default(), |next| format!("{context} {}", next.word));
+                print!("\r\n");
+                stdout.flush().unwrap();
+                (_, row) = cursor::position().unwrap();
+            },
             InputStatus::Changed => {
-                let compl_rec = word_index.find_completions(&search_str, num_completions);
-                queue!(stdout,  cursor::MoveTo(0, 4), terminal::Clear(terminal::ClearType::FromCursorDown));
+                let compl_rec = word_index.find_trie_completions(&search_str, num_completions);
+                queue!(stdout,  cursor::MoveTo(0, 4), terminal::Clear(terminal::ClearType::FromCursorDown))?;
                 print!("{}", format!("Search for completions completed in {:?}\r\n", compl_rec.duration).green());
                 
-                if compl_rec.compl.len() > 0 {
+                if !compl_rec.compl.is_empty() {
                     most_likely_completion = compl_rec.compl[0].completion.clone();
                     for (idx, Completion{completion, count}) in compl_rec.compl.iter().enumerate() {
                         print!("{}: completion '{}' occurs  {} times\r\n", idx + 1, completion, count);
@@ -115,55 +439,272 @@ fn search_file_via_console(filename: &str) -> Result<()> {
                     }
                     let max_dist = if num_chars > 3 {2} else {1};
 
-                    execute!(stdout, SavePosition);
-                    let compl_rec_dl = word_index.find_dl_completions(&search_str, num_completions, max_dist);
-                    execute!(stdout, cursor::RestorePosition, terminal::Clear(terminal::ClearType::FromCursorDown));
+                    execute!(stdout, SavePosition)?;
+                    let compl_rec_dl = word_index.find_weighted_completions(&search_str, num_completions, max_dist as f64, &edit_costs);
+                    execute!(stdout, cursor::RestorePosition, terminal::Clear(terminal::ClearType::FromCursorDown))?;
 
-                    print!("{}", format!("Search for Damerau–Levenshtein (max_dist={}) completed in {:?}\r\n", max_dist, compl_rec_dl.duration).green());
-                    if compl_rec_dl.compl.len() > 0 {
+                    print!("{}", format!("Search for weighted Damerau–Levenshtein (max_dist={}) completed in {:?}\r\n", max_dist, compl_rec_dl.duration).green());
+                    if !compl_rec_dl.compl.is_empty() {
                         for (idx, Completion{completion, count}) in compl_rec_dl.compl.iter().enumerate() {
                             print!("{}: completion '{}' occurs  {} times\r\n", idx + 1, completion, count);
                         }
                     }
                     print!("\r\n");
                 }
+
+                if word_index.has_phonetic_index() {
+                    let compl_rec_ph = word_index.find_phonetic_completions(&search_str, num_completions);
+                    print!("{}", format!("Search for words that sound alike completed in {:?}\r\n", compl_rec_ph.duration).green());
+                    for (idx, Completion{completion, count}) in compl_rec_ph.compl.iter().enumerate() {
+                        print!("{}: sounds like '{}' occurs  {} times\r\n", idx + 1, completion, count);
+                    }
+                    print!("\r\n");
+                }
                 (_, row) = cursor::position().unwrap();
             }
         }
         }
-        terminal::disable_raw_mode();
-        word_index.save_index();
+        terminal::disable_raw_mode()?;
     } 
     execute!(stdout, DisableMouseCapture)?;
 
-    return Ok(()); //temporary
+    Ok(()) //temporary
     
 }
 
+fn show_collocations(filename: &str, options: index::IndexOptions, args: &[String]) {
+    // collocations <term> [window] [pmi|ll|t-score] [k] [min_freq]
+    let Some(term) = args.first() else {
+        println!("Usage: text_index <file> collocations <term> [window] [pmi|ll|t-score] [k] [min_freq]");
+        return;
+    };
+    let window = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(COLLOCATION_WINDOW);
+    let measure = match args.get(2).map(|s| s.parse::<AssociationMeasure>()).transpose() {
+        Ok(measure) => measure.unwrap_or_default(),
+        Err(msg) => {
+            println!("{msg}");
+            return;
+        }
+    };
+    let k = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(20);
+    let min_freq = args.get(4).and_then(|s| s.parse().ok()).unwrap_or(index::MIN_COLLOCATION_FREQ);
+
+    let word_index = match index::WordIndex::try_build_index_with(BufReader::new(File::open(filename).expect("Cannot open file.")), options) {
+        Ok(word_index) => word_index,
+        Err(err) => {
+            println!("Can not index {filename}: {err}");
+            return;
+        }
+    };
+    println!("Collocations of '{term}' ({measure}, window {window}, at least {min_freq} co-occurrences):");
+    println!("{:>4}  {:<20} {:>8} {:>8} {:>10}", "rank", "collocate", "co-occ", "freq", "score");
+    for (idx, c) in word_index.collocations_with(term, window, measure, k, min_freq).iter().enumerate() {
+        println!("{:>4}  {:<20} {:>8} {:>8} {:>10.3}", idx + 1, c.word, c.cooccurrences, c.frequency, c.score);
+    }
+}
+
+
+fn show_facets(filename: &str, options: index::IndexOptions, args: &[String]) {
+    // facets <term> [level] [section]
+    let Some(term) = args.first() else {
+        println!("Usage: text_index <file> --sections <preset|rules> facets <term> [level] [section]");
+        return;
+    };
+    let level = match args.get(1).map(|s| s.parse::<u8>()).transpose() {
+        Ok(level) => level,
+        Err(_) => {
+            println!("The level must be a number from 1 to 255, not '{}'", args[1]);
+            return;
+        }
+    };
+    let word_index = match index::WordIndex::try_build_index_with(BufReader::new(File::open(filename).expect("Cannot open file.")), options) {
+        Ok(word_index) => word_index,
+        Err(err) => {
+            println!("Can not index {filename}: {err}");
+            return;
+        }
+    };
+    if word_index.sections().is_empty() {
+        println!("No sections found, use --sections markdown|shakespeare|<level>=<regex>;...");
+        return;
+    }
+    if let Some(section) = args.get(2) {
+        // the occurrences in the sections that match, e.g. 'hamlet/act iii'
+        let locs = word_index.find_matches_in_section(term, section);
+        println!("{} instances of '{term}' in sections matching '{section}':", locs.len());
+        for loc in locs {
+            println!("record {:>7}, word {:>3}  {}", loc.line, loc.word, word_index.section_path(&loc).unwrap_or_default());
+        }
+        return;
+    }
+    println!("Instances of '{term}' per section{}:", level.map_or(String::new(), |l| format!(" at level {l}")));
+    println!("{:>8}  section", "count");
+    for (path, count) in word_index.section_facets(term, level) {
+        println!("{:>8}  {}", count, path.as_deref().unwrap_or("(no section)"));
+    }
+}
+
+
+fn run_query(filename: &str, options: index::IndexOptions, args: &[String]) {
+    // query <query> [k], e.g. query "title:storm AND body:king"
+    let Some(text) = args.first() else {
+        println!("Usage: text_index <file> query <query> [k]");
+        return;
+    };
+    let query = match text.parse::<Query>() {
+        Ok(query) => query,
+        Err(msg) => {
+            println!("{msg}");
+            return;
+        }
+    };
+    let k = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(NUM_HITS);
+    let word_index = match index::WordIndex::try_build_index_with(BufReader::new(File::open(filename).expect("Cannot open file.")), options) {
+        Ok(word_index) => word_index,
+        Err(err) => {
+            println!("Can not index {filename}: {err}");
+            return;
+        }
+    };
+    print_query_hits(&word_index, &query, k, "\n");
+}
+
+
+fn show_fields(filename: &str, options: index::IndexOptions) {
+    // fields: the statistics of the fields of the records
+    let word_index = match index::WordIndex::try_build_index_with(BufReader::new(File::open(filename).expect("Cannot open file.")), options) {
+        Ok(word_index) => word_index,
+        Err(err) => {
+            println!("Can not index {filename}: {err}");
+            return;
+        }
+    };
+    println!("{:<20} {:>10} {:>12} {:>10} {:>10}", "field", "records", "words", "distinct", "avg words");
+    for field in word_index.field_stats() {
+        let name = if field.name.is_empty() { "(text)" } else { field.name.as_str() };
+        println!("{:<20} {:>10} {:>12} {:>10} {:>10.2}", name, field.records, field.tokens, field.distinct_words, field.avg_length);
+    }
+}
+
+
+fn show_stats(filename: &str, options: index::IndexOptions, args: &[String]) {
+    // stats [text|json|csv]
+    let format = match args.first().map(|s| s.parse::<StatsFormat>()).transpose() {
+        Ok(format) => format.unwrap_or_default(),
+        Err(msg) => {
+            println!("{msg}");
+            return;
+        }
+    };
+    let word_index = match index::WordIndex::try_build_index_with(BufReader::new(File::open(filename).expect("Cannot open file.")), options) {
+        Ok(word_index) => word_index,
+        Err(err) => {
+            println!("Can not index {filename}: {err}");
+            return;
+        }
+    };
+    print!("{}", word_index.stats().render(format));
+}
+
+
+fn export_index(filename: &str, options: index::IndexOptions, args: &[String]) {
+    // export <path> [wc-json|csv|tsv|json|jsonl|binary] [--gzip], compressed with --gzip or when the path ends in '.gz'
+    let Some(path) = args.first() else {
+        println!("Usage: text_index <file> export <path> [wc-json|csv|tsv|json|jsonl|binary] [--gzip]");
+        return;
+    };
+    let compress = args.iter().any(|a| a == "--gzip") || path.ends_with(".gz");
+    let format = match args.get(1).filter(|a| !a.starts_with("--")).map(|s| s.parse::<ExportFormat>()) {
+        Some(Ok(format)) => format,
+        Some(Err(msg)) => {
+            println!("{msg}");
+            return;
+        },
+        None => ExportFormat::from_path(Path::new(path)).unwrap_or(ExportFormat::PostingsJson)
+    };
+    let word_index = match index::WordIndex::build_index_from_file(filename, options) {
+        Ok(word_index) => word_index,
+        Err(err) => {
+            println!("Can not index {filename}: {err}");
+            return;
+        }
+    };
+    if let Err(err) = word_index.export(path, format, compress) {
+        println!("Export to {path} failed: {err}");
+    }
+}
+
+
 fn main() -> Result<()> {
 
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
     let default_filename = "t8.shakespeare.txt".to_string();  // define as is will be a temporary inside unwrap_or
+    // --format text|csv[:columns]|jsonl[:fields]|markdown|html, guessed from the extension of the file by default
+    // --records line|paragraph|sentence|regex:<pattern>|window:<words>
+    // --sections markdown|shakespeare|<level>=<regex>;...
+    let (format, records, sections) = match (take_option::<SourceFormat>(&mut args, "--format"), take_option::<RecordBoundary>(&mut args, "--records"),
+            take_option::<SectionRules>(&mut args, "--sections")) {
+        (Ok(format), Ok(records), Ok(sections)) => (format, records.unwrap_or_default(), sections.unwrap_or_default()),
+        (Err(msg), _, _) | (_, Err(msg), _) | (_, _, Err(msg)) => {
+            println!("{msg}");
+            return Ok(());
+        }
+    };
     let filename = args.get(1).unwrap_or(&default_filename);
+    let format = format.unwrap_or_else(|| SourceFormat::from_path(Path::new(filename)));
+    let options = index::IndexOptions{format, records, sections, ..Default::default()};
+
+    match args.get(2).map(|s| s.as_str()) {
+        Some("collocations") => show_collocations(filename, options, &args[3..]),
+        Some("stats") => show_stats(filename, options, &args[3..]),
+        Some("facets") => show_facets(filename, options, &args[3..]),
+        Some("query") => run_query(filename, options, &args[3..]),
+        Some("fields") => show_fields(filename, options),
+        Some("export") => export_index(filename, options, &args[3..]),
+        Some("check") => check_index(&args[3..]),
+        Some("delete") => delete_records(&args[3..]),
+        Some("merge") => merge_indexes(&args[3..]),
+        Some("shard") => build_shards(filename, options, &args[3..]),
+        Some("search-shards") => search_shards(&args[3..]),
+        _ => {
+            // text_index <file> [--index <saved index>] [--rebuild-if-stale] [--watch]
+            let saved_index = args.iter().position(|a| a == "--index").and_then(|pos| args.get(pos + 1));
+            let rebuild_if_stale = args.iter().any(|a| a == "--rebuild-if-stale");
+            let watch = args.iter().any(|a| a == "--watch");
+            if let Some(word_index) = open_index(filename, options, saved_index.map(|s| s.as_str()), rebuild_if_stale)? {
+                search_file_via_console(word_index, watch)?;
+            }
+        }
+    }
+
+    Ok(())
+}
 
-    search_file_via_console(filename);
 
-    return Ok(());
+fn take_option<T: std::str::FromStr<Err = String>>(args: &mut Vec<String>, name: &str) -> std::result::Result<Option<T>, String> {
+    // remove '<name> <value>' from the arguments and parse the value
+    let Some(pos) = args.iter().position(|a| a == name) else { return Ok(None) };
+    if pos + 1 >= args.len() {
+        return Err(format!("{name} needs a value"));
+    }
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    value.parse().map(Some)
 }
 
 
-fn get_input(search_str: &mut String, completion: &String) -> crossterm::Result<InputStatus> {
+fn get_input(search_str: &mut String, completion: &str) -> crossterm::Result<InputStatus> {
     // prints the key-codes in an event-loop. Als catches CTRL-C so use <ESC> to get out.
     let mut stdout = stdout();
 
-    queue!(stdout,  cursor::MoveTo(0, 2), terminal::Clear(terminal::ClearType::CurrentLine));
+    queue!(stdout,  cursor::MoveTo(0, 2), terminal::Clear(terminal::ClearType::CurrentLine))?;
     print!("Special commands: Tab=Accept completion, Enter=Search-locations, CTRL-C=quit program\r\n");
-    let completion_suffix: String = completion.chars().skip((&search_str).chars().count()).collect();
+    let completion_suffix: String = completion.chars().skip(search_str.chars().count()).collect();
     let sstr = search_str.clone();
     let len_compl_suffix = completion_suffix.len() as u16;
     //println!("{}{}{}", "SEARCH: ".bold(), search_str.as_ref::<String>().blue(), completion_suffix.grey());
     print!("{}{}{}", "SEARCH: ".bold(), sstr.blue(), completion_suffix.grey());
-    queue!(stdout, cursor::MoveLeft(len_compl_suffix));
+    queue!(stdout, cursor::MoveLeft(len_compl_suffix))?;
     stdout.flush().unwrap();
 
 
@@ -180,7 +721,7 @@ fn get_input(search_str: &mut String, completion: &String) -> crossterm::Result<
             Event::Key(event) if event == KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL) => return Ok(InputStatus::Quit),
             Event::Key(event) if event == KeyCode::Esc.into() =>  return Ok(InputStatus::Quit),
             Event::Key(event) if event == KeyCode::Tab.into() =>  {
-                search_str.push_str(&completion.chars().skip((&search_str).chars().count()).collect::<String>());
+                search_str.push_str(&completion.chars().skip(search_str.chars().count()).collect::<String>());
                 return Ok(InputStatus::Changed);
             },
             Event::Key(event) if event == KeyCode::Enter.into() =>  {
@@ -204,15 +745,41 @@ fn get_input(search_str: &mut String, completion: &String) -> crossterm::Result<
                     return Ok(InputStatus::None);
                 }
             },
-            Event::Mouse(event) => (), //println!("{:?}", event),
+            Event::Mouse(_event) => (), //println!("{:?}", event),
 //                #[cfg(feature = "bracketed-paste")]
-            Event::Paste(data) => (), //println!("Pasting: {}", data),
-            Event::Resize(width, height) => ()
+            Event::Paste(_data) => (), //println!("Pasting: {}", data),
+            Event::Resize(_width, _height) => ()
         }
         queue!(stdout, terminal::Clear(terminal::ClearType::FromCursorDown), cursor::MoveLeft(len_compl_suffix)).unwrap();
         stdout.flush().unwrap();
     }
 
-    return Ok(InputStatus::None);
+    Ok(InputStatus::None)
 }
 
+
+fn get_key() -> crossterm::Result<Option<char>> {
+    // wait for a single key press and return its character, if it has one.
+    loop {
+        if let Event::Key(KeyEvent{code, ..}) = read()? {
+            return Ok(match code {
+                KeyCode::Char(ch) => Some(ch),
+                _ => None
+            });
+        }
+    }
+}
+
+
+fn get_choice(num_choices: usize) -> crossterm::Result<Option<usize>> {
+    // wait for a single key to pick one of 'num_choices' numbered options. Enter picks the first one, any other key none.
+    loop {
+        if let Event::Key(KeyEvent{code, ..}) = read()? {
+            return Ok(match code {
+                KeyCode::Enter => Some(0),
+                KeyCode::Char(ch) => ch.to_digit(10).map(|d| d as usize).filter(|&d| d >= 1 && d <= num_choices).map(|d| d - 1),
+                _ => None
+            });
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// Heap memory accounting for the index.
+//
+// 'mem::size_of_val' only measures the fixed-size header of a struct, not the heap allocations behind it. MemoryUsage adds up
+// the allocations of the keys, the posting vectors (including their unused capacity) and an estimate of the BTreeMap nodes.
+// The node estimate follows the layout of the std BTreeMap (B = 6): a leaf stores up to 11 keys and values, an internal node
+// additionally 12 child pointers.
+
+use std::fmt;
+use std::mem;
+use serde::Serialize;
+
+
+const BTREE_CAPACITY: usize = 11;
+
+
+pub fn btree_node_estimate<K, V>(len: usize) -> usize {
+    // Estimated bytes of the nodes of a BTreeMap<K, V> with 'len' entries, assuming nodes are on average 2/3 filled.
+    if len == 0 {
+        return 0;
+    }
+    let leaf_size = BTREE_CAPACITY * (mem::size_of::<K>() + mem::size_of::<V>()) + mem::size_of::<usize>() + 2 * mem::size_of::<u16>();
+    let internal_size = leaf_size + (BTREE_CAPACITY + 1) * mem::size_of::<usize>();
+    let avg_fill = BTREE_CAPACITY * 2 / 3;
+    let leaves = len.div_ceil(avg_fill);
+    let internals = leaves.div_ceil(avg_fill + 1);
+    leaves * leaf_size + internals * internal_size
+}
+
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MemoryUsage {
+    pub keys: usize,            // heap bytes of the key strings
+    pub postings: usize,        // heap bytes of the posting vectors that are in use
+    pub postings_spare: usize,  // unused capacity of the posting vectors
+    pub tree_nodes: usize,      // estimate of the BTreeMap nodes, which hold the String and Vec headers
+    pub auxiliary: usize        // optional structures such as the BK-tree, the deletion index and the phonetic index
+}
+
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.keys + self.postings + self.postings_spare + self.tree_nodes + self.auxiliary
+    }
+}
+
+
+fn mib(bytes: usize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1} MiB (keys {:.1}, postings {:.1} + {:.1} spare, tree nodes {:.1}, auxiliary {:.1})",
+            mib(self.total()), mib(self.keys), mib(self.postings), mib(self.postings_spare), mib(self.tree_nodes), mib(self.auxiliary))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{btree_node_estimate, MemoryUsage};
+
+    #[test]
+    fn test_memory_usage() {
+        assert_eq!(btree_node_estimate::<String, Vec<u8>>(0), 0);
+        let small = btree_node_estimate::<String, Vec<u8>>(10);
+        let large = btree_node_estimate::<String, Vec<u8>>(10_000);
+        assert!(small > 0 && large > small);
+        // every entry holds a 24-byte String and a 24-byte Vec header, plus the overhead of partially filled nodes
+        assert!(large / 10_000 > 48 && large / 10_000 < 100);
+
+        let usage = MemoryUsage{ keys: 1024 * 1024, postings: 2 * 1024 * 1024, postings_spare: 0, tree_nodes: 0, auxiliary: 0 };
+        assert_eq!(usage.total(), 3 * 1024 * 1024);
+        assert!(usage.to_string().starts_with("3.0 MiB"));
+    }
+}
@@ -0,0 +1,154 @@
+// Bigram and trigram counts for next-word prediction.
+//
+// For every line the model counts which word follows each single word (bigrams) and each pair of words (trigrams). The
+// contexts are stored as one string with the words separated by a space, which is unambiguous as the words of the index
+// never contain whitespace. A prediction ranks the followers of the last two words of the context by their conditional
+// frequency count(context, word) / count(context) and backs off to the followers of the last word when fewer than k
+// followers are known.
+
+use std::collections::HashMap;
+use std::mem;
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NGramModel {
+    followers: HashMap<String, HashMap<String, u32>>,  // context of one or two words -> next word -> count
+    totals: HashMap<String, u32>                       // context -> number of times it was followed by a word
+}
+
+
+impl NGramModel {
+    pub fn new() -> Self {
+        NGramModel::default()
+    }
+
+    pub fn num_contexts(&self) -> usize {
+        self.followers.len()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        // estimate: the hash tables store a control byte per bucket next to the key and value
+        let map_size = |capacity: usize, entry: usize| capacity * (entry + 1);
+        map_size(self.followers.capacity(), mem::size_of::<(String, HashMap<String, u32>)>())
+            + self.followers.iter().map(|(k, v)| k.capacity() + map_size(v.capacity(), mem::size_of::<(String, u32)>())
+                + v.keys().map(|w| w.capacity()).sum::<usize>()).sum::<usize>()
+            + map_size(self.totals.capacity(), mem::size_of::<(String, u32)>())
+            + self.totals.keys().map(|k| k.capacity()).sum::<usize>()
+    }
+
+    pub fn add_line<S: AsRef<str>>(&mut self, words: &[S]) {
+        // count the bigrams and trigrams of the words of a single line
+        for i in 1..words.len() {
+            let next = words[i].as_ref();
+            self.count(words[i - 1].as_ref().to_string(), next);
+            if i >= 2 {
+                self.count(format!("{} {}", words[i - 2].as_ref(), words[i - 1].as_ref()), next);
+            }
+        }
+    }
+
+    pub fn remove_line<S: AsRef<str>>(&mut self, words: &[S]) {
+        // subtract the counts of a line that was added with 'add_line', e.g. of a deleted record
+        for i in 1..words.len() {
+            let next = words[i].as_ref();
+            self.uncount(words[i - 1].as_ref().to_string(), next);
+            if i >= 2 {
+                self.uncount(format!("{} {}", words[i - 2].as_ref(), words[i - 1].as_ref()), next);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: NGramModel) {
+        // add the counts of 'other', e.g. of an index of other text (see 'WordIndex::merge')
+        for (context, followers) in other.followers {
+            let merged = self.followers.entry(context).or_default();
+            for (next, count) in followers {
+                *merged.entry(next).or_default() += count;
+            }
+        }
+        for (context, total) in other.totals {
+            *self.totals.entry(context).or_default() += total;
+        }
+    }
+
+    fn count(&mut self, context: String, next: &str) {
+        *self.totals.entry(context.clone()).or_default() += 1;
+        *self.followers.entry(context).or_default().entry(next.to_string()).or_default() += 1;
+    }
+
+    fn uncount(&mut self, context: String, next: &str) {
+        // the contexts and followers whose count drops to zero are removed, so they are not predicted anymore
+        if let Some(total) = self.totals.get_mut(&context) {
+            *total = total.saturating_sub(1);
+            if *total == 0 {
+                self.totals.remove(&context);
+            }
+        }
+        if let Some(followers) = self.followers.get_mut(&context) {
+            if let Some(count) = followers.get_mut(next) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    followers.remove(next);
+                }
+            }
+            if followers.is_empty() {
+                self.followers.remove(&context);
+            }
+        }
+    }
+
+    pub fn predict(&self, context: &[&str], k: usize) -> Vec<(&str, u32, f64)> {
+        // The k most likely words to follow 'context' as (word, count, conditional frequency), most likely first.
+        // Trigram predictions come first; the remaining places are filled with bigram predictions.
+        let mut predictions: Vec<(&str, u32, f64)> = Vec::with_capacity(k);
+        let contexts = match context {
+            [.., w1, w2] => vec![format!("{w1} {w2}"), w2.to_string()],
+            [w] => vec![w.to_string()],
+            [] => vec![]
+        };
+        for ctx in contexts {
+            if predictions.len() >= k {
+                break;
+            }
+            let Some(followers) = self.followers.get(&ctx) else { continue };
+            let total = self.totals[&ctx] as f64;
+            let mut candidates: Vec<(&str, u32, f64)> = followers.iter()
+                .filter(|(word, _)| !predictions.iter().any(|(p, _, _)| p == word))
+                .map(|(word, &count)| (word.as_str(), count, count as f64 / total))
+                .collect();
+            candidates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            candidates.truncate(k - predictions.len());
+            predictions.extend(candidates);
+        }
+        predictions
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::NGramModel;
+
+    #[test]
+    fn test_predict_with_backoff() {
+        let mut model = NGramModel::new();
+        for line in ["to be or not to be", "to be the best", "not to be or", "to see the light", "be quiet"] {
+            model.add_line(&line.split(' ').collect::<Vec<_>>());
+        }
+        // "to be" was followed by "or" twice and by "the" once
+        assert_eq!(model.predict(&["to", "be"], 2), vec![("or", 2, 2.0 / 3.0), ("the", 1, 1.0 / 3.0)]);
+        // backing off to "be", which was also followed by "quiet"
+        assert_eq!(model.predict(&["to", "be"], 5), vec![("or", 2, 2.0 / 3.0), ("the", 1, 1.0 / 3.0), ("quiet", 1, 0.25)]);
+        assert_eq!(model.predict(&["we", "be"], 1), vec![("or", 2, 0.5)]);
+        assert_eq!(model.predict(&["to"], 2), vec![("be", 4, 0.8), ("see", 1, 0.2)]);
+        assert!(model.predict(&["light"], 2).is_empty());
+        assert!(model.predict(&[], 2).is_empty());
+
+        // removing a line undoes its counts
+        let before = model.clone();
+        model.add_line(&["to", "go", "home"]);
+        model.remove_line(&["to", "go", "home"]);
+        assert_eq!(model, before);
+    }
+}
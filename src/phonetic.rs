@@ -0,0 +1,214 @@
+// Phonetic keys (Soundex and Metaphone) for words that sound alike.
+//
+// Misspelled names ("Desdemonah", "Iachymo") are often far away in edit distance, while they map to the same phonetic key.
+// The PhoneticIndex groups the vocabulary by key, so all words that sound like the query can be found with a single lookup.
+// Both algorithms only consider the ASCII letters of a word and ignore case.
+
+use std::collections::HashMap;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PhoneticAlgorithm {
+    Soundex,
+    #[default]
+    Metaphone
+}
+
+
+impl PhoneticAlgorithm {
+    pub fn key(&self, word: &str) -> String {
+        match self {
+            PhoneticAlgorithm::Soundex => soundex(word),
+            PhoneticAlgorithm::Metaphone => metaphone(word)
+        }
+    }
+}
+
+
+fn ascii_letters(word: &str) -> Vec<u8> {
+    word.bytes().filter(u8::is_ascii_alphabetic).map(|b| b.to_ascii_uppercase()).collect()
+}
+
+
+pub fn soundex(word: &str) -> String {
+    // American Soundex: the first letter followed by three digits. Letters with the same code separated by 'H' or 'W' are coded once.
+    fn code(ch: u8) -> Option<u8> {
+        match ch {
+            b'B' | b'F' | b'P' | b'V' => Some(b'1'),
+            b'C' | b'G' | b'J' | b'K' | b'Q' | b'S' | b'X' | b'Z' => Some(b'2'),
+            b'D' | b'T' => Some(b'3'),
+            b'L' => Some(b'4'),
+            b'M' | b'N' => Some(b'5'),
+            b'R' => Some(b'6'),
+            _ => None
+        }
+    }
+
+    let letters = ascii_letters(word);
+    let Some(&first) = letters.first() else { return String::new() };
+    let mut key = vec![first];
+    let mut prev = code(first);
+    for &ch in &letters[1..] {
+        if key.len() == 4 {
+            break;
+        }
+        let curr = code(ch);
+        if let Some(digit) = curr.filter(|_| curr != prev) {
+            key.push(digit);
+        }
+        if ch != b'H' && ch != b'W' {
+            prev = curr;
+        }
+    }
+    key.resize(4, b'0');
+    String::from_utf8(key).unwrap()
+}
+
+
+pub fn metaphone(word: &str) -> String {
+    // The original Metaphone algorithm of Lawrence Philips. The result uses '0' for the 'th' sound and 'X' for 'sh'.
+    let mut w = ascii_letters(word);
+    if w.is_empty() {
+        return String::new();
+    }
+    // initial letter exceptions
+    match (w[0], w.get(1).copied()) {
+        (b'A', Some(b'E')) | (b'G', Some(b'N')) | (b'K', Some(b'N')) | (b'P', Some(b'N')) | (b'W', Some(b'R')) => { w.remove(0); },
+        (b'X', _) => w[0] = b'S',
+        (b'W', Some(b'H')) => { w.remove(1); },
+        _ => ()
+    }
+
+    let is_vowel = |ch: Option<u8>| matches!(ch, Some(b'A' | b'E' | b'I' | b'O' | b'U'));
+    let at = |i: isize| if i >= 0 { w.get(i as usize).copied() } else { None };
+    let mut key = Vec::new();
+    for (i, &ch) in w.iter().enumerate() {
+        let (prev, next, next2) = (at(i as isize - 1), at(i as isize + 1), at(i as isize + 2));
+        if prev == Some(ch) && ch != b'C' {
+            continue;  // skip double letters
+        }
+        match ch {
+            b'A' | b'E' | b'I' | b'O' | b'U' => if i == 0 { key.push(ch) },
+            b'B' => if !(prev == Some(b'M') && next.is_none()) { key.push(b'B') },
+            b'C' => {
+                if next == Some(b'I') && next2 == Some(b'A') {
+                    key.push(b'X');
+                } else if next == Some(b'H') {
+                    key.push(if prev == Some(b'S') { b'K' } else { b'X' });
+                } else if matches!(next, Some(b'I' | b'E' | b'Y')) {
+                    if prev != Some(b'S') {
+                        key.push(b'S');
+                    }
+                } else {
+                    key.push(b'K');
+                }
+            },
+            b'D' => key.push(if next == Some(b'G') && matches!(next2, Some(b'E' | b'Y' | b'I')) { b'J' } else { b'T' }),
+            b'G' => {
+                let silent = (next == Some(b'H') && !(next2.is_none() || is_vowel(next2)))
+                    || (next == Some(b'N') && (next2.is_none() || (next2 == Some(b'E') && at(i as isize + 3) == Some(b'D'))))
+                    || (prev == Some(b'D') && matches!(next, Some(b'E' | b'Y' | b'I')));
+                if !silent {
+                    key.push(if matches!(next, Some(b'I' | b'E' | b'Y')) && prev != Some(b'G') { b'J' } else { b'K' });
+                }
+            },
+            b'H' => if !matches!(prev, Some(b'C' | b'S' | b'P' | b'T' | b'G')) && (!is_vowel(prev) || is_vowel(next)) { key.push(b'H') },
+            b'K' => if prev != Some(b'C') { key.push(b'K') },
+            b'P' => key.push(if next == Some(b'H') { b'F' } else { b'P' }),
+            b'Q' => key.push(b'K'),
+            b'S' => key.push(if next == Some(b'H') || (next == Some(b'I') && matches!(next2, Some(b'O' | b'A'))) { b'X' } else { b'S' }),
+            b'T' => {
+                if next == Some(b'I') && matches!(next2, Some(b'O' | b'A')) {
+                    key.push(b'X');
+                } else if next == Some(b'H') {
+                    key.push(b'0');
+                } else if !(next == Some(b'C') && next2 == Some(b'H')) {
+                    key.push(b'T');
+                }
+            },
+            b'V' => key.push(b'F'),
+            b'W' | b'Y' => if is_vowel(next) { key.push(ch) },
+            b'X' => key.extend_from_slice(b"KS"),
+            b'Z' => key.push(b'S'),
+            _ => key.push(ch)  // F, J, L, M, N, R
+        }
+    }
+    String::from_utf8(key).unwrap()
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct PhoneticIndex {
+    algorithm: PhoneticAlgorithm,
+    keys: HashMap<String, Vec<String>>
+}
+
+
+impl PhoneticIndex {
+    pub fn build<'a, I: IntoIterator<Item = &'a String>>(words: I, algorithm: PhoneticAlgorithm) -> Self {
+        let mut index = PhoneticIndex{ algorithm, keys: HashMap::new() };
+        for word in words {
+            index.insert(word);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, word: &str) {
+        let key = self.algorithm.key(word);
+        if !key.is_empty() {
+            self.keys.entry(key).or_default().push(word.to_string());
+        }
+    }
+
+    pub fn algorithm(&self) -> PhoneticAlgorithm {
+        self.algorithm
+    }
+
+    pub fn heap_size(&self) -> usize {
+        // estimate: the hash table stores a control byte per bucket next to the key and value
+        self.keys.capacity() * (std::mem::size_of::<(String, Vec<String>)>() + 1)
+            + self.keys.iter().map(|(k, v)| k.capacity() + v.capacity() * std::mem::size_of::<String>() + v.iter().map(|w| w.capacity()).sum::<usize>()).sum::<usize>()
+    }
+
+    pub fn sounds_like(&self, word: &str) -> &[String] {
+        // all words of the vocabulary with the same phonetic key as 'word'
+        self.keys.get(&self.algorithm.key(word)).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{metaphone, soundex, PhoneticAlgorithm, PhoneticIndex};
+
+    #[test]
+    fn test_soundex() {
+        assert_eq!(soundex("Robert"), "R163");
+        assert_eq!(soundex("Rupert"), "R163");
+        assert_eq!(soundex("Rubin"), "R150");
+        assert_eq!(soundex("Ashcraft"), "A261");
+        assert_eq!(soundex("Tymczak"), "T522");
+        assert_eq!(soundex("Pfister"), "P236");
+        assert_eq!(soundex("A"), "A000");
+        assert_eq!(soundex("--"), "");
+    }
+
+    #[test]
+    fn test_metaphone() {
+        assert_eq!(metaphone("Thomas"), "0MS");
+        assert_eq!(metaphone("knight"), "NT");
+        assert_eq!(metaphone("Desdemona"), metaphone("Desdemonah"));
+        assert_eq!(metaphone("Iachimo"), metaphone("Iachymo"));
+        assert_eq!(metaphone("phantom"), "FNTM");
+        assert_eq!(metaphone("Xerxes"), "SRKSS");
+        assert_eq!(metaphone("church"), "XRX");
+    }
+
+    #[test]
+    fn test_phonetic_index() {
+        let words: Vec<String> = ["Desdemona", "Desdemon", "demon", "Iago", "Othello"].iter().map(|s| s.to_string()).collect();
+        let index = PhoneticIndex::build(&words, PhoneticAlgorithm::Metaphone);
+        assert_eq!(index.sounds_like("Desdemonah"), &["Desdemona".to_string(), "Desdemon".to_string()]);
+        assert!(index.sounds_like("zzz").is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+// Compressed posting lists.
+//
+// A Vec<WordLoc> takes 8 bytes per occurrence. As the locations of a word are sorted, the line numbers are stored as the
+// difference with the previous location and both the line delta and the word position are written as variable-byte integers
+// (7 bits per byte, the high bit marks that more bytes follow). Most locations then fit in 2 or 3 bytes. The field of a
+// location is only stored when a location of the list is not in the first field (see adapter.rs).
+//
+// The postings are split in blocks of BLOCK_SIZE locations. For every block a skip entry stores the first line and the byte
+// offset of the block, so decoding can start at the block that contains a given line instead of at the start of the list.
+// An intersection of posting lists (see 'FrozenWordIndex::records_with_all') uses this to jump over the blocks between
+// the records that it looks for.
+
+use serde::{Deserialize, Serialize};
+use crate::index::WordLoc;
+
+const BLOCK_SIZE: usize = 128;
+
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct SkipEntry {
+    line: u32,   // line of the first location in the block
+    offset: u32  // byte offset of the block in 'data'
+}
+
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CompressedPostings {
+    data: Vec<u8>,
+    skips: Vec<SkipEntry>,
+    len: u32,
+    #[serde(default)]
+    with_fields: bool  // every location is followed by its field
+}
+
+
+fn write_varint(data: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        data.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+
+fn read_varint(data: &[u8], pos: &mut usize) -> u32 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+
+impl CompressedPostings {
+    pub fn from_slice(locations: &[WordLoc]) -> Self {
+        // Compress a sorted list of locations.
+        let with_fields = locations.iter().any(|loc| loc.field > 0);
+        let mut postings = CompressedPostings{ len: locations.len() as u32, with_fields, ..Default::default() };
+        for block in locations.chunks(BLOCK_SIZE) {
+            postings.skips.push(SkipEntry{ line: block[0].line, offset: postings.data.len() as u32 });
+            let mut prev_line = block[0].line;
+            for loc in block {
+                write_varint(&mut postings.data, loc.line - prev_line);
+                write_varint(&mut postings.data, loc.word as u32);
+                if with_fields {
+                    write_varint(&mut postings.data, loc.field as u32);
+                }
+                prev_line = loc.line;
+            }
+        }
+        postings.data.shrink_to_fit();
+        postings.skips.shrink_to_fit();
+        postings
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.data.capacity() + self.skips.capacity() * std::mem::size_of::<SkipEntry>()
+    }
+
+    pub fn iter(&self) -> PostingsIter<'_> {
+        PostingsIter{ postings: self, block: 0, pos: 0, idx: 0, line: 0 }
+    }
+
+    pub fn iter_from_line(&self, line: u32) -> PostingsIter<'_> {
+        // Decode the locations at or after 'line', starting at the block that can contain it.
+        let mut iter = self.iter();
+        iter.seek(line);
+        iter
+    }
+}
+
+
+pub struct PostingsIter<'a> {
+    postings: &'a CompressedPostings,
+    block: usize,
+    pos: usize,   // byte position in the data
+    idx: usize,   // index of the next location
+    line: u32     // line of the previous location
+}
+
+
+impl PostingsIter<'_> {
+    pub fn seek(&mut self, line: u32) {
+        // Move forward to the first location at or after 'line'. When that is beyond the current block, decoding continues
+        // at the last block that starts before 'line', so the blocks in between are not decoded.
+        let skips = &self.postings.skips;
+        let next_block = self.idx.div_ceil(BLOCK_SIZE);
+        let ahead = skips.get(next_block..).map_or(0, |rest| rest.partition_point(|skip| skip.line < line));
+        if ahead > 0 {
+            let block = next_block + ahead - 1;
+            *self = PostingsIter{ postings: self.postings, block, pos: skips[block].offset as usize, idx: block * BLOCK_SIZE, line: 0 };
+        }
+        while self.peek_line().is_some_and(|l| l < line) {
+            self.next();
+        }
+    }
+
+    pub fn peek_line(&self) -> Option<u32> {
+        // the line of the next location, without decoding it
+        if self.idx >= self.postings.len() {
+            return None;
+        }
+        let mut pos = self.pos;
+        let delta = read_varint(&self.postings.data, &mut pos);
+        Some(if self.idx.is_multiple_of(BLOCK_SIZE) { self.postings.skips[self.idx / BLOCK_SIZE].line } else { self.line + delta })
+    }
+}
+
+
+impl Iterator for PostingsIter<'_> {
+    type Item = WordLoc;
+
+    fn next(&mut self) -> Option<WordLoc> {
+        if self.idx >= self.postings.len() {
+            return None;
+        }
+        if self.idx.is_multiple_of(BLOCK_SIZE) {
+            self.block = self.idx / BLOCK_SIZE;
+            self.line = self.postings.skips[self.block].line;
+        }
+        let data = &self.postings.data;
+        self.line += read_varint(data, &mut self.pos);
+        let word = read_varint(data, &mut self.pos) as u16;
+        let field = if self.postings.with_fields { read_varint(data, &mut self.pos) as u16 } else { 0 };
+        self.idx += 1;
+        Some(WordLoc{ line: self.line, field, word })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.postings.len() - self.idx;
+        (remaining, Some(remaining))
+    }
+}
+
+
+impl ExactSizeIterator for PostingsIter<'_> {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::CompressedPostings;
+    use crate::index::WordLoc;
+
+    #[test]
+    fn test_roundtrip_and_skip() {
+        let locations: Vec<WordLoc> = (0..1000u32).map(|i| WordLoc{ line: i * 3 + (i % 7) / 3, field: 0, word: (i % 11) as u16 }).collect();
+        let postings = CompressedPostings::from_slice(&locations);
+        assert_eq!(postings.len(), locations.len());
+        assert_eq!(postings.iter().collect::<Vec<_>>(), locations);
+        assert!(postings.heap_size() < locations.len() * std::mem::size_of::<WordLoc>() / 2);
+
+        for line in [0, 1, 383, 384, 385, 1500, 2996, 5000] {
+            let expected: Vec<WordLoc> = locations.iter().copied().filter(|loc| loc.line >= line).collect();
+            assert_eq!(postings.iter_from_line(line).collect::<Vec<_>>(), expected, "from line {line}");
+        }
+        // seeking forward from the middle of a block, over several blocks and within a block
+        let mut iter = postings.iter();
+        iter.nth(200);
+        for line in [700, 701, 2000, 2001, 2999] {
+            iter.seek(line);
+            assert_eq!(iter.peek_line(), locations.iter().map(|loc| loc.line).find(|&l| l >= line), "seek to line {line}");
+        }
+        iter.seek(5000);
+        assert_eq!((iter.peek_line(), iter.next()), (None, None));
+        assert_eq!(CompressedPostings::from_slice(&[]).iter().count(), 0);
+        let big = [WordLoc{ line: 0, field: 0, word: u16::MAX }, WordLoc{ line: u32::MAX, field: 0, word: 0 }];
+        assert_eq!(CompressedPostings::from_slice(&big).iter().collect::<Vec<_>>(), big);
+
+        let fields: Vec<WordLoc> = locations.iter().map(|&loc| WordLoc{ field: (loc.line % 3) as u16, ..loc }).collect();
+        let postings = CompressedPostings::from_slice(&fields);
+        assert_eq!(postings.iter().collect::<Vec<_>>(), fields);
+        assert_eq!(postings.iter_from_line(1500).next(), fields.iter().copied().find(|loc| loc.line >= 1500));
+    }
+}
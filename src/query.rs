@@ -0,0 +1,212 @@
+// Boolean queries over the records of an index, with terms that can be restricted to a field.
+//
+// Syntax: terms combined with AND, OR and NOT (in capitals) and parentheses, e.g. 'title:storm AND body:king' or
+// 'storm OR (tempest AND NOT title:lear)'. AND binds stronger than OR, and terms without an operator between them are
+// combined with AND. A term 'field:word' only matches the word in that field (see adapter.rs for the fields of a source), a
+// term without a field matches the word in any field. Words are matched exactly, like 'WordIndex::find_matches'.
+//
+// A query matches records. The matching records are ranked by the sum of the scores of the terms that match in the record.
+// The score of a term in a field is the BM25 weight without length normalisation (b = 0), as the length of a field in a
+// single record is not stored: idf * tf * (k1 + 1) / (tf + k1), with the idf computed from the number of records that
+// contain the word in that field and the number of records that have the field (see 'FieldStats').
+
+use std::fmt;
+use std::str::FromStr;
+use crate::index::WordLoc;
+
+
+// saturation of the term frequency in the score
+pub const BM25_K1: f64 = 1.2;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    Term{ field: Option<String>, word: String },
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>)
+}
+
+
+// statistics of a field over all records, e.g. for ranking
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldStats {
+    pub name: String,           // empty for the single field of plain text
+    pub records: usize,         // number of records with at least one word in the field
+    pub tokens: usize,          // number of words in the field over all records
+    pub distinct_words: usize,
+    pub avg_length: f64         // tokens / records
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryHit {
+    pub record: u32,
+    pub score: f64,
+    pub locs: Vec<WordLoc>  // the locations of the terms that matched, in order
+}
+
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s);
+        let mut parser = Parser{ tokens: &tokens, pos: 0 };
+        let query = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(query),
+            Some(token) => Err(format!("unexpected '{token}' in the query '{s}'"))
+        }
+    }
+}
+
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, parts: &[Query], op: &str| {
+            for (idx, part) in parts.iter().enumerate() {
+                if idx > 0 {
+                    write!(f, " {op} ")?;
+                }
+                match part {
+                    Query::And(_) | Query::Or(_) => write!(f, "({part})")?,
+                    _ => write!(f, "{part}")?
+                }
+            }
+            Ok(())
+        };
+        match self {
+            Query::Term{ field: Some(field), word } => write!(f, "{field}:{word}"),
+            Query::Term{ field: None, word } => write!(f, "{word}"),
+            Query::And(parts) => join(f, parts, "AND"),
+            Query::Or(parts) => join(f, parts, "OR"),
+            Query::Not(query) => match query.as_ref() {
+                Query::And(_) | Query::Or(_) => write!(f, "NOT ({query})"),
+                _ => write!(f, "NOT {query}")
+            }
+        }
+    }
+}
+
+
+impl Query {
+    pub fn terms(&self) -> Vec<(Option<&str>, &str)> {
+        // the (field, word) of the terms that are not negated
+        match self {
+            Query::Term{ field, word } => vec![(field.as_deref(), word.as_str())],
+            Query::And(parts) | Query::Or(parts) => parts.iter().flat_map(|p| p.terms()).collect(),
+            Query::Not(_) => Vec::new()
+        }
+    }
+}
+
+
+fn tokenize(s: &str) -> Vec<String> {
+    // words and parentheses
+    let mut tokens = Vec::new();
+    for word in s.split_whitespace() {
+        let mut rest = word;
+        while let Some(stripped) = rest.strip_prefix('(') {
+            tokens.push("(".to_string());
+            rest = stripped;
+        }
+        let closing = rest.len() - rest.trim_end_matches(')').len();
+        let rest = &rest[..rest.len() - closing];
+        if !rest.is_empty() {
+            tokens.push(rest.to_string());
+        }
+        tokens.extend(std::iter::repeat_n(")".to_string(), closing));
+    }
+    tokens
+}
+
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    pos: usize
+}
+
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn parse_or(&mut self) -> Result<Query, String> {
+        let mut parts = vec![self.parse_and()?];
+        while self.peek() == Some("OR") {
+            self.pos += 1;
+            parts.push(self.parse_and()?);
+        }
+        Ok(if parts.len() == 1 { parts.remove(0) } else { Query::Or(parts) })
+    }
+
+    fn parse_and(&mut self) -> Result<Query, String> {
+        let mut parts = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                Some("AND") => self.pos += 1,
+                Some("OR") | Some(")") | None => break,
+                Some(_) => ()  // implicit AND
+            }
+            parts.push(self.parse_unary()?);
+        }
+        Ok(if parts.len() == 1 { parts.remove(0) } else { Query::And(parts) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, String> {
+        let Some(token) = self.peek() else { return Err("the query ends where a term is expected".to_string()) };
+        self.pos += 1;
+        match token {
+            "NOT" => Ok(Query::Not(Box::new(self.parse_unary()?))),
+            "(" => {
+                let query = self.parse_or()?;
+                if self.peek() != Some(")") {
+                    return Err("missing ')' in the query".to_string());
+                }
+                self.pos += 1;
+                Ok(query)
+            },
+            "AND" | "OR" | ")" => Err(format!("'{token}' where a term is expected")),
+            _ => Ok(match token.split_once(':') {
+                Some((field, word)) if !field.is_empty() && !word.is_empty() => Query::Term{ field: Some(field.to_string()), word: word.to_string() },
+                _ => Query::Term{ field: None, word: token.to_string() }
+            })
+        }
+    }
+}
+
+
+pub fn idf(records_with_word: usize, records: usize) -> f64 {
+    // the BM25 idf, which is positive for any number of records with the word
+    let (n, df) = (records as f64, records_with_word as f64);
+    (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+}
+
+
+pub fn term_score(tf: usize, idf: f64) -> f64 {
+    let tf = tf as f64;
+    idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{idf, term_score, Query};
+
+    #[test]
+    fn test_parse_query() {
+        let term = |field: Option<&str>, word: &str| Query::Term{ field: field.map(|f| f.to_string()), word: word.to_string() };
+        assert_eq!("title:storm AND body:king".parse::<Query>().unwrap(), Query::And(vec![term(Some("title"), "storm"), term(Some("body"), "king")]));
+        assert_eq!("a b OR c".parse::<Query>().unwrap(), Query::Or(vec![Query::And(vec![term(None, "a"), term(None, "b")]), term(None, "c")]));
+        let query: Query = "storm OR (tempest AND NOT title:lear)".parse().unwrap();
+        assert_eq!(query.to_string(), "storm OR (tempest AND NOT title:lear)");
+        assert_eq!(query.terms(), vec![(None, "storm"), (None, "tempest")]);
+        assert_eq!("http: :x".parse::<Query>().unwrap(), Query::And(vec![term(None, "http:"), term(None, ":x")]));
+        assert!("a AND".parse::<Query>().is_err() && "(a OR b".parse::<Query>().is_err() && "a)".parse::<Query>().is_err() && "".parse::<Query>().is_err());
+
+        assert!(idf(1, 100) > idf(10, 100) && idf(100, 100) > 0.0);
+        assert!(term_score(2, 1.0) > term_score(1, 1.0) && term_score(100, 1.0) < super::BM25_K1 + 1.0);
+    }
+}
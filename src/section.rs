@@ -0,0 +1,292 @@
+// Sections: headings detected while indexing and the hierarchical table of sections they start.
+//
+// A section rule is a regex with a level (1 is the outermost level). Every source line that matches a rule is a heading
+// that starts a section at that level; the section ends where the next heading of the same or a higher level starts. The
+// title of a section is the first group of the match, or the whole match when the regex has no group. A line can match
+// several rules, e.g. "ACT I. SCENE II." starts an act and a scene.
+//
+// The rules are matched against the raw source lines (before the adapter removes the markup), so Markdown '#' headings
+// can be detected. A heading always starts a new record (see boundary.rs), so every section starts at a record.
+//
+// The table stores the sections in the order of their first record. The section of a record is the last section that
+// starts at or before it, as a section that starts later and is still open is always nested in the earlier ones.
+
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use regex_automata::meta::Regex;
+use serde::{Deserialize, Serialize};
+use crate::index::WordLoc;
+
+
+pub const PATH_SEPARATOR: &str = " › ";
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionRule {
+    pub level: u8,
+    pub pattern: String
+}
+
+
+// the rules of 'IndexOptions::sections', no sections are detected without rules
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionRules(pub Vec<SectionRule>);
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    pub level: u8,
+    pub title: String
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Section {
+    pub level: u8,
+    pub title: String,
+    pub parent: Option<u32>,  // index of the enclosing section
+    pub start: u32            // the first record of the section
+}
+
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SectionTable {
+    sections: Vec<Section>,
+    #[serde(skip)]
+    open: Vec<u32>  // the sections that are open after the last heading, outermost first
+}
+
+
+impl FromStr for SectionRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A preset ('markdown' or 'shakespeare') or rules separated by ';' as '<level>=<regex>', e.g. '1=^BOOK (\w+);2=^Chapter'.
+        let rules = match s {
+            "markdown" | "md" => (1..=6).map(|level| SectionRule{ level, pattern: format!(r"^ {{0,3}}#{{{level}}}[ \t]+(.+?)[ \t#]*$") }).collect(),
+            "shakespeare" => vec![
+                // the title of a work is a line of at least three words in capitals, e.g. "THE TRAGEDY OF HAMLET, PRINCE OF DENMARK"
+                SectionRule{ level: 1, pattern: r"^\s*([A-Z][A-Z,;'’-]*(?: +[A-Z,;'’-]+){2,})\s*$".to_string() },
+                SectionRule{ level: 2, pattern: r"(?i)^\s*(ACT [IVX]+)\b".to_string() },
+                SectionRule{ level: 3, pattern: r"(?i)^\s*(?:ACT [IVX]+\.?\s*)?(SCENE [IVX0-9]+)\b".to_string() }
+            ],
+            _ => s.split(';').filter(|r| !r.is_empty()).map(|rule| {
+                let (level, pattern) = rule.split_once('=').ok_or_else(|| format!("a section rule is '<level>=<regex>', not '{rule}'"))?;
+                let level = level.trim().parse().ok().filter(|&l| l >= 1).ok_or_else(|| format!("the level of '{rule}' must be a number from 1 to 255"))?;
+                Ok(SectionRule{ level, pattern: pattern.to_string() })
+            }).collect::<Result<Vec<_>, String>>()?
+        };
+        SectionDetector::new(&rules).map_err(|err| err.to_string())?;
+        Ok(SectionRules(rules))
+    }
+}
+
+
+pub struct SectionDetector {
+    rules: Vec<(u8, Regex)>
+}
+
+
+impl SectionDetector {
+    pub fn new(rules: &[SectionRule]) -> io::Result<Self> {
+        // fails when a pattern is not a valid regex
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let regex = Regex::new(&rule.pattern)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid section rule '{}': {err}", rule.pattern)))?;
+            compiled.push((rule.level, regex));
+        }
+        compiled.sort_by_key(|(level, _)| *level);
+        Ok(SectionDetector{ rules: compiled })
+    }
+
+    pub fn headings(&self, line: &str) -> Vec<Heading> {
+        // the headings of a line, outermost level first
+        let mut headings = Vec::new();
+        for (level, regex) in &self.rules {
+            if headings.last().is_some_and(|h: &Heading| h.level == *level) {
+                continue;  // one heading per level
+            }
+            let mut caps = regex.create_captures();
+            regex.captures(line, &mut caps);
+            if let Some(span) = caps.get_group(1).or_else(|| caps.get_match().map(|m| m.span())) {
+                headings.push(Heading{ level: *level, title: line[span.range()].trim().to_string() });
+            }
+        }
+        headings
+    }
+}
+
+
+impl PartialEq for SectionTable {
+    fn eq(&self, other: &Self) -> bool {
+        // the open sections follow from the sections
+        self.sections == other.sections
+    }
+}
+
+
+impl Eq for SectionTable {}
+
+
+impl SectionTable {
+    pub fn len(&self) -> usize {
+        self.sections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.sections.capacity() * std::mem::size_of::<Section>() + self.sections.iter().map(|s| s.title.capacity()).sum::<usize>()
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub fn start(&mut self, record: u32, heading: &Heading) {
+        // Start a section at 'record'. The open sections of the same or a deeper level end.
+        if self.open.is_empty() && !self.sections.is_empty() {
+            self.reopen();
+        }
+        while self.open.last().is_some_and(|&idx| self.sections[idx as usize].level >= heading.level) {
+            self.open.pop();
+        }
+        self.open.push(self.sections.len() as u32);
+        self.sections.push(Section{ level: heading.level, title: heading.title.clone(), parent: self.open.iter().rev().nth(1).copied(), start: record });
+    }
+
+    pub fn append(&mut self, other: &SectionTable, record_offset: u32) {
+        // Add the sections of an index whose records follow the records of this index. The sections start in the same
+        // order as when the records are indexed together, so a section of 'other' is nested in the sections that are open
+        // at the end of this table when its level is deeper.
+        for section in &other.sections {
+            self.start(record_offset + section.start, &Heading{ level: section.level, title: section.title.clone() });
+        }
+    }
+
+    fn reopen(&mut self) {
+        // the open sections of a loaded table, which only stores the sections
+        let mut idx = Some(self.sections.len() as u32 - 1);
+        while let Some(i) = idx {
+            self.open.insert(0, i);
+            idx = self.sections[i as usize].parent;
+        }
+    }
+
+    pub fn section_of(&self, record: u32) -> Option<u32> {
+        // the innermost section that contains 'record'
+        self.sections.partition_point(|s| s.start <= record).checked_sub(1).map(|idx| idx as u32)
+    }
+
+    pub fn ancestor_at(&self, section: u32, level: u8) -> Option<u32> {
+        // the section itself or its enclosing section at 'level', None when the section is not nested in one at that level
+        let mut curr = Some(section);
+        while let Some(idx) = curr {
+            let s = &self.sections[idx as usize];
+            if s.level <= level {
+                return (s.level == level).then_some(idx);
+            }
+            curr = s.parent;
+        }
+        None
+    }
+
+    pub fn titles(&self, section: u32) -> Vec<&str> {
+        // the titles from the outermost section down to 'section'
+        let mut titles = Vec::new();
+        let mut curr = Some(section);
+        while let Some(idx) = curr {
+            titles.push(self.sections[idx as usize].title.as_str());
+            curr = self.sections[idx as usize].parent;
+        }
+        titles.reverse();
+        titles
+    }
+
+    pub fn path(&self, section: u32) -> String {
+        self.titles(section).join(PATH_SEPARATOR)
+    }
+
+    pub fn matches(&self, section: u32, query: &str) -> bool {
+        // Whether the path of 'section' matches 'query': the parts of the query (separated by '/' or '›') are found in the
+        // titles of the path in the same order, ignoring case. "hamlet/act iii" matches "Hamlet › Act III › Scene 1".
+        let titles: Vec<String> = self.titles(section).iter().map(|t| t.to_lowercase()).collect();
+        let mut titles = titles.iter();
+        query.split(['/', '›']).map(|part| part.trim().to_lowercase()).filter(|part| !part.is_empty())
+            .all(|part| titles.any(|title| title.contains(&part)))
+    }
+
+    pub fn facets(&self, locs: &[WordLoc], level: Option<u8>) -> Vec<(Option<u32>, usize)> {
+        // The number of locations per section (at 'level', or the innermost section when None), most locations first.
+        // Locations that are not in a section (at that level) are counted for None.
+        let mut counts: HashMap<Option<u32>, usize> = HashMap::new();
+        for loc in locs {
+            let section = self.section_of(loc.line);
+            let section = match level {
+                Some(level) => section.and_then(|s| self.ancestor_at(s, level)),
+                None => section
+            };
+            *counts.entry(section).or_default() += 1;
+        }
+        let mut facets: Vec<(Option<u32>, usize)> = counts.into_iter().collect();
+        facets.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        facets
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Heading, SectionDetector, SectionRules, SectionTable};
+    use crate::index::WordLoc;
+
+    #[test]
+    fn test_section_detection() {
+        let rules = |s: &str| s.parse::<SectionRules>().map(|rules| rules.0);
+        let detector = SectionDetector::new(&rules("shakespeare").unwrap()).unwrap();
+        let titles = |line| detector.headings(line).into_iter().map(|h| (h.level, h.title)).collect::<Vec<_>>();
+        assert_eq!(titles("THE TRAGEDY OF HAMLET, PRINCE OF DENMARK"), vec![(1, "THE TRAGEDY OF HAMLET, PRINCE OF DENMARK".to_string())]);
+        assert_eq!(titles("ACT III. SCENE I."), vec![(2, "ACT III".to_string()), (3, "SCENE I".to_string())]);
+        assert_eq!(titles("Scene 2"), vec![(3, "Scene 2".to_string())]);
+        assert_eq!(titles("SCENE VIII"), vec![(3, "SCENE VIII".to_string())]);
+        assert!(titles("THE END").is_empty() && titles("  Ham. To be, or not to be").is_empty() && titles("the scene is set").is_empty());
+
+        let markdown = SectionDetector::new(&rules("markdown").unwrap()).unwrap();
+        assert_eq!(markdown.headings("## Usage ##"), vec![Heading{ level: 2, title: "Usage".to_string() }]);
+        assert!(markdown.headings("#hashtag").is_empty());
+        assert_eq!(rules("1=^BOOK (\\w+);2=^Chapter").unwrap().len(), 2);
+        assert!(rules("x=^A").is_err() && rules("1=(").is_err());
+    }
+
+    #[test]
+    fn test_section_table() {
+        let mut table = SectionTable::default();
+        let heading = |level, title: &str| Heading{ level, title: title.to_string() };
+        table.start(2, &heading(1, "Hamlet"));
+        table.start(3, &heading(2, "Act I"));
+        table.start(3, &heading(3, "Scene 1"));
+        table.start(10, &heading(3, "Scene 2"));
+        table.start(20, &heading(2, "Act II"));
+        table.start(30, &heading(1, "Lear"));
+        table.start(31, &heading(3, "Scene 1"));
+        assert_eq!(table.section_of(1), None);
+        assert_eq!(table.section_of(2).map(|s| table.path(s)).as_deref(), Some("Hamlet"));
+        assert_eq!(table.section_of(12).map(|s| table.path(s)).as_deref(), Some("Hamlet › Act I › Scene 2"));
+        assert_eq!(table.section_of(25).map(|s| table.path(s)).as_deref(), Some("Hamlet › Act II"));
+        assert_eq!(table.section_of(40).map(|s| table.path(s)).as_deref(), Some("Lear › Scene 1"));
+        assert!(table.matches(3, "hamlet/scene 2") && !table.matches(3, "act ii") && !table.matches(3, "scene/hamlet"));
+
+        let locs: Vec<WordLoc> = [0, 4, 11, 12, 21, 40].iter().map(|&line| WordLoc{ line, field: 0, word: 0 }).collect();
+        assert_eq!(table.facets(&locs, Some(1)), vec![(Some(0), 4), (None, 1), (Some(5), 1)]);
+        assert_eq!(table.facets(&locs, Some(2)), vec![(Some(1), 3), (None, 2), (Some(4), 1)]);
+
+        // a loaded table continues with the sections that were open
+        let mut loaded: SectionTable = serde_json::from_str(&serde_json::to_string(&table).unwrap()).unwrap();
+        loaded.start(50, &heading(3, "Scene 2"));
+        assert_eq!(loaded.path(7), "Lear › Scene 2");
+    }
+}
@@ -0,0 +1,274 @@
+// A sharded index: the index of a corpus split into N shards that are queried in parallel.
+//
+// The corpus is partitioned
+//  - by records: shard i has the locations of a contiguous range of records. Every shard has its own vocabulary, so the
+//    count of a word is the sum of its counts in the shards.
+//  - by term range: shard i has the words from 'bounds[i - 1]' up to (not including) 'bounds[i]', with all their locations.
+//    The ranges are chosen so that the shards hold about the same number of locations. A word is in exactly one shard.
+// The records keep their numbers in the shards, so the locations of all shards can be concatenated without remapping.
+//
+// A query runs on every shard in its own thread and the results are merged, so that the result equals the result of the
+// unsharded index. For completions the top-k of each shard is not enough when partitioned by records: a word that is just
+// outside the top-k of every shard can be in the global top-k. So each shard returns all matching words with their counts
+// and the counts are summed before selecting the top-k. When partitioned by term range, a shard returns its own top-k and
+// 'total_count', as the vocabularies of the shards are disjoint.
+//
+// A sharded index is saved as one binary index file per shard plus a JSON manifest with the strategy, the term bounds and
+// the names of the shard files (relative to the manifest), so that the shards can live in separate files.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::Instant;
+use std::ops::{Bound, Range};
+use serde::{Deserialize, Serialize};
+use crate::index::{top_completions_count, CompletionsRec, IndexOptions, NewCompl, WordIndex, WordLoc};
+use crate::export::ExportFormat;
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShardStrategy {
+    #[default]
+    Records,
+    TermRange
+}
+
+
+pub struct ShardedIndex {
+    strategy: ShardStrategy,
+    shards: Vec<WordIndex>,
+    bounds: Vec<String>  // TermRange: the first word of every shard but the first
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShardManifest {
+    strategy: ShardStrategy,
+    bounds: Vec<String>,
+    shards: Vec<String>  // file names of the shards, relative to the manifest
+}
+
+
+impl FromStr for ShardStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "records" | "documents" | "document" => Ok(ShardStrategy::Records),
+            "terms" | "term-range" => Ok(ShardStrategy::TermRange),
+            _ => Err(format!("unknown shard strategy '{s}' (use records or terms)"))
+        }
+    }
+}
+
+
+impl fmt::Display for ShardStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShardStrategy::Records => write!(f, "records"),
+            ShardStrategy::TermRange => write!(f, "terms")
+        }
+    }
+}
+
+
+impl ShardedIndex {
+    pub fn new(index: &WordIndex, num_shards: usize, strategy: ShardStrategy) -> Self {
+        // Split 'index' into 'num_shards' shards (at least one). Deleted records are left out.
+        let num_shards = num_shards.max(1);
+        let record_count = index.record_count as u32;
+        let (shards, bounds) = match strategy {
+            ShardStrategy::Records => {
+                let size = record_count.div_ceil(num_shards as u32).max(1);
+                let shards = (0..num_shards as u32)
+                    .map(|i| index.shard((Bound::Unbounded, Bound::Unbounded), (i * size).min(record_count)..((i + 1) * size).min(record_count)))
+                    .collect();
+                (shards, Vec::new())
+            },
+            ShardStrategy::TermRange => {
+                let bounds = term_bounds(&index.prefix_counts(""), num_shards);
+                let shards = (0..num_shards).map(|i| {
+                    let lower = if i == 0 { Bound::Unbounded } else { Bound::Included(bounds[i - 1].as_str()) };
+                    let upper = bounds.get(i).map_or(Bound::Unbounded, |b| Bound::Excluded(b.as_str()));
+                    index.shard((lower, upper), 0..record_count)
+                }).collect();
+                (shards, bounds)
+            }
+        };
+        ShardedIndex{ strategy, shards, bounds }
+    }
+
+    pub fn build_from_file<P: AsRef<Path>>(path: P, options: IndexOptions, num_shards: usize, strategy: ShardStrategy) -> io::Result<Self> {
+        Ok(ShardedIndex::new(&WordIndex::build_index_from_file(path, options)?, num_shards, strategy))
+    }
+
+    pub fn strategy(&self) -> ShardStrategy {
+        self.strategy
+    }
+
+    pub fn shards(&self) -> &[WordIndex] {
+        &self.shards
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.record_count).max().unwrap_or(0)
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.word_count).sum()
+    }
+
+    fn fan_out<'a, T: Send>(&'a self, shards: Range<usize>, query: impl Fn(&'a WordIndex) -> T + Sync) -> Vec<T> {
+        // run 'query' on the shards in parallel, the results are in the order of the shards
+        let query = &query;
+        thread::scope(|scope| {
+            let handles: Vec<_> = self.shards[shards].iter().map(|shard| scope.spawn(move || query(shard))).collect();
+            handles.into_iter().map(|handle| handle.join().expect("a query on a shard panicked")).collect()
+        })
+    }
+
+    fn term_shard(&self, word: &str) -> usize {
+        // TermRange: the shard that holds 'word'
+        self.bounds.partition_point(|bound| bound.as_str() <= word)
+    }
+
+    pub fn find_matches(&self, word: &str) -> Option<Vec<WordLoc>> {
+        // As 'WordIndex::find_matches'. The records of a shard follow the records of the shards before it, so the
+        // locations are concatenated in the order of the shards.
+        let shards = match self.strategy {
+            ShardStrategy::Records => 0..self.shards.len(),
+            ShardStrategy::TermRange => self.term_shard(word)..self.term_shard(word) + 1
+        };
+        let locs: Vec<WordLoc> = self.fan_out(shards, |shard| shard.find_matches(word).map(|locs| locs.into_owned()))
+            .into_iter().flatten().flatten().collect();
+        (!locs.is_empty()).then_some(locs)
+    }
+
+    pub fn find_completions(&self, check_word: &str, num_completions: usize) -> CompletionsRec {
+        // as 'WordIndex::find_completions'
+        self.merge_completions(num_completions, |shard| shard.prefix_counts(check_word))
+    }
+
+    pub fn find_dl_completions(&self, check_word: &str, num_completions: usize, max_dist: usize) -> CompletionsRec {
+        // as 'WordIndex::find_dl_completions'
+        self.merge_completions(num_completions, |shard| shard.dl_prefix_counts(check_word, max_dist))
+    }
+
+    fn merge_completions<'a>(&'a self, num_completions: usize, matches: impl Fn(&'a WordIndex) -> Vec<(&'a str, usize)> + Sync) -> CompletionsRec {
+        // The top 'num_completions' of the words that 'matches' returns for the shards (see the module header).
+        let start = Instant::now();
+        let mut completions_rec = match self.strategy {
+            ShardStrategy::Records => {
+                let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+                for (word, count) in self.fan_out(0..self.shards.len(), matches).into_iter().flatten() {
+                    *counts.entry(word).or_default() += count;
+                }
+                top_of(counts, num_completions)
+            },
+            ShardStrategy::TermRange => {
+                let tops = self.fan_out(0..self.shards.len(), |shard| top_of(matches(shard).into_iter().collect(), num_completions));
+                let counts = tops.iter().flat_map(|top| top.compl.iter().map(|c| (c.completion.as_str(), c.count))).collect();
+                CompletionsRec{ total_count: tops.iter().map(|top| top.total_count).sum(), ..top_of(counts, num_completions) }
+            }
+        };
+        completions_rec.duration = start.elapsed();
+        completions_rec
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, manifest: P) -> io::Result<()> {
+        // Write every shard as a binary index next to the manifest ('<manifest stem>.<shard>.bin') and then the manifest.
+        let manifest = manifest.as_ref();
+        let dir = manifest.parent().unwrap_or(Path::new(""));
+        let stem = manifest.file_stem().and_then(|s| s.to_str()).unwrap_or("shards");
+        let mut names = Vec::with_capacity(self.shards.len());
+        for (idx, shard) in self.shards.iter().enumerate() {
+            let name = format!("{stem}.{idx}.bin");
+            shard.export(dir.join(&name), ExportFormat::Binary, false)?;
+            names.push(name);
+        }
+        let contents = ShardManifest{ strategy: self.strategy, bounds: self.bounds.clone(), shards: names };
+        serde_json::to_writer_pretty(BufWriter::new(File::create(manifest)?), &contents).map_err(io::Error::other)
+    }
+
+    pub fn load<P: AsRef<Path>>(manifest: P) -> io::Result<Self> {
+        // Read the manifest and load the shards in parallel.
+        let manifest = manifest.as_ref();
+        let dir = manifest.parent().unwrap_or(Path::new(""));
+        let contents: ShardManifest = serde_json::from_reader(BufReader::new(File::open(manifest)?))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if contents.strategy == ShardStrategy::TermRange && contents.bounds.len() + 1 != contents.shards.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} needs {} term bounds for {} shards", manifest.display(), contents.shards.len().saturating_sub(1), contents.shards.len())));
+        }
+        let shards = thread::scope(|scope| {
+            let handles: Vec<_> = contents.shards.iter().map(|name| scope.spawn(move || WordIndex::load_binary(dir.join(name)))).collect();
+            handles.into_iter().map(|handle| handle.join().expect("loading a shard panicked")).collect::<io::Result<Vec<WordIndex>>>()
+        })?;
+        Ok(ShardedIndex{ strategy: contents.strategy, shards, bounds: contents.bounds })
+    }
+}
+
+
+fn top_of(counts: BTreeMap<&str, usize>, num_completions: usize) -> CompletionsRec {
+    // The candidates are folded in the order of the words, so ties are broken as in the unsharded index.
+    counts.into_iter().fold(CompletionsRec::new(num_completions), |state, (word, count)| top_completions_count(state, word, count))
+}
+
+
+fn term_bounds(counts: &[(&str, usize)], num_shards: usize) -> Vec<String> {
+    // The first words of shards 1..num_shards, such that every shard holds about the same number of locations. There are
+    // fewer bounds when there are fewer words than shards; the remaining shards are empty.
+    let total: usize = counts.iter().map(|&(_, count)| count).sum();
+    let mut bounds = Vec::with_capacity(num_shards - 1);
+    let mut seen = 0;
+    for (idx, &(_, count)) in counts.iter().enumerate() {
+        seen += count;
+        let next = counts.get(idx + 1).map(|&(word, _)| word);
+        if let Some(next) = next.filter(|_| bounds.len() + 1 < num_shards && seen * num_shards >= total * (bounds.len() + 1)) {
+            bounds.push(next.to_string());
+        }
+    }
+    bounds
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ShardStrategy, ShardedIndex};
+    use crate::index::{CompletionsRec, WordIndex};
+    use crate::testutil::TempPath;
+
+    #[test]
+    fn test_sharded_index() {
+        let lines: Vec<String> = (0..900).map(|i| format!("w{} w{} the thee word{} then", i % 37, i % 101, i % 250)).collect();
+        let whole = WordIndex::build_index(lines.join("\n").as_bytes());
+        let summary = |rec: CompletionsRec| (rec.compl.into_iter().map(|c| (c.completion, c.count)).collect::<Vec<_>>(), rec.total_count);
+        for strategy in [ShardStrategy::Records, ShardStrategy::TermRange] {
+            let sharded = ShardedIndex::new(&whole, 4, strategy);
+            assert_eq!(sharded.shards().len(), 4);
+            assert_eq!((sharded.record_count(), sharded.word_count()), (whole.record_count, whole.word_count));
+            for word in ["the", "w3", "word249", "missing"] {
+                assert_eq!(sharded.find_matches(word).as_ref(), whole.find_matches(word).as_deref());
+            }
+            for (prefix, k) in [("w", 5), ("word1", 3), ("th", 10), ("x", 5)] {
+                assert_eq!(summary(sharded.find_completions(prefix, k)), summary(whole.find_completions(prefix, k)));
+            }
+            assert_eq!(summary(sharded.find_dl_completions("wrd2", 5, 1)), summary(whole.find_dl_completions(&"wrd2".to_string(), 5, 1)));
+
+            // the shard files are written next to the manifest
+            let dir = TempPath::new(&format!("shards_{strategy}"));
+            std::fs::create_dir(&dir).unwrap();
+            sharded.save(dir.join("index.json")).unwrap();
+            assert!((0..4).all(|idx| dir.join(format!("index.{idx}.bin")).is_file()));
+            let loaded = ShardedIndex::load(dir.join("index.json")).unwrap();
+            assert_eq!(loaded.strategy(), strategy);
+            assert_eq!(summary(loaded.find_completions("w", 5)), summary(whole.find_completions("w", 5)));
+            assert_eq!(loaded.find_matches("then").map(|locs| locs.len()), Some(900));
+        }
+        assert_eq!("terms".parse::<ShardStrategy>(), Ok(ShardStrategy::TermRange));
+        assert!("hash".parse::<ShardStrategy>().is_err());
+    }
+}
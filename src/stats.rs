@@ -0,0 +1,227 @@
+// Corpus statistics: type/token ratio, frequency spectrum, hapax legomena, Zipf's law and Heaps' law.
+//
+// Zipf's law states that the frequency of the word with rank r is proportional to r^-s, Heaps' law that a text of n tokens
+// has about K * n^beta distinct words. Both exponents are estimated with a least-squares fit of a line on the log-log scale.
+// The vocabulary growth curve is sampled by 'WordIndex::build_index' (see 'GROWTH_SAMPLE_INTERVAL').
+//
+// The report can be rendered as text, as JSON or as CSV. The CSV has the columns 'section,key,value' so that the scalar
+// values and the tables (spectrum, hapaxes, growth) fit in a single file.
+
+use std::fmt::Write;
+use std::str::FromStr;
+use serde::Serialize;
+use crate::memory::MemoryUsage;
+
+
+// number of records between two samples of the vocabulary growth curve
+pub const GROWTH_SAMPLE_INTERVAL: usize = 1000;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatsFormat {
+    #[default]
+    Text,
+    Json,
+    Csv
+}
+
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" => Ok(StatsFormat::Text),
+            "json" => Ok(StatsFormat::Json),
+            "csv" => Ok(StatsFormat::Csv),
+            _ => Err(format!("unknown format '{s}' (use text, json or csv)"))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PowerLawFit {
+    pub exponent: f64,   // Zipf: s in freq ~ rank^-s,  Heaps: beta in types ~ K * tokens^beta
+    pub constant: f64,   // the factor in front of the power (only meaningful for Heaps' K)
+    pub r_squared: f64   // goodness of the fit on the log-log scale
+}
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CorpusStats {
+    pub records: usize,
+    pub tokens: usize,
+    pub types: usize,
+    pub type_token_ratio: f64,
+    pub spectrum: Vec<(usize, usize)>,           // (frequency, number of words with that frequency), ascending frequency
+    pub hapaxes: Vec<String>,                    // words that occur exactly once
+    pub zipf: Option<PowerLawFit>,
+    pub heaps: Option<PowerLawFit>,
+    pub vocabulary_growth: Vec<(usize, usize)>,  // (tokens, types) sampled while building the index
+    pub memory: MemoryUsage
+}
+
+
+pub fn fit_power_law(points: &[(f64, f64)]) -> Option<PowerLawFit> {
+    // Least-squares fit of log(y) = log(c) + e * log(x) over the points with positive coordinates.
+    let logs: Vec<(f64, f64)> = points.iter().filter(|(x, y)| *x > 0.0 && *y > 0.0).map(|(x, y)| (x.ln(), y.ln())).collect();
+    let n = logs.len() as f64;
+    if logs.len() < 2 {
+        return None;
+    }
+    let (mean_x, mean_y) = (logs.iter().map(|p| p.0).sum::<f64>() / n, logs.iter().map(|p| p.1).sum::<f64>() / n);
+    let sxx: f64 = logs.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let sxy: f64 = logs.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let syy: f64 = logs.iter().map(|(_, y)| (y - mean_y).powi(2)).sum();
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    let r_squared = if syy == 0.0 { 1.0 } else { sxy * sxy / (sxx * syy) };
+    Some(PowerLawFit{ exponent: slope, constant: (mean_y - slope * mean_x).exp(), r_squared })
+}
+
+
+impl CorpusStats {
+    pub fn new<'a, I>(counts: I, records: usize, vocabulary_growth: Vec<(usize, usize)>, memory: MemoryUsage) -> Self
+    where I: IntoIterator<Item = (&'a String, usize)> {
+        // Compute the statistics from the (word, count) pairs of the vocabulary.
+        let mut freqs: Vec<usize> = Vec::new();
+        let mut hapaxes = Vec::new();
+        for (word, count) in counts {
+            freqs.push(count);
+            if count == 1 {
+                hapaxes.push(word.clone());
+            }
+        }
+        let tokens: usize = freqs.iter().sum();
+        let types = freqs.len();
+
+        freqs.sort_unstable_by(|a, b| b.cmp(a));
+        let mut spectrum: Vec<(usize, usize)> = Vec::new();
+        for &freq in freqs.iter().rev() {
+            match spectrum.last_mut() {
+                Some((f, num)) if *f == freq => *num += 1,
+                _ => spectrum.push((freq, 1))
+            }
+        }
+
+        let ranked: Vec<(f64, f64)> = freqs.iter().enumerate().map(|(rank, &freq)| ((rank + 1) as f64, freq as f64)).collect();
+        let zipf = fit_power_law(&ranked).map(|fit| PowerLawFit{ exponent: -fit.exponent, ..fit });
+        let growth: Vec<(f64, f64)> = vocabulary_growth.iter().map(|&(n, v)| (n as f64, v as f64)).collect();
+        let heaps = fit_power_law(&growth);
+
+        CorpusStats{
+            records, tokens, types,
+            type_token_ratio: if tokens > 0 { types as f64 / tokens as f64 } else { 0.0 },
+            spectrum, hapaxes, zipf, heaps, vocabulary_growth, memory
+        }
+    }
+
+    pub fn render(&self, format: StatsFormat) -> String {
+        match format {
+            StatsFormat::Text => self.to_text(),
+            StatsFormat::Json => serde_json::to_string_pretty(self).unwrap(),
+            StatsFormat::Csv => self.to_csv()
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut s = String::new();
+        let zipf = self.zipf.map_or("n/a".to_string(), |f| format!("{:.3} (R² = {:.3})", f.exponent, f.r_squared));
+        let heaps = self.heaps.map_or("n/a".to_string(), |f| format!("{:.3} (K = {:.2}, R² = {:.3})", f.exponent, f.constant, f.r_squared));
+        writeln!(s, "Records:           {}", self.records).unwrap();
+        writeln!(s, "Tokens:            {}", self.tokens).unwrap();
+        writeln!(s, "Types:             {}", self.types).unwrap();
+        writeln!(s, "Type/token ratio:  {:.4}", self.type_token_ratio).unwrap();
+        writeln!(s, "Hapax legomena:    {} ({:.1}% of the types)", self.hapaxes.len(), 100.0 * self.hapaxes.len() as f64 / self.types.max(1) as f64).unwrap();
+        writeln!(s, "Zipf exponent:     {zipf}").unwrap();
+        writeln!(s, "Heaps exponent:    {heaps}").unwrap();
+        writeln!(s, "Memory used:       {}", self.memory).unwrap();
+        writeln!(s, "\nFrequency spectrum (frequency: number of words)").unwrap();
+        for (freq, num) in self.spectrum.iter().take(20) {
+            writeln!(s, "{freq:>10}: {num}").unwrap();
+        }
+        if self.spectrum.len() > 20 {
+            writeln!(s, "       ...  ({} more frequencies)", self.spectrum.len() - 20).unwrap();
+        }
+        writeln!(s, "\nVocabulary growth (tokens: types)").unwrap();
+        for (tokens, types) in &self.vocabulary_growth {
+            writeln!(s, "{tokens:>10}: {types}").unwrap();
+        }
+        writeln!(s, "\nHapax legomena").unwrap();
+        writeln!(s, "{}", self.hapaxes.join(" ")).unwrap();
+        s
+    }
+
+    fn to_csv(&self) -> String {
+        let mut s = String::from("section,key,value\n");
+        let mut row = |section: &str, key: &dyn std::fmt::Display, value: &dyn std::fmt::Display| writeln!(s, "{section},{key},{value}").unwrap();
+        row("summary", &"records", &self.records);
+        row("summary", &"tokens", &self.tokens);
+        row("summary", &"types", &self.types);
+        row("summary", &"type_token_ratio", &self.type_token_ratio);
+        row("summary", &"hapaxes", &self.hapaxes.len());
+        for (name, fit) in [("zipf", &self.zipf), ("heaps", &self.heaps)] {
+            if let Some(fit) = fit {
+                row("summary", &format!("{name}_exponent"), &fit.exponent);
+                row("summary", &format!("{name}_constant"), &fit.constant);
+                row("summary", &format!("{name}_r_squared"), &fit.r_squared);
+            }
+        }
+        row("summary", &"memory_bytes", &self.memory.total());
+        for (freq, num) in &self.spectrum {
+            row("spectrum", freq, num);
+        }
+        for (tokens, types) in &self.vocabulary_growth {
+            row("growth", tokens, types);
+        }
+        for word in &self.hapaxes {
+            row("hapax", &csv_field(word), &1);
+        }
+        s
+    }
+}
+
+
+fn csv_field(s: &str) -> String {
+    // quote a field when it contains a separator, a quote or a line break
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{fit_power_law, CorpusStats, StatsFormat};
+    use crate::memory::MemoryUsage;
+
+    #[test]
+    fn test_power_law_fit() {
+        let points: Vec<(f64, f64)> = (1..=100).map(|x| (x as f64, 3.0 * (x as f64).powf(0.6))).collect();
+        let fit = fit_power_law(&points).unwrap();
+        assert!((fit.exponent - 0.6).abs() < 1e-9 && (fit.constant - 3.0).abs() < 1e-9 && (fit.r_squared - 1.0).abs() < 1e-9);
+        assert!(fit_power_law(&[(1.0, 1.0)]).is_none());
+    }
+
+    #[test]
+    fn test_corpus_stats() {
+        let words: Vec<(String, usize)> = [("the", 8), ("a", 4), ("cat", 2), ("dog", 2), ("\"hi\",", 1), ("mat", 1)]
+            .iter().map(|(w, c)| (w.to_string(), *c)).collect();
+        let stats = CorpusStats::new(words.iter().map(|(w, c)| (w, *c)), 3, vec![(10, 5), (18, 6)], MemoryUsage::default());
+        assert_eq!((stats.tokens, stats.types), (18, 6));
+        assert_eq!(stats.spectrum, vec![(1, 2), (2, 2), (4, 1), (8, 1)]);
+        assert_eq!(stats.hapaxes, vec!["\"hi\",", "mat"]);
+        assert!(stats.zipf.unwrap().exponent > 1.0);
+
+        let csv = stats.render(StatsFormat::Csv);
+        assert!(csv.contains("summary,types,6\n") && csv.contains("spectrum,2,2\n") && csv.contains("hapax,\"\"\"hi\"\",\",1\n"));
+        let json: serde_json::Value = serde_json::from_str(&stats.render(StatsFormat::Json)).unwrap();
+        assert_eq!(json["vocabulary_growth"][1][1], 6);
+        assert!(stats.render(StatsFormat::Text).contains("Hapax legomena:    2 (33.3% of the types)"));
+    }
+}
@@ -0,0 +1,174 @@
+// A SymSpell-style deletion index for fast spelling suggestions.
+//
+// For every word of the vocabulary all strings that can be obtained by deleting up to 'max_dist' characters are precomputed
+// and mapped back to the word. At query time the same deletes are generated for the query, so a lookup becomes a few hash probes.
+// Two words within Damerau-Levenshtein distance 'max_dist' always share a delete, so the probes return a superset of the answer,
+// which is then verified with the exact distance.
+//
+// The memory/precision tradeoff is configured via 'SymSpellConfig':
+//  - prefix_length: only the first 'prefix_length' characters of a word generate deletes. A short prefix saves a lot of memory,
+//    but the probes return more candidates that need to be verified.
+//  - min_count: words that occur less often are not indexed at all (and thus never suggested).
+// The deletes are stored as 64-bit hashes instead of strings; a hash collision only adds a candidate that fails verification.
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use crate::levenshtein::dam_lev_distance;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymSpellConfig {
+    pub max_dist: usize,
+    pub prefix_length: usize,
+    pub min_count: usize
+}
+
+impl Default for SymSpellConfig {
+    fn default() -> Self {
+        SymSpellConfig{ max_dist: 2, prefix_length: 7, min_count: 1 }
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct SymSpell {
+    config: SymSpellConfig,
+    words: Vec<String>,
+    deletes: HashMap<u64, Vec<u32>>  // hash of a delete -> indices in 'words'
+}
+
+
+fn hash_str(s: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    hasher.finish()
+}
+
+
+fn edits(word: &str, max_dist: usize, prefix_length: usize) -> HashSet<String> {
+    // all distinct strings obtained by deleting up to 'max_dist' characters from the first 'prefix_length' characters of 'word' (including the prefix itself).
+    let prefix: String = word.chars().take(prefix_length).collect();
+    let mut result = HashSet::new();
+    let mut frontier = vec![prefix.clone()];
+    result.insert(prefix);
+    for _ in 0..max_dist {
+        let mut next = Vec::new();
+        for s in frontier {
+            let chs: Vec<char> = s.chars().collect();
+            for i in 0..chs.len() {
+                let del: String = chs[..i].iter().chain(chs[i + 1..].iter()).collect();
+                if result.insert(del.clone()) {
+                    next.push(del);
+                }
+            }
+        }
+        frontier = next;
+    }
+    result
+}
+
+
+impl SymSpell {
+    pub fn build<'a, I: IntoIterator<Item = (&'a String, usize)>>(words: I, config: SymSpellConfig) -> Self {
+        // Build the deletion index over (word, count) pairs; words below 'config.min_count' are skipped.
+        let mut sym = SymSpell{ config, words: Vec::new(), deletes: HashMap::new() };
+        for (word, count) in words {
+            if count < config.min_count {
+                continue;
+            }
+            let word_idx = sym.words.len() as u32;
+            sym.words.push(word.clone());
+            for del in edits(word, config.max_dist, config.prefix_length) {
+                sym.deletes.entry(hash_str(&del)).or_default().push(word_idx);
+            }
+        }
+        sym
+    }
+
+    pub fn config(&self) -> SymSpellConfig {
+        self.config
+    }
+
+    pub fn num_words(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn num_deletes(&self) -> usize {
+        self.deletes.len()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        // estimate: the hash table stores a control byte per bucket next to the key and value
+        self.words.capacity() * std::mem::size_of::<String>()
+            + self.words.iter().map(|w| w.capacity()).sum::<usize>()
+            + self.deletes.capacity() * (std::mem::size_of::<(u64, Vec<u32>)>() + 1)
+            + self.deletes.values().map(|v| v.capacity() * 4).sum::<usize>()
+    }
+
+    pub fn lookup(&self, word: &str, max_dist: usize) -> Vec<(&str, usize)> {
+        // Return all indexed words within 'max_dist' (at most the configured max_dist) of 'word', ordered by distance.
+        let max_dist = max_dist.min(self.config.max_dist);
+        let word_len = word.chars().count();
+        let mut seen = HashSet::new();
+        let mut matches = Vec::new();
+        for del in edits(word, max_dist, self.config.prefix_length) {
+            let Some(candidates) = self.deletes.get(&hash_str(&del)) else { continue };
+            for &idx in candidates {
+                if !seen.insert(idx) {
+                    continue;
+                }
+                let candidate = &self.words[idx as usize];
+                if candidate.chars().count().abs_diff(word_len) > max_dist {
+                    continue;
+                }
+                if let Some(dist) = dam_lev_distance(word, candidate, max_dist) {
+                    matches.push((candidate.as_str(), dist));
+                }
+            }
+        }
+        matches.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+        matches
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{SymSpell, SymSpellConfig};
+    use crate::levenshtein::dam_lev_distance;
+
+    #[test]
+    fn test_symspell_matches_linear_scan() {
+        let words: Vec<String> = ["the", "thee", "they", "then", "them", "these", "those", "three", "tree", "other", "mother", "brother",
+            "themselves", "themself", "thereupon", "desdemona", "demon", "a", "an", "and", "hand", "band"]
+            .iter().map(|s| s.to_string()).collect();
+
+        for prefix_length in [3, 5, 7, 20] {
+            let config = SymSpellConfig{ max_dist: 2, prefix_length, min_count: 1 };
+            let sym = SymSpell::build(words.iter().map(|w| (w, 1)), config);
+            for query in ["teh", "thm", "bother", "nad", "themsleves", "dsedemona", "x", "thereuponn"] {
+                for max_dist in 0..=2 {
+                    let mut expected: Vec<(&str, usize)> = words.iter()
+                        .filter_map(|w| dam_lev_distance(query, w, max_dist).map(|d| (w.as_str(), d)))
+                        .collect();
+                    expected.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+                    assert_eq!(sym.lookup(query, max_dist), expected, "query '{query}' max_dist {max_dist} prefix_length {prefix_length}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_symspell_config_tradeoffs() {
+        let words: Vec<(String, usize)> = vec![("thereupon".to_string(), 5), ("therein".to_string(), 1)];
+        let full = SymSpell::build(words.iter().map(|(w, c)| (w, *c)), SymSpellConfig{ prefix_length: 20, ..Default::default() });
+        let short = SymSpell::build(words.iter().map(|(w, c)| (w, *c)), SymSpellConfig{ prefix_length: 4, ..Default::default() });
+        assert!(short.num_deletes() < full.num_deletes());
+
+        let frequent = SymSpell::build(words.iter().map(|(w, c)| (w, *c)), SymSpellConfig{ min_count: 2, ..Default::default() });
+        assert_eq!(frequent.num_words(), 1);
+        assert_eq!(frequent.lookup("therin", 2), vec![]);
+        assert_eq!(full.lookup("therin", 2), vec![("therein", 1)]);
+    }
+}
@@ -0,0 +1,39 @@
+// Helpers shared by the tests of the modules.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+
+// A path in the temporary directory that is removed (as a file or a directory) when it is dropped, also when a test fails.
+pub struct TempPath(PathBuf);
+
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        // the process id keeps the files of test runs that happen at the same time apart, 'name' those of the tests of a run
+        TempPath(std::env::temp_dir().join(format!("text_index_test_{}_{name}", std::process::id())))
+    }
+}
+
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = if self.0.is_dir() { std::fs::remove_dir_all(&self.0) } else { std::fs::remove_file(&self.0) };
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 
 pub fn duration_sec_float(dur: Duration) -> f64 {
@@ -0,0 +1,229 @@
+// A completion trie that answers top-k completion queries without visiting all words that share the prefix.
+//
+// Every node caches the ids of the 'top_k' most frequent words in its subtree (ordered by count, ties by word) and the number
+// of words in its subtree. A query walks down the prefix and copies the cache of the node it ends in, so its cost depends on
+// the length of the prefix and on k, not on the number of completions. Queries for more than 'top_k' completions fall back
+// to a traversal of the subtree.
+//
+// When the count of a word changes only the caches on the path from the root to that word can change, so 'set_count'
+// recomputes those caches bottom-up from the caches of their children.
+
+use std::cmp::Ordering;
+use std::mem;
+
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: Vec<(char, u32)>,  // (label, node index), ordered by label
+    word: Option<u32>,           // id of the word ending in this node
+    num_words: usize,            // number of words with a non-zero count in the subtree
+    top: Vec<u32>                // ids of the most frequent words in the subtree, most frequent first
+}
+
+
+#[derive(Debug, Clone)]
+pub struct CompletionTrie {
+    top_k: usize,
+    nodes: Vec<TrieNode>,
+    words: Vec<String>,
+    counts: Vec<usize>
+}
+
+
+impl CompletionTrie {
+    pub fn new(top_k: usize) -> Self {
+        CompletionTrie{ top_k, nodes: vec![TrieNode::default()], words: Vec::new(), counts: Vec::new() }
+    }
+
+    pub fn build<'a, I: IntoIterator<Item = (&'a String, usize)>>(words: I, top_k: usize) -> Self {
+        // Insert all (word, count) pairs and fill the caches in a single pass afterwards.
+        let mut trie = CompletionTrie::new(top_k);
+        for (word, count) in words {
+            let node = trie.find_or_insert_path(word);
+            trie.set_word(node, word, count);
+        }
+        // children always have a larger index than their parent, so a reverse scan visits the children first
+        for idx in (0..trie.nodes.len()).rev() {
+            trie.refresh(idx);
+        }
+        trie
+    }
+
+    pub fn top_k(&self) -> usize {
+        self.top_k
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes[0].num_words
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.nodes.capacity() * mem::size_of::<TrieNode>()
+            + self.nodes.iter().map(|n| n.children.capacity() * mem::size_of::<(char, u32)>() + n.top.capacity() * 4).sum::<usize>()
+            + self.words.capacity() * mem::size_of::<String>()
+            + self.words.iter().map(|w| w.capacity()).sum::<usize>()
+            + self.counts.capacity() * mem::size_of::<usize>()
+    }
+
+    pub fn count(&self, word: &str) -> usize {
+        self.find_node(word).and_then(|idx| self.nodes[idx].word).map_or(0, |id| self.counts[id as usize])
+    }
+
+    pub fn set_count(&mut self, word: &str, count: usize) {
+        // Set the count of a word (inserting it when new). A count of 0 removes the word from the completions.
+        let path = self.find_or_insert_path_with_parents(word);
+        let node = *path.last().unwrap();
+        self.set_word(node, word, count);
+        for &idx in path.iter().rev() {
+            self.refresh(idx);
+        }
+    }
+
+    pub fn add_count(&mut self, word: &str, delta: usize) {
+        let count = self.count(word);
+        self.set_count(word, count + delta);
+    }
+
+    pub fn completions(&self, prefix: &str, num_completions: usize) -> (Vec<(&str, usize)>, usize) {
+        // The 'num_completions' most frequent words starting with 'prefix' and the total number of words with that prefix.
+        let Some(node) = self.find_node(prefix) else { return (Vec::new(), 0) };
+        let node = &self.nodes[node];
+        let ids: Vec<u32> = if num_completions <= self.top_k {
+            node.top.iter().take(num_completions).copied().collect()
+        } else {
+            let mut ids = Vec::with_capacity(node.num_words);
+            self.collect_words(node, &mut ids);
+            ids.sort_by(|&a, &b| self.compare(a, b));
+            ids.truncate(num_completions);
+            ids
+        };
+        (ids.into_iter().map(|id| (self.words[id as usize].as_str(), self.counts[id as usize])).collect(), node.num_words)
+    }
+
+    fn compare(&self, a: u32, b: u32) -> Ordering {
+        // the order of the caches: most frequent first and alphabetical within the same count
+        self.counts[b as usize].cmp(&self.counts[a as usize]).then_with(|| self.words[a as usize].cmp(&self.words[b as usize]))
+    }
+
+    fn find_node(&self, word: &str) -> Option<usize> {
+        let mut curr = 0;
+        for ch in word.chars() {
+            let children = &self.nodes[curr].children;
+            let pos = children.binary_search_by_key(&ch, |&(label, _)| label).ok()?;
+            curr = children[pos].1 as usize;
+        }
+        Some(curr)
+    }
+
+    fn find_or_insert_path(&mut self, word: &str) -> usize {
+        *self.find_or_insert_path_with_parents(word).last().unwrap()
+    }
+
+    fn find_or_insert_path_with_parents(&mut self, word: &str) -> Vec<usize> {
+        // the indices of the nodes from the root up to the node of 'word', creating the missing nodes.
+        let mut path = vec![0];
+        let mut curr = 0;
+        for ch in word.chars() {
+            curr = match self.nodes[curr].children.binary_search_by_key(&ch, |&(label, _)| label) {
+                Ok(pos) => self.nodes[curr].children[pos].1 as usize,
+                Err(pos) => {
+                    let new_idx = self.nodes.len();
+                    self.nodes.push(TrieNode::default());
+                    self.nodes[curr].children.insert(pos, (ch, new_idx as u32));
+                    new_idx
+                }
+            };
+            path.push(curr);
+        }
+        path
+    }
+
+    fn set_word(&mut self, node: usize, word: &str, count: usize) {
+        match self.nodes[node].word {
+            Some(id) => self.counts[id as usize] = count,
+            None => {
+                self.nodes[node].word = Some(self.words.len() as u32);
+                self.words.push(word.to_string());
+                self.counts.push(count);
+            }
+        }
+    }
+
+    fn refresh(&mut self, idx: usize) {
+        // recompute the cache of a node from its own word and the caches of its children
+        let node = &self.nodes[idx];
+        let own = node.word.filter(|&id| self.counts[id as usize] > 0);
+        let mut num_words = own.map_or(0, |_| 1);
+        let mut top: Vec<u32> = own.into_iter().collect();
+        for &(_, child) in &node.children {
+            let child = &self.nodes[child as usize];
+            num_words += child.num_words;
+            top.extend_from_slice(&child.top);
+        }
+        top.sort_by(|&a, &b| self.compare(a, b));
+        top.truncate(self.top_k);
+        top.shrink_to_fit();
+        let node = &mut self.nodes[idx];
+        node.num_words = num_words;
+        node.top = top;
+    }
+
+    fn collect_words(&self, node: &TrieNode, ids: &mut Vec<u32>) {
+        if let Some(id) = node.word.filter(|&id| self.counts[id as usize] > 0) {
+            ids.push(id);
+        }
+        for &(_, child) in &node.children {
+            self.collect_words(&self.nodes[child as usize], ids);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::CompletionTrie;
+
+    fn linear_scan<'a>(words: &'a [(String, usize)], prefix: &str, n: usize) -> Vec<(&'a str, usize)> {
+        let mut expected: Vec<(&str, usize)> = words.iter()
+            .filter(|(w, c)| w.starts_with(prefix) && *c > 0)
+            .map(|(w, c)| (w.as_str(), *c))
+            .collect();
+        expected.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        expected.truncate(n);
+        expected
+    }
+
+    #[test]
+    fn test_trie_matches_linear_scan() {
+        let mut words: Vec<(String, usize)> = [("the", 50), ("then", 7), ("them", 7), ("these", 3), ("thee", 9), ("theatre", 1), ("thé", 4),
+            ("that", 20), ("a", 40), ("an", 12), ("and", 30), ("hand", 2), ("t", 1), ("λόγος", 2)]
+            .iter().map(|(w, c)| (w.to_string(), *c)).collect();
+        let mut trie = CompletionTrie::build(words.iter().map(|(w, c)| (w, *c)), 3);
+        assert_eq!(trie.len(), words.len());
+
+        let check = |trie: &CompletionTrie, words: &[(String, usize)]| {
+            for prefix in ["", "t", "th", "the", "them", "a", "x", "λ", "thé"] {
+                for n in [1, 3, 10] {
+                    let (found, total) = trie.completions(prefix, n);
+                    assert_eq!(found, linear_scan(words, prefix, n), "prefix '{prefix}' n {n}");
+                    assert_eq!(total, linear_scan(words, prefix, usize::MAX).len());
+                }
+            }
+        };
+        check(&trie, &words);
+
+        // updates keep the caches in sync
+        trie.add_count("these", 60);
+        words[3].1 += 60;
+        trie.set_count("the", 0);
+        words[0].1 = 0;
+        trie.add_count("thorn", 8);
+        words.push(("thorn".to_string(), 8));
+        check(&trie, &words);
+        assert_eq!(trie.completions("th", 2).0, vec![("these", 63), ("that", 20)]);
+    }
+}
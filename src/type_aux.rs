@@ -6,5 +6,5 @@ pub fn print_type_of<T>(_: &T) {
 
 
 pub fn type_of<T>(_: &T) -> String {
-    format!("{}", std::any::type_name::<T>())
+    std::any::type_name::<T>().to_string()
 }
@@ -0,0 +1,160 @@
+// Watching the source of an index and re-indexing it in the background.
+//
+// The watcher polls the source file of a 'WordIndex' (see 'WordIndex::staleness'), which needs no platform specific
+// notification API and also works for files on network drives. The index is shared with the owner as an 'Arc'. When the
+// source changed, a background thread re-indexes it and sends the updated index to the owner, which swaps it in as a
+// whole, so queries never see a half updated index. A changed source is indexed into a new index. Appended lines are
+// added to the index itself (see 'WordIndex::refresh_from_source'), which is only copied while the owner still holds
+// it, so the watcher never keeps a second copy of the postings. A source that can not be checked or re-indexed is
+// reported once until the error changes or goes away.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use crate::header::Staleness;
+use crate::index::WordIndex;
+
+
+// default time between two checks of the source
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+
+pub enum WatchEvent {
+    Reindexed{ index: Arc<WordIndex>, staleness: Staleness, new_records: usize },  // new_records is 0 after a rebuild
+    Error(String)
+}
+
+
+pub struct IndexWatcher {
+    events: Receiver<WatchEvent>,
+    _stop: Sender<()>  // dropping it wakes the thread, which stops without waiting for the interval
+}
+
+
+fn refresh(current: &mut Arc<WordIndex>, staleness: Staleness) -> io::Result<Staleness> {
+    // Bring the shared index up to date with its source.
+    if staleness == Staleness::Appended && current.can_append() {
+        let index = Arc::make_mut(current);
+        index.set_quiet(true);
+        index.refresh_from_source()
+    } else {
+        *current = Arc::new(current.rebuilt_from_source()?);
+        Ok(staleness)
+    }
+}
+
+
+impl IndexWatcher {
+    pub fn start(index: Arc<WordIndex>, interval: Duration) -> Self {
+        // Watch the source of 'index', which the owner keeps using until it receives an updated index. The index should be
+        // quiet (see 'WordIndex::set_quiet'), as a rebuild in the background reports its progress otherwise.
+        let (sender, events) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let mut current = index;
+            let mut reported_error: Option<String> = None;
+            // wait for the interval, or stop when the watcher is dropped
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let event = match current.staleness() {
+                    Ok(staleness @ (Staleness::Appended | Staleness::Changed)) => {
+                        let old_records = current.record_count;
+                        match refresh(&mut current, staleness) {
+                            Ok(staleness) => {
+                                // build the trie here, so that the first query after the swap does not have to
+                                current.completion_trie();
+                                let new_records = if staleness == Staleness::Appended && current.can_append() { current.record_count - old_records } else { 0 };
+                                WatchEvent::Reindexed{ index: Arc::clone(&current), staleness, new_records }
+                            },
+                            Err(err) => WatchEvent::Error(format!("could not re-index the source: {err}"))
+                        }
+                    },
+                    Ok(_) => {
+                        reported_error = None;
+                        continue
+                    },
+                    Err(err) => WatchEvent::Error(format!("could not check the source: {err}"))
+                };
+                match &event {
+                    WatchEvent::Error(msg) if reported_error.as_ref() == Some(msg) => continue,
+                    WatchEvent::Error(msg) => reported_error = Some(msg.clone()),
+                    WatchEvent::Reindexed{ .. } => reported_error = None
+                }
+                if sender.send(event).is_err() {
+                    break;  // the owner is gone
+                }
+            }
+        });
+        IndexWatcher{ events, _stop: stop }
+    }
+
+    pub fn try_next(&self) -> Option<WatchEvent> {
+        // the next event without waiting, None when nothing happened since the last call
+        self.events.try_recv().ok()
+    }
+
+    pub fn next_timeout(&self, timeout: Duration) -> Option<WatchEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use super::{IndexWatcher, WatchEvent};
+    use crate::header::Staleness;
+    use crate::index::{IndexOptions, WordIndex};
+    use crate::testutil::TempPath;
+
+    #[test]
+    fn test_watch_appended_lines() {
+        let path = TempPath::new("watch.txt");
+        std::fs::write(&path, "to be or not to be\n").unwrap();
+        let mut word_index = Arc::new(WordIndex::build_index_from_file(&path, IndexOptions{quiet: true, ..Default::default()}).unwrap());
+        let watcher = IndexWatcher::start(Arc::clone(&word_index), Duration::from_millis(20));
+        assert!(watcher.try_next().is_none());
+
+        std::fs::write(&path, "to be or not to be\nthat is the question\nwhether tis nobler\n").unwrap();
+        let event = watcher.next_timeout(Duration::from_secs(10));
+        let Some(WatchEvent::Reindexed{ index, staleness, new_records }) = event else { panic!("no re-index event") };
+        assert_eq!((staleness, new_records), (Staleness::Appended, 2));
+        assert_eq!(index.record_count, 3);
+        assert_eq!(index.find_matches("question").map(|locs| locs.len()), Some(1));
+        // the index of the owner is not changed under its hands
+        assert_eq!(word_index.find_matches("question"), None);
+        word_index = index;
+
+        std::fs::write(&path, "a different text\n").unwrap();
+        let event = watcher.next_timeout(Duration::from_secs(10));
+        drop(watcher);
+        let Some(WatchEvent::Reindexed{ index, staleness, new_records }) = event else { panic!("no re-index event") };
+        assert_eq!((staleness, new_records, index.record_count), (Staleness::Changed, 0, 1));
+        assert_eq!(index.find_matches("question"), None);
+        assert_eq!(word_index.record_count, 3);
+    }
+
+    #[test]
+    fn test_watch_stop_and_errors() {
+        // a source that can not be checked is reported once, and dropping the watcher does not wait for the interval
+        let dir = TempPath::new("watch_dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("source.txt");
+        std::fs::write(&path, "to be or not to be\n").unwrap();
+        let word_index = Arc::new(WordIndex::build_index_from_file(&path, IndexOptions::default()).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::write(&dir, "a file where the directory was").unwrap();
+        let watcher = IndexWatcher::start(Arc::clone(&word_index), Duration::from_millis(10));
+        let first = watcher.next_timeout(Duration::from_secs(10));
+        let second = watcher.next_timeout(Duration::from_millis(200));
+        drop(watcher);
+        assert!(matches!(first, Some(WatchEvent::Error(_))));
+        assert!(second.is_none());
+
+        let start = std::time::Instant::now();
+        drop(IndexWatcher::start(word_index, Duration::from_secs(60)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}